@@ -1,7 +1,7 @@
 use std::usize;
 
 use async_trait::async_trait;
-use hcnet::{CloseCode, Handler, Message, NetConn, NetResult, NetSender};
+use hcnet::{CloseCode, Handler, Message, NetConn, NetResult, NetSender, Settings};
 use tokio::io::{AsyncBufReadExt, BufReader};
 
 struct ClientHandler {
@@ -66,6 +66,17 @@ async fn main() {
             .await
             .unwrap(),
         "kcp" => NetConn::kcp_connect("127.0.0.1:2003").await.unwrap(),
+        "quic" => {
+            // 自签名证书时将证书加入信任列表
+            let settings = Settings {
+                domain: Some("test.wmproxy.net".to_string()),
+                ca_files: vec!["key/example.com.pem".to_string()],
+                ..Settings::default()
+            };
+            NetConn::quic_connect_with_settings("127.0.0.1:2003", settings)
+                .await
+                .unwrap()
+        }
         _ => NetConn::tcp_connect("127.0.0.1:2003").await.unwrap(),
     };
     let _ = conn
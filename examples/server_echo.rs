@@ -57,6 +57,15 @@ async fn main() {
             };
             NetConn::ws_bind("0.0.0.0:2003", settings).await.unwrap()
         }
+        "quic" => {
+            let settings = Settings {
+                domain: Some("test.wmproxy.net".to_string()),
+                cert: Some("key/example.com.pem".to_string()),
+                key: Some("key/example.com.key".to_string()),
+                ..Settings::default()
+            };
+            NetConn::quic_bind("0.0.0.0:2003", settings).await.unwrap()
+        }
         "kcp" => NetConn::kcp_bind("0.0.0.0:2003", Settings::default())
             .await
             .unwrap(),
@@ -1,13 +1,15 @@
 use std::net::SocketAddr;
 
 use crate::{
-    NetError, NetResult,
+    NetError, NetResult, ProxyHeader,
     {stream::MaybeAcceptStream, MaybeTlsStream},
 };
 
 pub struct TcpAcceptServer {
     stream: Option<MaybeAcceptStream>,
     pub addr: SocketAddr,
+    /// 是否在tls握手前解析PROXY protocol头
+    proxy_protocol: bool,
 }
 
 impl TcpAcceptServer {
@@ -15,16 +17,35 @@ impl TcpAcceptServer {
         TcpAcceptServer {
             stream: Some(stream),
             addr,
+            proxy_protocol: false,
         }
     }
 
-    pub(crate) async fn accept(&mut self) -> NetResult<(MaybeTlsStream, SocketAddr)> {
+    /// 开启后接受时先解析PROXY protocol头, 头格式错误时拒绝连接
+    pub fn with_proxy_protocol(mut self, enable: bool) -> TcpAcceptServer {
+        self.proxy_protocol = enable;
+        self
+    }
+
+    /// 完成接受, 解析了PROXY protocol头时返回的地址为真实的客户端地址
+    pub(crate) async fn accept(
+        &mut self,
+    ) -> NetResult<(MaybeTlsStream, SocketAddr, Option<ProxyHeader>)> {
         if self.stream.is_none() {
             return Err(NetError::Extension("can't accept twice"));
         } else {
-            let stream = self.stream.take().unwrap();
+            let mut stream = self.stream.take().unwrap();
+            let header = if self.proxy_protocol {
+                let header = stream.read_proxy_header().await?;
+                if let Some(source) = header.source {
+                    self.addr = source;
+                }
+                Some(header)
+            } else {
+                None
+            };
             let stream = stream.accept().await?;
-            Ok((stream, self.addr))
+            Ok((stream, self.addr, header))
         }
     }
 }
@@ -1,7 +1,12 @@
+#[cfg(unix)]
+use std::path::Path;
+
 use tokio::net::ToSocketAddrs;
 use webparse::Url;
 
-use crate::{NetConn, NetError, NetResult, Settings};
+use crate::{
+    CodecType, CompressType, DeflateSettings, NetConn, NetError, NetHub, NetResult, ProxyConfig, QueueType, ReconnectSettings, Settings, SniCert, TlsMode, ToTarget, TrustRoots,
+};
 
 pub struct Builder {
     settings: Settings,
@@ -24,6 +29,11 @@ impl Builder {
         self
     }
 
+    pub fn queue_type(mut self, queue_type: QueueType) -> Self {
+        self.settings.queue_type = queue_type;
+        self
+    }
+
     pub fn in_buffer_max(mut self, in_buffer_max: usize) -> Self {
         self.settings.in_buffer_max = in_buffer_max;
         self
@@ -39,6 +49,11 @@ impl Builder {
         self
     }
 
+    pub fn fragment_size(mut self, fragment_size: usize) -> Self {
+        self.settings.fragment_size = fragment_size;
+        self
+    }
+
     pub fn closing_time(mut self, closing_time: usize) -> Self {
         self.settings.closing_time = closing_time;
         self
@@ -64,6 +79,23 @@ impl Builder {
         self
     }
 
+    pub fn codec(mut self, codec: CodecType) -> Self {
+        self.settings.codec = codec;
+        self
+    }
+
+    pub fn compress(mut self, compress: CompressType, threshold: usize) -> Self {
+        self.settings.compress = compress;
+        self.settings.compress_threshold = threshold;
+        self
+    }
+
+    pub fn heartbeat(mut self, interval: usize, miss: usize) -> Self {
+        self.settings.heartbeat_interval = interval;
+        self.settings.heartbeat_miss = miss;
+        self
+    }
+
     pub fn domain(mut self, domain: String) -> Self {
         self.settings.domain = Some(domain);
         self
@@ -75,11 +107,158 @@ impl Builder {
         self
     }
 
+    /// 监听定时检查证书文件, 变化后新的连接将使用新证书
+    pub fn cert_watch(mut self, interval: usize) -> Self {
+        self.settings.cert_watch_interval = interval;
+        self
+    }
+
+    /// 服务端按SNI选择的证书, host支持`*.example.com`形式的通配符
+    pub fn sni_cert(mut self, host: String, cert: String, key: String) -> Self {
+        self.settings.sni_certs.push(SniCert {
+            host,
+            cert,
+            key,
+            cert_data: None,
+            key_data: None,
+        });
+        self
+    }
+
+    /// 服务端按SNI选择的内存中PEM或DER格式的证书及私钥
+    pub fn sni_cert_data(mut self, host: String, cert: Vec<u8>, key: Vec<u8>) -> Self {
+        self.settings.sni_certs.push(SniCert {
+            host,
+            cert: String::new(),
+            key: String::new(),
+            cert_data: Some(cert),
+            key_data: Some(key),
+        });
+        self
+    }
+
+    /// 使用内存中PEM或DER格式的证书及私钥, 如从密钥管理服务中获取
+    pub fn tls_data(mut self, cert: Vec<u8>, key: Vec<u8>) -> Self {
+        self.settings.cert_data = Some(cert);
+        self.settings.key_data = Some(key);
+        self
+    }
+
+    /// 加密的PKCS#8私钥的密码
+    pub fn key_password(mut self, password: String) -> Self {
+        self.settings.key_password = Some(password);
+        self
+    }
+
+    /// 监听在tls握手前解析PROXY protocol v1/v2头
+    pub fn proxy_protocol(mut self) -> Self {
+        self.settings.proxy_protocol = true;
+        self
+    }
+
+    /// tcp, ws及kcp连接的tls启用方式, 默认由是否配置证书或domain决定
+    pub fn tls_mode(mut self, mode: TlsMode) -> Self {
+        self.settings.tls_mode = mode;
+        self
+    }
+
+    /// 增加一个ALPN协议, 按添加的顺序作为优先级
+    pub fn alpn(mut self, protocol: String) -> Self {
+        self.settings.alpn.push(protocol);
+        self
+    }
+
+    /// 服务端校验客户端证书, optional为true时允许客户端不出示证书
+    pub fn client_ca(mut self, ca: String, optional: bool) -> Self {
+        self.settings.client_ca = Some(ca);
+        self.settings.client_auth_optional = optional;
+        self
+    }
+
+    /// 客户端出示的证书, 用于双向认证
+    pub fn client_cert(mut self, cert: String, key: String) -> Self {
+        self.settings.client_cert = Some(cert);
+        self.settings.client_key = Some(key);
+        self
+    }
+
+    /// 客户端出示的内存中PEM或DER格式的证书及私钥, 优先于client_cert
+    pub fn client_cert_data(mut self, cert: Vec<u8>, key: Vec<u8>) -> Self {
+        self.settings.client_cert_data = Some(cert);
+        self.settings.client_key_data = Some(key);
+        self
+    }
+
+    /// 客户端信任的根证书来源
+    pub fn trust_roots(mut self, trust_roots: TrustRoots) -> Self {
+        self.settings.trust_roots = trust_roots;
+        self
+    }
+
+    /// 客户端额外信任的CA证书文件
+    pub fn ca_file(mut self, ca: String) -> Self {
+        self.settings.ca_files.push(ca);
+        self
+    }
+
+    /// 客户端校验的服务端证书指纹, 格式为`spki:<hash>`或`cert:<hash>`
+    pub fn pin(mut self, pin: String) -> Self {
+        self.settings.pins.push(pin);
+        self
+    }
+
+    /// 客户端不校验服务端的证书链, 仅可用于本地测试
+    pub fn insecure_skip_verify(mut self) -> Self {
+        self.settings.insecure_skip_verify = true;
+        self
+    }
+
+    pub fn deflate(mut self, deflate: DeflateSettings) -> Self {
+        self.settings.deflate = Some(deflate);
+        self
+    }
+
+    /// 增加一个websocket子协议, 按添加的顺序作为优先级
+    pub fn ws_protocol(mut self, protocol: String) -> Self {
+        self.settings.ws_protocols.push(protocol);
+        self
+    }
+
+    /// websocket客户端握手时附加的请求头, 同名时覆盖默认的头
+    pub fn ws_header(mut self, name: String, value: String) -> Self {
+        self.settings.ws_headers.push((name, value));
+        self
+    }
+
+    /// 客户端经由socks5或http代理连接
+    pub fn proxy(mut self, proxy: ProxyConfig) -> Self {
+        self.settings.proxy = Some(proxy);
+        self
+    }
+
+    pub fn reconnect(mut self, reconnect: ReconnectSettings) -> Self {
+        self.settings.reconnect = Some(reconnect);
+        self
+    }
+
+    pub fn hub(mut self, hub: NetHub) -> Self {
+        self.settings.hub = Some(hub);
+        self
+    }
+
     pub fn settings(self) -> Settings {
         self.settings
     }
 
-    pub async fn tcp_connect<A: ToSocketAddrs>(self, addr: A) -> NetResult<NetConn> {
+    pub async fn connect_url(self, url: &str) -> NetResult<NetConn> {
+        NetConn::connect_url_with_settings(url, self.settings).await
+    }
+
+    pub async fn bind_url(self, url: &str) -> NetResult<NetConn> {
+        NetConn::bind_url(url, self.settings).await
+    }
+
+    pub async fn tcp_connect<A: ToTarget>(self, addr: A) -> NetResult<NetConn> {
         NetConn::tcp_connect_with_settings(addr, self.settings).await
     }
 
@@ -87,6 +266,10 @@ impl Builder {
         NetConn::tcp_bind(addr, self.settings).await
     }
 
+    pub async fn auto_bind<A: ToSocketAddrs>(self, addr: A) -> NetResult<NetConn> {
+        NetConn::auto_bind(addr, self.settings).await
+    }
+
     pub async fn kcp_connect<A: ToSocketAddrs>(self, addr: A) -> NetResult<NetConn> {
         NetConn::kcp_connect_with_settings(addr, self.settings).await
     }
@@ -95,6 +278,14 @@ impl Builder {
         NetConn::kcp_bind(addr, self.settings).await
     }
 
+    pub async fn quic_connect<A: ToSocketAddrs>(self, addr: A) -> NetResult<NetConn> {
+        NetConn::quic_connect_with_settings(addr, self.settings).await
+    }
+
+    pub async fn quic_bind<A: ToSocketAddrs>(self, addr: A) -> NetResult<NetConn> {
+        NetConn::quic_bind(addr, self.settings).await
+    }
+
     pub async fn ws_connect<U>(self, u: U) -> NetResult<NetConn>
     where
         Url: TryFrom<U>,
@@ -106,4 +297,14 @@ impl Builder {
     pub async fn ws_bind<A: ToSocketAddrs>(self, addr: A) -> NetResult<NetConn> {
         NetConn::ws_bind(addr, self.settings).await
     }
+
+    #[cfg(unix)]
+    pub async fn unix_connect<P: AsRef<Path>>(self, path: P) -> NetResult<NetConn> {
+        NetConn::unix_connect_with_settings(path, self.settings).await
+    }
+
+    #[cfg(unix)]
+    pub async fn unix_bind<P: AsRef<Path>>(self, path: P) -> NetResult<NetConn> {
+        NetConn::unix_bind(path, self.settings).await
+    }
 }
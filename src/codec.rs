@@ -0,0 +1,660 @@
+use algorithm::buf::{BinaryMut, Bt, BtMut};
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+use crate::{
+    build_message, decode_frame, encode::encode_fragments, encode_fragment_message, CompressType,
+    Message, NetError, NetResult, OpCode, Settings, FRAGMENT_MORE, FRAME_COMPRESSED,
+};
+
+/// tcp/kcp等流式协议的分包方式, 负责从读缓存中解析消息及将消息写入写缓存
+pub trait Codec: Send + Sync {
+    /// 从读缓存中解析出一个完整的消息, 数据不足时返回None且不消耗数据
+    fn decode(&mut self, data: &mut BinaryMut, settings: &Settings) -> NetResult<Option<Message>>;
+
+    /// 将消息编码写入写缓存, 不支持的消息类型可直接忽略
+    fn encode(&mut self, data: &mut BinaryMut, msg: Message, settings: &Settings) -> NetResult<()>;
+
+    /// 是否支持close/ping/pong等控制消息, 不支持时不启用心跳
+    fn has_control(&self) -> bool {
+        false
+    }
+
+    /// 内置的分包方式, 用于广播时缓存编码结果, 自定义的分包返回None
+    fn codec_type(&self) -> Option<CodecType> {
+        None
+    }
+}
+
+/// 内置的分包方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CodecType {
+    /// hcnet的默认格式, 3字节长度 + 1字节操作码
+    #[default]
+    Hcnet,
+    /// 2字节大端长度前缀
+    U16Be,
+    /// 2字节小端长度前缀
+    U16Le,
+    /// 4字节大端长度前缀
+    U32Be,
+    /// 4字节小端长度前缀
+    U32Le,
+    /// varint(LEB128)长度前缀
+    Varint,
+    /// 以换行符分隔的文本
+    Line,
+    /// 不分包, 收到的数据原样返回
+    Raw,
+}
+
+impl CodecType {
+    pub(crate) const COUNT: usize = 8;
+
+    pub fn build(&self) -> Box<dyn Codec> {
+        match self {
+            CodecType::Hcnet => Box::new(HcnetCodec::new()),
+            CodecType::U16Be => Box::new(LengthCodec::new(*self, 2, true)),
+            CodecType::U16Le => Box::new(LengthCodec::new(*self, 2, false)),
+            CodecType::U32Be => Box::new(LengthCodec::new(*self, 4, true)),
+            CodecType::U32Le => Box::new(LengthCodec::new(*self, 4, false)),
+            CodecType::Varint => Box::new(VarintCodec),
+            CodecType::Line => Box::new(LineCodec),
+            CodecType::Raw => Box::new(RawCodec),
+        }
+    }
+}
+
+impl FromStr for CodecType {
+    type Err = NetError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "hcnet" => Ok(CodecType::Hcnet),
+            "u16_be" => Ok(CodecType::U16Be),
+            "u16_le" => Ok(CodecType::U16Le),
+            "u32_be" => Ok(CodecType::U32Be),
+            "u32_le" => Ok(CodecType::U32Le),
+            "varint" => Ok(CodecType::Varint),
+            "line" => Ok(CodecType::Line),
+            "raw" => Ok(CodecType::Raw),
+            _ => Err(NetError::BadUrl("invalid settings value")),
+        }
+    }
+}
+
+/// 编码消息, 预编码的消息在内置分包下直接复用缓存的结果
+pub(crate) fn encode_with_codec(
+    codec: &mut dyn Codec,
+    data: &mut BinaryMut,
+    msg: Message,
+    settings: &Settings,
+) -> NetResult<()> {
+    match msg {
+        Message::Prepared(p) => match codec.codec_type() {
+            Some(t) => match p.encoded(t, settings)? {
+                Some(encoded) => {
+                    data.put_slice(encoded);
+                    Ok(())
+                }
+                None => codec.encode(data, p.message().clone(), settings),
+            },
+            None => codec.encode(data, p.message().clone(), settings),
+        },
+        msg => codec.encode(data, msg, settings),
+    }
+}
+
+/// 数据消息的内容, 控制消息返回None
+fn payload(msg: Message) -> Option<Vec<u8>> {
+    match msg {
+        Message::Text(text) => Some(text.into_bytes()),
+        Message::Binary(bytes) => Some(bytes),
+        Message::Prepared(p) => payload(p.message().clone()),
+        _ => None,
+    }
+}
+
+/// hcnet的默认格式, 超出fragment_size的消息分片发送, 接收时重新组装
+/// 配置了压缩时超出compress_threshold的消息将压缩发送
+#[derive(Default)]
+pub struct HcnetCodec {
+    /// 正在组装的分片消息, 及是否为压缩的数据
+    fragment: Option<(OpCode, bool, Vec<u8>)>,
+}
+
+impl HcnetCodec {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl HcnetCodec {
+    /// 组装完成的数据消息, 带有压缩标记的先解压
+    fn finish(
+        code: OpCode,
+        compressed: bool,
+        val: Vec<u8>,
+        settings: &Settings,
+    ) -> NetResult<Message> {
+        if compressed {
+            build_message(code, decompress(&val, settings)?)
+        } else {
+            build_message(code, val)
+        }
+    }
+}
+
+/// 解压数据, 先校验前置的原始长度以防止压缩炸弹
+fn decompress(val: &[u8], settings: &Settings) -> NetResult<Vec<u8>> {
+    if val.len() < 4 {
+        return Err(NetError::TooShort);
+    }
+    let size = u32::from_le_bytes([val[0], val[1], val[2], val[3]]) as usize;
+    if size > settings.onemsg_max_size {
+        return Err(NetError::OverMsgSize);
+    }
+    lz4_flex::decompress_size_prepended(val)
+        .map_err(|_| NetError::Compress("lz4 decompress failed"))
+}
+
+impl Codec for HcnetCodec {
+    fn decode(&mut self, data: &mut BinaryMut, settings: &Settings) -> NetResult<Option<Message>> {
+        loop {
+            let (op, val) = unwrap_or!(decode_frame(data, settings)?, return Ok(None));
+            let is_more = op & FRAGMENT_MORE != 0;
+            let compressed = op & FRAME_COMPRESSED != 0;
+            let code = OpCode::from(op & !(FRAGMENT_MORE | FRAME_COMPRESSED));
+            // 控制消息可穿插在分片之间, 但自身不可分片或压缩
+            if code.is_control() {
+                if is_more || compressed {
+                    return Err(NetError::BadCode);
+                }
+                return build_message(code, val).map(Some);
+            }
+            match (&mut self.fragment, code) {
+                (None, OpCode::Continuation) => return Err(NetError::BadCode),
+                (None, code) => {
+                    if !is_more {
+                        return Self::finish(code, compressed, val, settings).map(Some);
+                    }
+                    self.fragment = Some((code, compressed, val));
+                }
+                (Some((_, _, buf)), OpCode::Continuation) => {
+                    // 压缩标记仅出现在首个分片
+                    if compressed {
+                        return Err(NetError::BadCode);
+                    }
+                    if buf.len() + val.len() > settings.onemsg_max_size {
+                        return Err(NetError::OverMsgSize);
+                    }
+                    buf.extend_from_slice(&val);
+                }
+                (Some(_), _) => return Err(NetError::BadCode),
+            }
+            if !is_more {
+                if let Some((code, compressed, buf)) = self.fragment.take() {
+                    return Self::finish(code, compressed, buf, settings).map(Some);
+                }
+            }
+        }
+    }
+
+    fn encode(&mut self, data: &mut BinaryMut, msg: Message, settings: &Settings) -> NetResult<()> {
+        if settings.compress != CompressType::None {
+            let (op, val) = match &msg {
+                Message::Text(text) => (OpCode::Text, text.as_bytes()),
+                Message::Binary(bytes) => (OpCode::Binary, &bytes[..]),
+                _ => (OpCode::Bad, &[][..]),
+            };
+            if !op.is_control() && val.len() >= settings.compress_threshold {
+                let compressed = lz4_flex::compress_prepend_size(val);
+                // 压缩后没有变小则按原数据发送
+                if compressed.len() < val.len() {
+                    let op: u8 = op.into();
+                    encode_fragments(
+                        data,
+                        op | FRAME_COMPRESSED,
+                        &compressed,
+                        settings.fragment_size,
+                    );
+                    return Ok(());
+                }
+            }
+        }
+        encode_fragment_message(data, msg, false, settings.fragment_size)
+    }
+
+    fn has_control(&self) -> bool {
+        true
+    }
+
+    fn codec_type(&self) -> Option<CodecType> {
+        Some(CodecType::Hcnet)
+    }
+}
+
+/// 不分包, 收到的数据原样以Binary返回
+pub struct RawCodec;
+
+impl Codec for RawCodec {
+    fn decode(&mut self, data: &mut BinaryMut, _: &Settings) -> NetResult<Option<Message>> {
+        if data.is_empty() {
+            return Ok(None);
+        }
+        let val = data.chunk().to_vec();
+        data.advance_all();
+        Ok(Some(Message::Binary(val)))
+    }
+
+    fn encode(&mut self, data: &mut BinaryMut, msg: Message, _: &Settings) -> NetResult<()> {
+        if let Some(val) = payload(msg) {
+            data.put_slice(&val);
+        }
+        Ok(())
+    }
+
+    fn codec_type(&self) -> Option<CodecType> {
+        Some(CodecType::Raw)
+    }
+}
+
+/// 固定字节数的长度前缀, 长度不包含前缀本身, 收到的数据以Binary返回
+pub struct LengthCodec {
+    codec_type: CodecType,
+    size: usize,
+    is_big: bool,
+}
+
+impl LengthCodec {
+    pub fn new(codec_type: CodecType, size: usize, is_big: bool) -> Self {
+        Self {
+            codec_type,
+            size,
+            is_big,
+        }
+    }
+
+    fn max_len(&self) -> usize {
+        if self.size >= std::mem::size_of::<usize>() {
+            usize::MAX
+        } else {
+            (1usize << (self.size * 8)) - 1
+        }
+    }
+}
+
+impl Codec for LengthCodec {
+    fn decode(&mut self, data: &mut BinaryMut, settings: &Settings) -> NetResult<Option<Message>> {
+        if data.len() < self.size {
+            return Ok(None);
+        }
+        let head = &data.chunk()[..self.size];
+        let mut length = 0usize;
+        for i in 0..self.size {
+            let b = if self.is_big {
+                head[i]
+            } else {
+                head[self.size - 1 - i]
+            };
+            length = (length << 8) | b as usize;
+        }
+        if length > settings.onemsg_max_size {
+            return Err(NetError::OverMsgSize);
+        }
+        if data.len() < self.size + length {
+            return Ok(None);
+        }
+        data.advance(self.size);
+        let mut val = vec![0; length];
+        data.copy_to_slice(&mut val);
+        Ok(Some(Message::Binary(val)))
+    }
+
+    fn encode(&mut self, data: &mut BinaryMut, msg: Message, _: &Settings) -> NetResult<()> {
+        let val = unwrap_or!(payload(msg), return Ok(()));
+        if val.len() > self.max_len() {
+            return Err(NetError::OverMsgSize);
+        }
+        for i in 0..self.size {
+            let shift = if self.is_big {
+                (self.size - 1 - i) * 8
+            } else {
+                i * 8
+            };
+            data.put_u8((val.len() >> shift) as u8);
+        }
+        data.put_slice(&val);
+        Ok(())
+    }
+
+    fn codec_type(&self) -> Option<CodecType> {
+        Some(self.codec_type)
+    }
+}
+
+/// varint(LEB128)长度前缀, 收到的数据以Binary返回
+pub struct VarintCodec;
+
+impl Codec for VarintCodec {
+    fn decode(&mut self, data: &mut BinaryMut, settings: &Settings) -> NetResult<Option<Message>> {
+        let chunk = data.chunk();
+        let mut length = 0usize;
+        let mut head = 0;
+        loop {
+            if head >= chunk.len() {
+                return Ok(None);
+            }
+            if head >= 10 {
+                return Err(NetError::OverMsgSize);
+            }
+            let b = chunk[head];
+            length |= ((b & 0x7F) as usize).wrapping_shl(head as u32 * 7);
+            head += 1;
+            if b & 0x80 == 0 {
+                break;
+            }
+        }
+        if length > settings.onemsg_max_size {
+            return Err(NetError::OverMsgSize);
+        }
+        if data.len() < head + length {
+            return Ok(None);
+        }
+        data.advance(head);
+        let mut val = vec![0; length];
+        data.copy_to_slice(&mut val);
+        Ok(Some(Message::Binary(val)))
+    }
+
+    fn encode(&mut self, data: &mut BinaryMut, msg: Message, _: &Settings) -> NetResult<()> {
+        let val = unwrap_or!(payload(msg), return Ok(()));
+        let mut length = val.len();
+        loop {
+            if length < 0x80 {
+                data.put_u8(length as u8);
+                break;
+            }
+            data.put_u8((length & 0x7F) as u8 | 0x80);
+            length >>= 7;
+        }
+        data.put_slice(&val);
+        Ok(())
+    }
+
+    fn codec_type(&self) -> Option<CodecType> {
+        Some(CodecType::Varint)
+    }
+}
+
+/// 以换行符分隔的文本, 收到的数据去除行尾的\r\n后以Text返回
+/// 消息中间含有换行符时将被接收端拆分, 故编码时返回BadText
+pub struct LineCodec;
+
+impl Codec for LineCodec {
+    fn decode(&mut self, data: &mut BinaryMut, settings: &Settings) -> NetResult<Option<Message>> {
+        let pos = match data.chunk().iter().position(|b| *b == b'\n') {
+            Some(pos) => pos,
+            None => {
+                if data.len() > settings.onemsg_max_size {
+                    return Err(NetError::OverMsgSize);
+                }
+                return Ok(None);
+            }
+        };
+        if pos > settings.onemsg_max_size {
+            return Err(NetError::OverMsgSize);
+        }
+        let mut val = vec![0; pos];
+        data.copy_to_slice(&mut val);
+        data.advance(1);
+        if val.last() == Some(&b'\r') {
+            val.pop();
+        }
+        match String::from_utf8(val) {
+            Ok(v) => Ok(Some(Message::Text(v))),
+            Err(_) => Err(NetError::BadText),
+        }
+    }
+
+    fn encode(&mut self, data: &mut BinaryMut, msg: Message, _: &Settings) -> NetResult<()> {
+        let val = unwrap_or!(payload(msg), return Ok(()));
+        let line = val.strip_suffix(b"\n").unwrap_or(&val);
+        if line.contains(&b'\n') {
+            return Err(NetError::BadText);
+        }
+        data.put_slice(line);
+        data.put_u8(b'\n');
+        Ok(())
+    }
+
+    fn codec_type(&self) -> Option<CodecType> {
+        Some(CodecType::Line)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use algorithm::buf::{BinaryMut, Bt, BtMut};
+
+    use super::CodecType;
+    use crate::{
+        encode::encode_fragments, CloseCode, CompressType, Message, NetError, OpCode, Settings,
+        FRAME_COMPRESSED,
+    };
+
+    const ALL: [CodecType; CodecType::COUNT] = [
+        CodecType::Hcnet,
+        CodecType::U16Be,
+        CodecType::U16Le,
+        CodecType::U32Be,
+        CodecType::U32Le,
+        CodecType::Varint,
+        CodecType::Line,
+        CodecType::Raw,
+    ];
+
+    /// 以字节流的形式解出的数据内容
+    fn payload(msg: &Message) -> Vec<u8> {
+        match msg {
+            Message::Text(t) => t.as_bytes().to_vec(),
+            Message::Binary(b) => b.clone(),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let settings = Settings::default();
+        let msgs = ["hello", "", "world"];
+        for codec_type in ALL {
+            let mut codec = codec_type.build();
+            let mut data = BinaryMut::new();
+            for msg in msgs {
+                codec
+                    .encode(&mut data, Message::Text(msg.to_string()), &settings)
+                    .unwrap();
+            }
+            if codec_type == CodecType::Raw {
+                let msg = codec.decode(&mut data, &settings).unwrap().unwrap();
+                assert_eq!(payload(&msg), b"helloworld");
+                continue;
+            }
+            for msg in msgs {
+                let decoded = codec.decode(&mut data, &settings).unwrap().unwrap();
+                assert_eq!(payload(&decoded), msg.as_bytes(), "{:?}", codec_type);
+            }
+            assert!(codec.decode(&mut data, &settings).unwrap().is_none());
+        }
+    }
+
+    #[test]
+    fn hcnet_keeps_message_type() {
+        let settings = Settings::default();
+        let mut codec = CodecType::Hcnet.build();
+        let mut data = BinaryMut::new();
+        for msg in [
+            Message::Binary(vec![1, 2, 3]),
+            Message::Ping(vec![4]),
+            Message::Close(CloseCode::Away, "bye".to_string()),
+        ] {
+            codec.encode(&mut data, msg, &settings).unwrap();
+        }
+        let mut next = || codec.decode(&mut data, &settings).unwrap().unwrap();
+        assert!(matches!(next(), Message::Binary(v) if v == [1, 2, 3]));
+        assert!(matches!(next(), Message::Ping(v) if v == [4]));
+        assert!(matches!(next(), Message::Close(CloseCode::Away, r) if r == "bye"));
+    }
+
+    #[test]
+    fn partial_input() {
+        let settings = Settings::default();
+        for codec_type in ALL {
+            if codec_type == CodecType::Raw {
+                continue;
+            }
+            let mut codec = codec_type.build();
+            let mut encoded = BinaryMut::new();
+            codec
+                .encode(&mut encoded, Message::Binary(vec![7; 300]), &settings)
+                .unwrap();
+            let encoded = encoded.chunk().to_vec();
+            let mut data = BinaryMut::new();
+            for (i, b) in encoded.iter().enumerate() {
+                data.put_u8(*b);
+                let ret = codec.decode(&mut data, &settings).unwrap();
+                if i + 1 < encoded.len() {
+                    // 数据不足时不消耗数据
+                    assert!(ret.is_none(), "{:?}", codec_type);
+                    assert_eq!(data.remaining(), i + 1);
+                } else {
+                    assert_eq!(payload(&ret.unwrap()), vec![7; 300]);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn reject_oversize_length() {
+        let settings = Settings {
+            onemsg_max_size: 16,
+            ..Settings::default()
+        };
+        let heads: [(CodecType, &[u8]); 6] = [
+            (CodecType::Hcnet, &[0, 0, 100, 2]),
+            (CodecType::U16Be, &[0, 17]),
+            (CodecType::U16Le, &[17, 0]),
+            (CodecType::U32Be, &[0, 0, 1, 0]),
+            (CodecType::U32Le, &[0, 1, 0, 0]),
+            (CodecType::Varint, &[0x80, 0x01]),
+        ];
+        // 仅有长度前缀即可判定超出大小
+        for (codec_type, head) in heads {
+            let mut data = BinaryMut::new();
+            data.put_slice(head);
+            assert!(
+                matches!(
+                    codec_type.build().decode(&mut data, &settings),
+                    Err(NetError::OverMsgSize)
+                ),
+                "{:?}",
+                codec_type
+            );
+        }
+        let mut data = BinaryMut::new();
+        data.put_slice(&[b'a'; 17]);
+        assert!(matches!(
+            CodecType::Line.build().decode(&mut data, &settings),
+            Err(NetError::OverMsgSize)
+        ));
+        // 超出长度前缀所能表示的大小
+        let mut data = BinaryMut::new();
+        assert!(matches!(
+            CodecType::U16Be.build().encode(
+                &mut data,
+                Message::Binary(vec![0; u16::MAX as usize + 1]),
+                &settings
+            ),
+            Err(NetError::OverMsgSize)
+        ));
+    }
+
+    #[test]
+    fn line_newline() {
+        let settings = Settings::default();
+        let mut codec = CodecType::Line.build();
+        let mut data = BinaryMut::new();
+        codec
+            .encode(&mut data, Message::Text("one\n".to_string()), &settings)
+            .unwrap();
+        assert!(matches!(
+            codec.encode(
+                &mut data,
+                Message::Text("two\nthree".to_string()),
+                &settings
+            ),
+            Err(NetError::BadText)
+        ));
+        assert_eq!(data.chunk(), b"one\n");
+        data.put_slice(b"crlf\r\n");
+        let mut next = || codec.decode(&mut data, &settings).unwrap();
+        assert!(matches!(next(), Some(Message::Text(t)) if t == "one"));
+        assert!(matches!(next(), Some(Message::Text(t)) if t == "crlf"));
+        assert!(next().is_none());
+    }
+
+    fn lz4_settings() -> Settings {
+        Settings {
+            compress: CompressType::Lz4,
+            compress_threshold: 64,
+            ..Settings::default()
+        }
+    }
+
+    #[test]
+    fn lz4_round_trip() {
+        let settings = lz4_settings();
+        let mut codec = CodecType::Hcnet.build();
+        let mut data = BinaryMut::new();
+        let text = "compress me ".repeat(100);
+        codec
+            .encode(&mut data, Message::Text(text.clone()), &settings)
+            .unwrap();
+        assert!(data.remaining() < text.len());
+        assert_eq!(data.chunk()[3] & FRAME_COMPRESSED, FRAME_COMPRESSED);
+        // 接收端无需配置即可解压
+        let msg = codec.decode(&mut data, &Settings::default()).unwrap();
+        assert!(matches!(msg, Some(Message::Text(t)) if t == text));
+    }
+
+    #[test]
+    fn lz4_skip_small() {
+        let settings = lz4_settings();
+        let mut codec = CodecType::Hcnet.build();
+        let mut data = BinaryMut::new();
+        codec
+            .encode(&mut data, Message::Binary(vec![0; 63]), &settings)
+            .unwrap();
+        assert_eq!(data.chunk()[3] & FRAME_COMPRESSED, 0);
+        assert_eq!(data.remaining(), 63 + 4);
+        let msg = codec.decode(&mut data, &settings).unwrap();
+        assert!(matches!(msg, Some(Message::Binary(v)) if v == vec![0; 63]));
+    }
+
+    #[test]
+    fn lz4_reject_oversize() {
+        // 声明的原始长度超出限制, 在解压及分配内存前拒绝
+        let mut payload = u32::MAX.to_le_bytes().to_vec();
+        payload.extend_from_slice(&[0x1f, 0, 1, 0]);
+        let binary: u8 = OpCode::Binary.into();
+        let mut data = BinaryMut::new();
+        encode_fragments(&mut data, binary | FRAME_COMPRESSED, &payload, 1024);
+        assert!(matches!(
+            CodecType::Hcnet
+                .build()
+                .decode(&mut data, &Settings::default()),
+            Err(NetError::OverMsgSize)
+        ));
+    }
+}
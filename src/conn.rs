@@ -1,16 +1,25 @@
 use std::net::SocketAddr;
 use std::time::Duration;
 
-use crate::{CloseCode, NetReceiver};
+use crate::{CloseCode, Codec, NetReceiver, ToTarget};
 
 use super::handler::Handler;
 use super::kcp::KcpConn;
+use super::quic::QuicConn;
 use super::tcp::TcpConn;
+#[cfg(unix)]
+use super::unix::UnixConn;
 use super::ws::WsConn;
-use super::{NetError, NetResult, NetSender, Settings};
+use super::{NetError, NetResult, NetSender, NetUrl, ProxyHeader, Settings, TlsInfo, TlsReloader};
+use quinn::Endpoint;
+#[cfg(unix)]
+use std::path::Path;
 use tokio::net::{lookup_host, TcpListener, TcpStream, ToSocketAddrs};
+#[cfg(unix)]
+use tokio::net::{unix::UCred, UnixStream};
 use tokio::task::JoinHandle;
 use tokio_kcp::{KcpListener, KcpStream};
+use tokio_rustls::rustls::pki_types::CertificateDer;
 use webparse::Url;
 
 #[derive(Debug)]
@@ -29,20 +38,112 @@ pub enum NetConn {
     Ws(WsConn),
     /// kcp的封装
     Kcp(KcpConn),
+    /// quic的封装
+    Quic(QuicConn),
+    /// unix domain socket的封装
+    #[cfg(unix)]
+    Unix(UnixConn),
 }
 
 impl NetConn {
+    /// 创建未连接的对象, 运行时再连接, unix socket的地址为文件路径, 需通过new_unix创建
     pub async fn new<A: ToSocketAddrs>(t: NetType, addr: A) -> NetResult<NetConn> {
+        if let NetType::UnixSocket = t {
+            return Err(NetError::BadUrl(
+                "unix socket requires a path, use new_unix",
+            ));
+        }
         let addrs = lookup_host(addr)
             .await?
             .into_iter()
             .collect::<Vec<SocketAddr>>();
         match t {
             NetType::Tcp => Ok(NetConn::Tcp(TcpConn::new(addrs))),
+            NetType::Quic => Ok(NetConn::Quic(QuicConn::new(addrs))),
             _ => Err(NetError::OnlyTcp),
         }
     }
 
+    /// 创建未连接的unix socket对象, 运行时再连接path
+    #[cfg(unix)]
+    pub fn new_unix<P: AsRef<Path>>(path: P) -> NetConn {
+        NetConn::Unix(UnixConn::new(path.as_ref().to_path_buf()))
+    }
+
+    /// 通过url发起连接, 切换协议仅需修改url, 如
+    /// `tcp://127.0.0.1:2003`, `tls://example.com:2003`, `ws://example.com/ws`,
+    /// `kcp://127.0.0.1:2003?read_timeout=5000`, `unix:///tmp/hcnet.sock`
+    pub async fn connect_url(url: &str) -> NetResult<NetConn> {
+        Self::connect_url_with_settings(url, Settings::default()).await
+    }
+
+    /// 通过url发起连接, url中的query参数将覆盖settings中的同名配置
+    pub async fn connect_url_with_settings(
+        url: &str,
+        mut settings: Settings,
+    ) -> NetResult<NetConn> {
+        let url = NetUrl::parse(url)?;
+        let unknown = url.apply_settings(&mut settings)?;
+        match &*url.scheme {
+            "tcp" | "ws" | "kcp" => settings.require_tls(false, false)?,
+            "tls" | "wss" | "kcps" => {
+                settings.require_tls(false, true)?;
+                if settings.domain.is_none() {
+                    settings.domain = Some(url.host().to_string());
+                }
+            }
+            _ => {}
+        }
+        match &*url.scheme {
+            "tcp" | "tls" => Self::tcp_connect_with_settings(&*url.authority, settings).await,
+            "ws" | "wss" => {
+                let u = url.to_ws_url(&unknown);
+                Self::ws_connect_with_settings(&*u, settings).await
+            }
+            "kcp" | "kcps" => Self::kcp_connect_with_settings(&*url.authority, settings).await,
+            "quic" => {
+                if settings.domain.is_none() {
+                    settings.domain = Some(url.host().to_string());
+                }
+                Self::quic_connect_with_settings(&*url.authority, settings).await
+            }
+            #[cfg(unix)]
+            "unix" => Self::unix_connect_with_settings(&url.path, settings).await,
+            _ => Err(NetError::BadUrl("unsupported scheme")),
+        }
+    }
+
+    /// 通过url进行监听, tls/wss/kcps/quic需在settings或query中配置cert及key
+    pub async fn bind_url(url: &str, settings: Settings) -> NetResult<NetConn> {
+        let url = NetUrl::parse(url)?;
+        let mut settings = settings;
+        url.apply_settings(&mut settings)?;
+        let has_cert = (settings.cert.is_some() || settings.cert_data.is_some())
+            && (settings.key.is_some() || settings.key_data.is_some())
+            || !settings.sni_certs.is_empty();
+        match &*url.scheme {
+            "tcp" | "ws" | "kcp" => settings.require_tls(true, false)?,
+            "tls" | "wss" | "kcps" => settings.require_tls(true, true)?,
+            _ => {}
+        }
+        match &*url.scheme {
+            "tcp" => Self::tcp_bind(&*url.authority, settings).await,
+            "ws" => Self::ws_bind(&*url.authority, settings).await,
+            "kcp" => Self::kcp_bind(&*url.authority, settings).await,
+            "auto" => Self::auto_bind(&*url.authority, settings).await,
+            "tls" | "wss" | "kcps" | "quic" if !has_cert => {
+                Err(NetError::BadUrl("miss cert or key"))
+            }
+            "tls" => Self::tcp_bind(&*url.authority, settings).await,
+            "wss" => Self::ws_bind(&*url.authority, settings).await,
+            "kcps" => Self::kcp_bind(&*url.authority, settings).await,
+            "quic" => Self::quic_bind(&*url.authority, settings).await,
+            #[cfg(unix)]
+            "unix" => Self::unix_bind(&url.path, settings).await,
+            _ => Err(NetError::BadUrl("unsupported scheme")),
+        }
+    }
+
     pub async fn ws_bind_with_listener(
         listener: TcpListener,
         settings: Settings,
@@ -99,7 +200,22 @@ impl NetConn {
         Ok(NetConn::Tcp(TcpConn::bind(addr, settings).await?))
     }
 
-    pub async fn tcp_connect<A: ToSocketAddrs>(addr: A) -> NetResult<NetConn> {
+    pub async fn auto_bind_with_listener(
+        listener: TcpListener,
+        settings: Settings,
+    ) -> NetResult<NetConn> {
+        Ok(NetConn::Tcp(
+            TcpConn::auto_bind_with_listener(listener, settings).await?,
+        ))
+    }
+
+    /// 单端口同时接受tcp, ws及tls的连接, 按首个数据识别协议
+    /// 接受的连接为NetConn::Tcp或NetConn::Ws, 配置了证书时可同时接受加密及不加密的连接
+    pub async fn auto_bind<A: ToSocketAddrs>(addr: A, settings: Settings) -> NetResult<NetConn> {
+        Ok(NetConn::Tcp(TcpConn::auto_bind(addr, settings).await?))
+    }
+
+    pub async fn tcp_connect<A: ToTarget>(addr: A) -> NetResult<NetConn> {
         Ok(NetConn::Tcp(TcpConn::connect(addr).await?))
     }
 
@@ -109,7 +225,7 @@ impl NetConn {
         ))
     }
 
-    pub async fn tcp_connect_with_settings<A: ToSocketAddrs>(
+    pub async fn tcp_connect_with_settings<A: ToTarget>(
         addr: A,
         settings: Settings,
     ) -> NetResult<NetConn> {
@@ -118,7 +234,7 @@ impl NetConn {
         ))
     }
 
-    pub async fn tcp_connect_with_timeout<A: ToSocketAddrs>(
+    pub async fn tcp_connect_with_timeout<A: ToTarget>(
         addr: A,
         timeout: Duration,
     ) -> NetResult<NetConn> {
@@ -166,19 +282,100 @@ impl NetConn {
         ))
     }
 
+    pub async fn quic_bind_with_endpoint(
+        endpoint: Endpoint,
+        settings: Settings,
+    ) -> NetResult<NetConn> {
+        Ok(NetConn::Quic(
+            QuicConn::bind_with_endpoint(endpoint, settings).await?,
+        ))
+    }
+
+    pub async fn quic_bind<A: ToSocketAddrs>(addr: A, settings: Settings) -> NetResult<NetConn> {
+        Ok(NetConn::Quic(QuicConn::bind(addr, settings).await?))
+    }
+
+    pub async fn quic_connect<A: ToSocketAddrs>(addr: A) -> NetResult<NetConn> {
+        Self::quic_connect_with_settings(addr, Settings::default()).await
+    }
+
+    pub async fn quic_connect_with_settings<A: ToSocketAddrs>(
+        addr: A,
+        settings: Settings,
+    ) -> NetResult<NetConn> {
+        Ok(NetConn::Quic(
+            QuicConn::connect_with_settings(addr, settings).await?,
+        ))
+    }
+
+    pub async fn quic_connect_with_timeout<A: ToSocketAddrs>(
+        addr: A,
+        timeout: Duration,
+    ) -> NetResult<NetConn> {
+        Ok(NetConn::Quic(
+            QuicConn::connect_with_timeout(addr, timeout).await?,
+        ))
+    }
+
+    #[cfg(unix)]
+    pub async fn unix_bind<P: AsRef<Path>>(path: P, settings: Settings) -> NetResult<NetConn> {
+        Ok(NetConn::Unix(UnixConn::bind(path, settings).await?))
+    }
+
+    #[cfg(unix)]
+    pub async fn unix_connect<P: AsRef<Path>>(path: P) -> NetResult<NetConn> {
+        Self::unix_connect_with_settings(path, Settings::default()).await
+    }
+
+    #[cfg(unix)]
+    pub async fn unix_connect_with_stream(stream: UnixStream) -> NetResult<NetConn> {
+        Ok(NetConn::Unix(
+            UnixConn::connect_with_stream(stream, Settings::default()).await?,
+        ))
+    }
+
+    #[cfg(unix)]
+    pub async fn unix_connect_with_settings<P: AsRef<Path>>(
+        path: P,
+        settings: Settings,
+    ) -> NetResult<NetConn> {
+        Ok(NetConn::Unix(
+            UnixConn::connect_with_settings(path, settings).await?,
+        ))
+    }
+
     pub fn set_settings(&mut self, settings: Settings) {
         match self {
             NetConn::Tcp(tcp) => tcp.set_settings(settings),
             NetConn::Ws(ws) => ws.set_settings(settings),
             NetConn::Kcp(kcp) => kcp.set_settings(settings),
+            NetConn::Quic(quic) => quic.set_settings(settings),
+            #[cfg(unix)]
+            NetConn::Unix(unix) => unix.set_settings(settings),
         }
     }
 
+    /// 设置自定义的分包方式, websocket自带分帧不支持
+    pub fn set_codec(&mut self, codec: Box<dyn Codec>) -> NetResult<()> {
+        match self {
+            NetConn::Tcp(tcp) => tcp.set_codec(codec),
+            NetConn::Kcp(kcp) => kcp.set_codec(codec),
+            NetConn::Quic(quic) => quic.set_codec(codec),
+            #[cfg(unix)]
+            NetConn::Unix(unix) => unix.set_codec(codec),
+            NetConn::Ws(_) => return Err(NetError::Extension("codec unsupported")),
+        }
+        Ok(())
+    }
+
     pub fn get_settings(&mut self) -> &mut Settings {
         match self {
             NetConn::Tcp(tcp) => tcp.get_settings(),
             NetConn::Ws(ws) => ws.get_settings(),
             NetConn::Kcp(kcp) => kcp.get_settings(),
+            NetConn::Quic(quic) => quic.get_settings(),
+            #[cfg(unix)]
+            NetConn::Unix(unix) => unix.get_settings(),
         }
     }
 
@@ -186,29 +383,107 @@ impl NetConn {
     where
         F: FnOnce(NetSender) -> H + Send + 'static,
         H: Handler + 'static + Sync + Send,
+    {
+        let id = self.get_connection_id();
+        let (sender, mut receiver) = NetSender::from_settings(self.get_settings(), id);
+        let _avoid = sender.clone();
+        let hub = self
+            .get_settings()
+            .hub
+            .clone()
+            .filter(|_| !self.is_listen());
+        if let Some(hub) = &hub {
+            hub.register(sender.clone());
+        }
+        let mut handler = factory(sender);
+        let ret = self
+            .inner_run_with_handler(&mut handler, &mut receiver)
+            .await;
+        if let Some(hub) = &hub {
+            hub.remove(id);
+        }
+        if let Err(e) = ret {
+            handler
+                .on_close(CloseCode::Error, "NetError".to_string())
+                .await;
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    async fn run_once<H>(&mut self, handler: &mut H, receiver: &mut NetReceiver) -> NetResult<()>
+    where
+        H: Handler + 'static + Sync + Send,
     {
         match self {
-            NetConn::Tcp(tcp) => tcp.inner_run_handler(factory).await?,
-            NetConn::Ws(ws) => ws.inner_run_handler(factory).await?,
-            NetConn::Kcp(kcp) => kcp.inner_run_handler(factory).await?,
+            NetConn::Tcp(tcp) => tcp.inner_run_with_handler(handler, receiver).await?,
+            NetConn::Ws(ws) => ws.inner_run_with_handler(handler, receiver).await?,
+            NetConn::Kcp(kcp) => kcp.inner_run_with_handler(handler, receiver).await?,
+            NetConn::Quic(quic) => quic.inner_run_with_handler(handler, receiver).await?,
+            #[cfg(unix)]
+            NetConn::Unix(unix) => unix.inner_run_with_handler(handler, receiver).await?,
         }
         Ok(())
     }
 
+    /// 运行连接, 若配置了重连策略, 客户端断开后将按策略重连,
+    /// 重连期间receiver保持不变, 故NetSender依然有效且未发送的消息不会丢失
     async fn inner_run_with_handler<H>(
         &mut self,
         handler: &mut H,
-        receiver: NetReceiver,
+        receiver: &mut NetReceiver,
     ) -> NetResult<()>
     where
         H: Handler + 'static + Sync + Send,
     {
+        let mut attempt = 0;
+        loop {
+            let ret = self.run_once(handler, receiver).await;
+            let policy = unwrap_or!(self.get_settings().reconnect.clone(), return ret);
+            if !self.can_reconnect() || receiver.is_closed() {
+                return ret;
+            }
+            if let Err(e) = &ret {
+                log::warn!("连接断开, 准备重连, 错误:{:?}", e);
+            }
+            loop {
+                if policy.max_attempts != 0 && attempt >= policy.max_attempts {
+                    return ret;
+                }
+                let delay = policy.delay(attempt);
+                attempt += 1;
+                if !handler.on_reconnecting(attempt, delay).await? {
+                    return ret;
+                }
+                tokio::time::sleep(delay).await;
+                match self.reconnect().await {
+                    Ok(()) => {
+                        attempt = 0;
+                        handler.on_reconnected().await?;
+                        break;
+                    }
+                    Err(e) => log::warn!("第{}次重连失败, 错误:{:?}", attempt, e),
+                }
+            }
+        }
+    }
+
+    fn can_reconnect(&self) -> bool {
         match self {
-            NetConn::Tcp(tcp) => tcp.inner_run_with_handler(handler, receiver).await?,
-            NetConn::Ws(ws) => ws.inner_run_with_handler(handler, receiver).await?,
-            NetConn::Kcp(kcp) => kcp.inner_run_with_handler(handler, receiver).await?,
+            NetConn::Tcp(tcp) => tcp.can_reconnect(),
+            NetConn::Ws(ws) => ws.can_reconnect(),
+            NetConn::Kcp(kcp) => kcp.can_reconnect(),
+            _ => false,
+        }
+    }
+
+    async fn reconnect(&mut self) -> NetResult<()> {
+        match self {
+            NetConn::Tcp(tcp) => tcp.reconnect().await,
+            NetConn::Ws(ws) => ws.reconnect().await,
+            NetConn::Kcp(kcp) => kcp.reconnect().await,
+            _ => Err(NetError::Extension("reconnect unsupported")),
         }
-        Ok(())
     }
 
     pub async fn run_handler<F, H>(mut self, factory: F) -> NetResult<JoinHandle<()>>
@@ -234,7 +509,11 @@ impl NetConn {
     {
         let handler = tokio::spawn(async move {
             let mut handler = handler;
-            if let Err(e) = self.inner_run_with_handler(&mut handler, receiver).await {
+            let mut receiver = receiver;
+            if let Err(e) = self
+                .inner_run_with_handler(&mut handler, &mut receiver)
+                .await
+            {
                 handler
                     .on_close(CloseCode::Error, "NetError".to_string())
                     .await;
@@ -249,6 +528,78 @@ impl NetConn {
             NetConn::Tcp(tcp) => tcp.remote_addr(),
             NetConn::Ws(ws) => ws.remote_addr(),
             NetConn::Kcp(kcp) => kcp.remote_addr(),
+            NetConn::Quic(quic) => quic.remote_addr(),
+            #[cfg(unix)]
+            NetConn::Unix(_) => None,
+        }
+    }
+
+    /// 监听开启proxy_protocol时负载均衡传递的头, 包含v2的TLV
+    /// 服务端接受的连接在运行后才解析, 解析后remote_addr为真实的客户端地址
+    pub fn proxy_header(&self) -> Option<&ProxyHeader> {
+        match self {
+            NetConn::Tcp(tcp) => tcp.proxy_header(),
+            NetConn::Ws(ws) => ws.proxy_header(),
+            _ => None,
+        }
+    }
+
+    /// 对端的证书链, 仅在tls连接且对端出示了证书时存在
+    /// 服务端接受的连接在运行后才完成握手, 可通过Handler::on_peer_certificates获取
+    pub fn peer_certificates(&self) -> Option<Vec<CertificateDer<'static>>> {
+        match self {
+            NetConn::Tcp(tcp) => tcp.peer_certificates(),
+            NetConn::Ws(ws) => ws.peer_certificates(),
+            NetConn::Kcp(kcp) => kcp.peer_certificates(),
+            NetConn::Quic(quic) => quic.peer_certificates(),
+            _ => None,
+        }
+    }
+
+    /// 监听的证书热更新句柄, 仅tcp, ws, kcp, quic的监听存在
+    pub fn tls_reloader(&self) -> Option<TlsReloader> {
+        match self {
+            NetConn::Tcp(tcp) => tcp.tls_reloader(),
+            NetConn::Ws(ws) => ws.tls_reloader(),
+            NetConn::Kcp(kcp) => kcp.tls_reloader(),
+            NetConn::Quic(quic) => quic.tls_reloader(),
+            _ => None,
+        }
+    }
+
+    /// tls握手协商的信息, 仅在tls连接时存在
+    /// 服务端接受的连接在运行后才完成握手, 可通过Handler::on_tls_handshake获取
+    pub fn tls_info(&self) -> Option<TlsInfo> {
+        match self {
+            NetConn::Tcp(tcp) => tcp.tls_info(),
+            NetConn::Ws(ws) => ws.tls_info(),
+            NetConn::Kcp(kcp) => kcp.tls_info(),
+            NetConn::Quic(quic) => quic.tls_info(),
+            _ => None,
+        }
+    }
+
+    /// 通过ALPN协商的应用层协议, 服务端接受的连接需在握手完成后获取
+    pub fn alpn_protocol(&self) -> Option<String> {
+        self.tls_info().and_then(|info| info.alpn_protocol)
+    }
+
+    /// websocket握手协商的子协议, 服务端接受的连接需在握手完成后获取
+    pub fn ws_protocol(&self) -> Option<String> {
+        match self {
+            NetConn::Ws(ws) => ws.protocol(),
+            _ => None,
+        }
+    }
+
+    pub fn is_listen(&self) -> bool {
+        match self {
+            NetConn::Tcp(tcp) => tcp.is_listen(),
+            NetConn::Ws(ws) => ws.is_listen(),
+            NetConn::Kcp(kcp) => kcp.is_listen(),
+            NetConn::Quic(quic) => quic.is_listen(),
+            #[cfg(unix)]
+            NetConn::Unix(unix) => unix.is_listen(),
         }
     }
 
@@ -257,6 +608,18 @@ impl NetConn {
             NetConn::Tcp(tcp) => tcp.get_connection_id(),
             NetConn::Ws(ws) => ws.get_connection_id(),
             NetConn::Kcp(kcp) => kcp.get_connection_id(),
+            NetConn::Quic(quic) => quic.get_connection_id(),
+            #[cfg(unix)]
+            NetConn::Unix(unix) => unix.get_connection_id(),
+        }
+    }
+
+    /// unix socket对端的进程凭证(uid/gid/pid), 其它协议返回None
+    #[cfg(unix)]
+    pub fn peer_cred(&self) -> Option<UCred> {
+        match self {
+            NetConn::Unix(unix) => unix.peer_cred(),
+            _ => None,
         }
     }
 }
@@ -302,3 +665,153 @@ impl From<(KcpConn, Settings)> for NetConn {
         conn
     }
 }
+
+impl From<QuicConn> for NetConn {
+    fn from(value: QuicConn) -> Self {
+        NetConn::Quic(value)
+    }
+}
+
+impl From<(QuicConn, Settings)> for NetConn {
+    fn from(value: (QuicConn, Settings)) -> Self {
+        let mut conn = NetConn::Quic(value.0);
+        conn.set_settings(value.1);
+        conn
+    }
+}
+
+#[cfg(unix)]
+impl From<UnixConn> for NetConn {
+    fn from(value: UnixConn) -> Self {
+        NetConn::Unix(value)
+    }
+}
+
+#[cfg(unix)]
+impl From<(UnixConn, Settings)> for NetConn {
+    fn from(value: (UnixConn, Settings)) -> Self {
+        let mut conn = NetConn::Unix(value.0);
+        conn.set_settings(value.1);
+        conn
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use algorithm::buf::BinaryMut;
+    use async_trait::async_trait;
+    use tokio::{
+        io::AsyncReadExt,
+        net::TcpListener,
+        sync::mpsc::{unbounded_channel, UnboundedSender},
+        time::timeout,
+    };
+
+    use crate::{
+        CodecType, Handler, Message, NetConn, NetResult, NetSender, ReconnectSettings, Settings,
+    };
+
+    enum Event {
+        Reconnecting(usize, Duration),
+        Reconnected,
+    }
+
+    struct ReconnectHandler {
+        sender: NetSender,
+        events: UnboundedSender<Event>,
+    }
+
+    #[async_trait]
+    impl Handler for ReconnectHandler {
+        async fn on_reconnecting(&mut self, attempt: usize, delay: Duration) -> NetResult<bool> {
+            // 断开期间发送的消息应在重连后送达
+            if attempt == 1 {
+                self.sender.send_message(Message::Binary(vec![1, 2, 3]))?;
+                self.sender
+                    .send_message(Message::Text("after".to_string()))?;
+            }
+            let _ = self.events.send(Event::Reconnecting(attempt, delay));
+            Ok(true)
+        }
+
+        async fn on_reconnected(&mut self) -> NetResult<()> {
+            let _ = self.events.send(Event::Reconnected);
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn reconnect_with_backoff() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let settings = Settings {
+            reconnect: Some(ReconnectSettings {
+                min_delay: 50,
+                max_delay: 1000,
+                factor: 2.0,
+                jitter: false,
+                ..Default::default()
+            }),
+            ..Settings::default()
+        };
+        let conn = NetConn::tcp_connect_with_settings(addr, settings.clone())
+            .await
+            .unwrap();
+        let (events, mut rx) = unbounded_channel();
+        conn.run_handler(move |sender| ReconnectHandler { sender, events })
+            .await
+            .unwrap();
+
+        // 断开首个连接并关闭监听, 使前几次重连失败
+        let (first, _) = listener.accept().await.unwrap();
+        drop(first);
+        drop(listener);
+
+        let mut delays = vec![];
+        let listener = loop {
+            match timeout(Duration::from_secs(5), rx.recv()).await.unwrap() {
+                Some(Event::Reconnecting(attempt, delay)) => {
+                    delays.push((attempt, delay));
+                    if attempt == 2 {
+                        break TcpListener::bind(addr).await.unwrap();
+                    }
+                }
+                _ => unreachable!(),
+            }
+        };
+        assert_eq!(
+            delays,
+            vec![
+                (1, Duration::from_millis(50)),
+                (2, Duration::from_millis(100))
+            ]
+        );
+
+        let (mut second, _) = timeout(Duration::from_secs(5), listener.accept())
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(matches!(rx.recv().await, Some(Event::Reconnected)));
+
+        let mut codec = CodecType::default().build();
+        let mut read = BinaryMut::new();
+        let mut msgs = vec![];
+        while msgs.len() < 2 {
+            if let Some(msg) = codec.decode(&mut read, &settings).unwrap() {
+                msgs.push(msg);
+                continue;
+            }
+            let mut buf = [0u8; 1024];
+            let n = timeout(Duration::from_secs(5), second.read(&mut buf))
+                .await
+                .unwrap()
+                .unwrap();
+            assert!(n > 0);
+            read.put_slice(&buf[..n]);
+        }
+        assert!(matches!(&msgs[0], Message::Binary(v) if v == &[1, 2, 3]));
+        assert!(matches!(&msgs[1], Message::Text(t) if t == "after"));
+    }
+}
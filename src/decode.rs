@@ -1,7 +1,9 @@
 use algorithm::buf::{BinaryMut, Bt};
 
-use super::{read_u24, Message, NetError, NetResult, OpCode, Settings};
+use super::{read_u24, Message, NetError, NetResult, OpCode, Settings, FRAGMENT_MORE};
 
+/// 解析单个未分片的消息, 分片或压缩的帧返回BadCode
+/// encode_message对超出MAX_FRAME_PAYLOAD的消息分片, 需使用HcnetCodec解析
 pub fn decode_message(data: &mut BinaryMut, settings: &Settings) -> NetResult<Option<Message>> {
     if settings.is_raw {
         if data.len() == 0 {
@@ -12,6 +14,18 @@ pub fn decode_message(data: &mut BinaryMut, settings: &Settings) -> NetResult<Op
         return Ok(Some(Message::Binary(val)))
     }
     
+    let (op, val) = unwrap_or!(decode_frame(data, settings)?, return Ok(None));
+    if op & FRAGMENT_MORE != 0 {
+        return Err(NetError::BadCode);
+    }
+    build_message(OpCode::from(op), val).map(Some)
+}
+
+/// 解析单个帧, 返回原始的操作码字节(含分片标记)及帧内容
+pub fn decode_frame(
+    data: &mut BinaryMut,
+    settings: &Settings,
+) -> NetResult<Option<(u8, Vec<u8>)>> {
     if data.len() < 4 {
         return Ok(None);
     }
@@ -27,32 +41,31 @@ pub fn decode_message(data: &mut BinaryMut, settings: &Settings) -> NetResult<Op
         data.rewind_mark();
         return Ok(None);
     }
-    let op = OpCode::from(data.get_u8());
+    let op = data.get_u8();
     let mut val = vec![0; length - 4];
     data.copy_to_slice(&mut val);
+    Ok(Some((op, val)))
+}
+
+/// 根据操作码将帧内容转成消息
+pub fn build_message(op: OpCode, val: Vec<u8>) -> NetResult<Message> {
     match op {
-        OpCode::Text => {
-            if let Ok(v) = String::from_utf8(val) {
-                return Ok(Some(Message::Text(v)));
-            } else {
-                return Err(NetError::BadText);
-            }
-        },
-        OpCode::Binary => return Ok(Some(Message::Binary(val))),
+        OpCode::Text => String::from_utf8(val)
+            .map(Message::Text)
+            .map_err(|_| NetError::BadText),
+        OpCode::Binary => Ok(Message::Binary(val)),
         OpCode::Close => {
             if val.len() < 2 {
                 return Err(NetError::TooShort);
             }
             let code = (val[0] as u16).wrapping_shl(8) + val[1] as u16;
-            if let Ok(v) = String::from_utf8(val[2..].to_vec()) {
-                return Ok(Some(Message::Close(code.into(), v)));
-            } else {
-                return Err(NetError::BadText);
-            }
+            String::from_utf8(val[2..].to_vec())
+                .map(|v| Message::Close(code.into(), v))
+                .map_err(|_| NetError::BadText)
         },
-        OpCode::Ping => return Ok(Some(Message::Ping(val))),
-        OpCode::Pong => return Ok(Some(Message::Pong(val))),
+        OpCode::Ping => Ok(Message::Ping(val)),
+        OpCode::Pong => Ok(Message::Pong(val)),
 
-        OpCode::Bad | OpCode::Shutdown => return Err(NetError::BadCode),
+        OpCode::Continuation | OpCode::Bad | OpCode::Shutdown => Err(NetError::BadCode),
     }
-}
\ No newline at end of file
+}
@@ -2,25 +2,63 @@ use algorithm::buf::{BinaryMut, BtMut};
 
 use super::{encode_u24, Message, NetResult, OpCode};
 
+/// 单帧所能表示的最大数据长度, 长度字段为u24且包含4字节的头
+pub const MAX_FRAME_PAYLOAD: usize = 0xFFFFFF - 4;
+/// 操作码的分片标记, 表示后续还有分片
+pub const FRAGMENT_MORE: u8 = 0x80;
+/// 操作码的压缩标记, 表示数据经过压缩, 分片时仅在首帧设置
+pub const FRAME_COMPRESSED: u8 = 0x40;
+
+fn encode_frame(data: &mut BinaryMut, op: u8, bytes: &[u8]) {
+    encode_u24(data, (bytes.len() + 4) as u32);
+    data.put_u8(op);
+    data.put_slice(bytes);
+}
+
+/// 超出分片大小的数据拆分成多个帧, 首帧为原操作码, 后续帧为Continuation
+/// 除最后一帧外均带有分片标记
+pub(crate) fn encode_fragments(data: &mut BinaryMut, op: u8, bytes: &[u8], fragment_size: usize) {
+    let size = fragment_size.clamp(1, MAX_FRAME_PAYLOAD);
+    if bytes.len() <= size {
+        encode_frame(data, op, bytes);
+        return;
+    }
+    let mut first = Some(op);
+    let mut iter = bytes.chunks(size).peekable();
+    while let Some(chunk) = iter.next() {
+        let mut code = first.take().unwrap_or(OpCode::Continuation.into());
+        if iter.peek().is_some() {
+            code |= FRAGMENT_MORE;
+        }
+        encode_frame(data, code, chunk);
+    }
+}
+
+/// 编码消息, 超出MAX_FRAME_PAYLOAD的消息将分片, 分片的消息需使用HcnetCodec解析
 pub fn encode_message(data: &mut BinaryMut, msg: Message, is_raw: bool) -> NetResult<()> {
+    encode_fragment_message(data, msg, is_raw, MAX_FRAME_PAYLOAD)
+}
+
+/// 编码消息, 文本及二进制数据超出fragment_size时将分片发送
+pub fn encode_fragment_message(
+    data: &mut BinaryMut,
+    msg: Message,
+    is_raw: bool,
+    fragment_size: usize,
+) -> NetResult<()> {
     match msg {
         Message::Text(text) => {
             if is_raw {
                 data.put_slice(text.as_bytes());
             } else {
-                let bytes = text.into_bytes();
-                encode_u24(data, (bytes.len() + 4) as u32);
-                data.put_u8(OpCode::Text.into());
-                data.put_slice(&bytes);
+                encode_fragments(data, OpCode::Text.into(), text.as_bytes(), fragment_size);
             }
         }
         Message::Binary(bytes) => {
             if is_raw {
                 data.put_slice(&bytes);
             } else {
-                encode_u24(data, (bytes.len() + 4) as u32);
-                data.put_u8(OpCode::Binary.into());
-                data.put_slice(&bytes);
+                encode_fragments(data, OpCode::Binary.into(), &bytes, fragment_size);
             }
         }
         Message::Close(code, reason) => {
@@ -34,18 +72,17 @@ pub fn encode_message(data: &mut BinaryMut, msg: Message, is_raw: bool) -> NetRe
         }
         Message::Ping(bytes) => {
             if !is_raw {
-                encode_u24(data, (bytes.len() + 4) as u32);
-                data.put_u8(OpCode::Ping.into());
-                data.put_slice(&bytes);
+                encode_frame(data, OpCode::Ping.into(), &bytes);
             }
         }
         Message::Pong(bytes) => {
             if !is_raw {
-                encode_u24(data, (bytes.len() + 4) as u32);
-                data.put_u8(OpCode::Pong.into());
-                data.put_slice(&bytes);
+                encode_frame(data, OpCode::Pong.into(), &bytes);
             }
         }
+        Message::Prepared(p) => {
+            encode_fragment_message(data, p.message().clone(), is_raw, fragment_size)?;
+        }
         _ => {
             // encode_u24(data, 4);
             // data.put_u8(OpCode::Shutdown.into());
@@ -55,3 +92,114 @@ pub fn encode_message(data: &mut BinaryMut, msg: Message, is_raw: bool) -> NetRe
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use algorithm::buf::{BinaryMut, Bt};
+
+    use super::{encode_fragments, MAX_FRAME_PAYLOAD};
+    use crate::{
+        decode_frame, decode_message, encode_message, Codec, HcnetCodec, Message, NetError,
+        OpCode, Settings, FRAGMENT_MORE,
+    };
+
+    fn settings() -> Settings {
+        Settings {
+            onemsg_max_size: MAX_FRAME_PAYLOAD * 2 + 8,
+            ..Settings::default()
+        }
+    }
+
+    /// 解析出所有的帧, 返回各帧的操作码及长度
+    fn frames(data: &mut BinaryMut, settings: &Settings) -> Vec<(u8, usize)> {
+        let mut ret = vec![];
+        while let Some((op, val)) = decode_frame(data, settings).unwrap() {
+            ret.push((op, val.len()));
+        }
+        ret
+    }
+
+    #[test]
+    fn split_at_frame_limit() {
+        let settings = settings();
+        let binary: u8 = OpCode::Binary.into();
+        let continuation: u8 = OpCode::Continuation.into();
+
+        let mut data = BinaryMut::new();
+        encode_message(&mut data, Message::Binary(vec![1; MAX_FRAME_PAYLOAD]), false).unwrap();
+        assert_eq!(data.remaining(), MAX_FRAME_PAYLOAD + 4);
+        assert_eq!(frames(&mut data, &settings), vec![(binary, MAX_FRAME_PAYLOAD)]);
+
+        let mut data = BinaryMut::new();
+        encode_message(
+            &mut data,
+            Message::Binary(vec![1; MAX_FRAME_PAYLOAD + 1]),
+            false,
+        )
+        .unwrap();
+        let mut copy = BinaryMut::new();
+        copy.put_slice(data.chunk());
+        assert_eq!(
+            frames(&mut copy, &settings),
+            vec![(binary | FRAGMENT_MORE, MAX_FRAME_PAYLOAD), (continuation, 1)]
+        );
+        // 单帧的解析不支持分片
+        assert!(matches!(
+            decode_message(&mut data, &settings),
+            Err(NetError::BadCode)
+        ));
+    }
+
+    #[test]
+    fn reassemble_over_limit() {
+        let settings = settings();
+        let mut data = BinaryMut::new();
+        let mut val = vec![1; MAX_FRAME_PAYLOAD + 1];
+        val[MAX_FRAME_PAYLOAD] = 2;
+        encode_message(&mut data, Message::Binary(val.clone()), false).unwrap();
+        let msg = HcnetCodec::new().decode(&mut data, &settings).unwrap();
+        assert!(matches!(msg, Some(Message::Binary(v)) if v == val));
+    }
+
+    #[test]
+    fn several_fragments() {
+        let settings = Settings::default();
+        let text: u8 = OpCode::Text.into();
+        let continuation: u8 = OpCode::Continuation.into();
+        let val = "0123456789".repeat(3) + "abcde";
+
+        let mut data = BinaryMut::new();
+        encode_fragments(&mut data, text, val.as_bytes(), 10);
+        let mut copy = BinaryMut::new();
+        copy.put_slice(data.chunk());
+        assert_eq!(
+            frames(&mut copy, &settings),
+            vec![
+                (text | FRAGMENT_MORE, 10),
+                (continuation | FRAGMENT_MORE, 10),
+                (continuation | FRAGMENT_MORE, 10),
+                (continuation, 5),
+            ]
+        );
+        let mut codec = HcnetCodec::new();
+        let msg = codec.decode(&mut data, &settings).unwrap();
+        assert!(matches!(msg, Some(Message::Text(t)) if t == val));
+        assert!(codec.decode(&mut data, &settings).unwrap().is_none());
+    }
+
+    #[test]
+    fn control_between_fragments() {
+        let settings = Settings::default();
+        let text: u8 = OpCode::Text.into();
+        let mut data = BinaryMut::new();
+        encode_fragments(&mut data, text | FRAGMENT_MORE, b"ab", 10);
+        encode_message(&mut data, Message::Ping(vec![9]), false).unwrap();
+        encode_fragments(&mut data, OpCode::Continuation.into(), b"cd", 10);
+
+        let mut codec = HcnetCodec::new();
+        let msg = codec.decode(&mut data, &settings).unwrap();
+        assert!(matches!(msg, Some(Message::Ping(v)) if v == [9]));
+        let msg = codec.decode(&mut data, &settings).unwrap();
+        assert!(matches!(msg, Some(Message::Text(t)) if t == "abcd"));
+    }
+}
@@ -2,8 +2,9 @@ use std::io;
 
 use webparse::WebError;
 use kcp::Error as KcpError;
+use quinn::{ConnectError, ConnectionError};
 
-use super::{sender::Command, ws::WsError};
+use super::{sender::Command, ws::WsError, TlsError};
 
 #[derive(Debug)]
 pub enum NetError {
@@ -13,6 +14,8 @@ pub enum NetError {
     TooShortLength,
     /// 当前只允许Tcp连接
     OnlyTcp,
+    /// 错误的连接地址
+    BadUrl(&'static str),
     /// 错误的CODE
     BadCode,
     /// 错误的文本
@@ -32,16 +35,30 @@ pub enum NetError {
     SendClosed(Command),
     /// SendFull
     SendFull(Command),
+    /// 连接不存在
+    NoConnection(u64),
+    /// 压缩或解压错误
+    Compress(&'static str),
+    /// PROXY protocol头格式错误
+    BadProxyHeader(&'static str),
+    /// 经由代理连接失败
+    Proxy(&'static str),
     /// 其它类型错误
     Extension(&'static str),
     /// websocket相关错误
     Ws(WsError),
+    /// 证书及私钥相关错误
+    Tls(TlsError),
     /// io错误
     Io(io::Error),
     /// webparse
     Web(WebError),
     /// kcp错误 
-    Kcp(KcpError)
+    Kcp(KcpError),
+    /// quic发起连接错误
+    QuicConnect(ConnectError),
+    /// quic连接错误
+    QuicConnection(ConnectionError),
 }
 
 impl From<io::Error> for NetError {
@@ -62,8 +79,26 @@ impl From<WsError> for NetError {
     }
 }
 
+impl From<TlsError> for NetError {
+    fn from(value: TlsError) -> Self {
+        NetError::Tls(value)
+    }
+}
+
 impl From<KcpError> for NetError {
     fn from(value: KcpError) -> Self {
         NetError::Kcp(value)
     }
 }
+
+impl From<ConnectError> for NetError {
+    fn from(value: ConnectError) -> Self {
+        NetError::QuicConnect(value)
+    }
+}
+
+impl From<ConnectionError> for NetError {
+    fn from(value: ConnectionError) -> Self {
+        NetError::QuicConnection(value)
+    }
+}
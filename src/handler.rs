@@ -1,8 +1,11 @@
+use std::time::Duration;
+
 use crate::ws::WsHandshake;
 
-use super::{CloseCode, Message, NetConn, NetResult};
+use super::{CloseCode, Message, NetConn, NetResult, ProxyHeader, TlsInfo};
 use async_trait::async_trait;
 use log::trace;
+use tokio_rustls::rustls::pki_types::CertificateDer;
 use webparse::{Request, Response};
 
 /// 连接处理回调函数
@@ -15,6 +18,33 @@ pub trait Handler {
         unreachable!("Listener must impl accept")
     }
 
+    /// 此接口在监听开启proxy_protocol且解析了头时触发, 早于on_tls_handshake及on_open
+    /// header.source为负载均衡传递的真实客户端地址
+    async fn on_proxy_header(&mut self, header: ProxyHeader) -> NetResult<()> {
+        let _ = header;
+        Ok(())
+    }
+
+    /// 此接口在tls握手完成时触发, 早于on_peer_certificates及on_open
+    /// 服务端可通过info.server_name获取客户端请求的域名
+    async fn on_tls_handshake(&mut self, info: TlsInfo) -> NetResult<()> {
+        let _ = info;
+        Ok(())
+    }
+
+    /// 此接口在tls握手完成且对端出示了证书时触发, 早于on_open
+    /// 返回错误将关闭连接, 可用于根据证书认证对端的身份
+    async fn on_peer_certificates(&mut self, certs: Vec<CertificateDer<'static>>) -> NetResult<()> {
+        let _ = certs;
+        Ok(())
+    }
+
+    /// 此接口在websocket握手协商了子协议时触发, 早于on_open
+    async fn on_ws_protocol(&mut self, protocol: String) -> NetResult<()> {
+        let _ = protocol;
+        Ok(())
+    }
+
     /// 此接口在可以发送消息时触发
     /// 例如websocket将在握手成功后触发该函数
     async fn on_open(&mut self) -> NetResult<()> {
@@ -43,17 +73,43 @@ pub trait Handler {
         Ok(())
     }
 
+    /// 收到心跳的响应, rtt为往返时长
+    async fn on_heartbeat(&mut self, rtt: Duration) -> NetResult<()> {
+        trace!("on_heartbeat rtt = {rtt:?}");
+        Ok(())
+    }
+
     /// message信息收到
     async fn on_message(&mut self, msg: Message) -> NetResult<()> {
         let _ = msg;
         Ok(())
     }
 
+    /// 客户端连接断开且按重连策略即将重连时触发, attempt从1开始
+    /// 返回false则放弃重连
+    async fn on_reconnecting(&mut self, attempt: usize, delay: Duration) -> NetResult<bool> {
+        trace!("on_reconnecting attempt = {attempt}, delay = {delay:?}");
+        Ok(true)
+    }
+
+    /// 客户端重连成功时触发, 之后将重新触发on_open
+    async fn on_reconnected(&mut self) -> NetResult<()> {
+        trace!("on_reconnected");
+        Ok(())
+    }
+
     async fn on_request(&mut self, req: Request<Vec<u8>>) -> NetResult<Response<Vec<u8>>> {
         WsHandshake::build_request(&req)
     }
 
-    async fn on_response(&mut self, res: Request<Vec<u8>>) -> NetResult<()> {
+    /// 此接口在websocket服务端收到非升级的http请求时触发, 可用于健康检查等接口
+    /// 默认交由on_request处理, 以兼容在on_request中处理普通http请求的实现
+    /// 返回的结果写回后默认保持连接, 请求或结果带有`Connection: close`时关闭连接
+    async fn on_http(&mut self, req: Request<Vec<u8>>) -> NetResult<Response<Vec<u8>>> {
+        self.on_request(req).await
+    }
+
+    async fn on_response(&mut self, res: Response<Vec<u8>>) -> NetResult<()> {
         let _ = res;
         Ok(())
     }
@@ -0,0 +1,147 @@
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+use crate::Settings;
+
+/// 心跳状态, 定时发送ping并统计未响应的次数
+pub(crate) struct Heartbeat {
+    /// 心跳间隔, 为空则表示不启用
+    interval: Option<Duration>,
+    /// 最大允许的连续未响应次数
+    max_miss: usize,
+    /// 当前连续未响应的次数
+    missed: usize,
+    /// 下一次发送心跳的时间
+    next: Instant,
+    /// 最后一次发送的序号
+    seq: u64,
+    /// 等待响应的序号及发送时间
+    wait: Option<(u64, Instant)>,
+}
+
+impl Heartbeat {
+    /// has_control为分包方式是否支持ping/pong
+    pub fn new(settings: &Settings, has_control: bool) -> Self {
+        let interval = if settings.heartbeat_interval == 0 || !has_control {
+            None
+        } else {
+            Some(Duration::from_millis(settings.heartbeat_interval as u64))
+        };
+        Self {
+            interval,
+            max_miss: settings.heartbeat_miss.max(1),
+            missed: 0,
+            next: Instant::now() + interval.unwrap_or_default(),
+            seq: 0,
+            wait: None,
+        }
+    }
+
+    pub fn is_enable(&self) -> bool {
+        self.interval.is_some()
+    }
+
+    pub fn deadline(&self) -> Instant {
+        self.next
+    }
+
+    /// 到达心跳时间, 返回需要发送的ping数据
+    /// 若连续未响应次数达到上限则返回None, 并停止心跳
+    pub fn on_tick(&mut self) -> Option<Vec<u8>> {
+        let interval = self.interval?;
+        if self.wait.is_some() {
+            self.missed += 1;
+        }
+        if self.missed >= self.max_miss {
+            self.interval = None;
+            return None;
+        }
+        let now = Instant::now();
+        self.seq = self.seq.wrapping_add(1);
+        self.wait = Some((self.seq, now));
+        self.next = now + interval;
+        Some(self.seq.to_be_bytes().to_vec())
+    }
+
+    /// 收到pong消息, 若为心跳的响应则返回往返时长
+    pub fn on_pong(&mut self, data: &[u8]) -> Option<Duration> {
+        if data.len() != 8 {
+            return None;
+        }
+        let mut seq = [0u8; 8];
+        seq.copy_from_slice(data);
+        let seq = u64::from_be_bytes(seq);
+        match self.wait {
+            Some((wait, time)) if wait == seq => {
+                self.wait = None;
+                self.missed = 0;
+                Some(time.elapsed())
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::Heartbeat;
+    use crate::Settings;
+
+    fn heartbeat(miss: usize) -> Heartbeat {
+        let settings = Settings {
+            heartbeat_interval: 1000,
+            heartbeat_miss: miss,
+            ..Settings::default()
+        };
+        Heartbeat::new(&settings, true)
+    }
+
+    #[test]
+    fn disabled() {
+        let settings = Settings {
+            heartbeat_interval: 1000,
+            ..Settings::default()
+        };
+        assert!(!Heartbeat::new(&settings, false).is_enable());
+        assert!(!Heartbeat::new(&Settings::default(), true).is_enable());
+    }
+
+    #[test]
+    fn timeout_after_missed() {
+        let mut heartbeat = heartbeat(3);
+        assert!(heartbeat.on_tick().is_some());
+        assert!(heartbeat.on_tick().is_some());
+        assert!(heartbeat.on_tick().is_some());
+        assert!(heartbeat.on_tick().is_none());
+        assert!(!heartbeat.is_enable());
+        assert!(heartbeat.on_tick().is_none());
+    }
+
+    #[tokio::test]
+    async fn pong_rtt() {
+        let mut heartbeat = heartbeat(2);
+        assert!(heartbeat.on_tick().is_some());
+        let data = heartbeat.on_tick().unwrap();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        let rtt = heartbeat.on_pong(&data).unwrap();
+        assert!(rtt >= Duration::from_millis(20));
+        // 响应后未响应次数清零
+        assert!(heartbeat.on_tick().is_some());
+        assert!(heartbeat.on_tick().is_some());
+        assert!(heartbeat.on_tick().is_none());
+    }
+
+    #[test]
+    fn ignore_stale_pong() {
+        let mut heartbeat = heartbeat(3);
+        let first = heartbeat.on_tick().unwrap();
+        let second = heartbeat.on_tick().unwrap();
+        assert!(heartbeat.on_pong(&first).is_none());
+        assert!(heartbeat.on_pong(b"pong").is_none());
+        assert!(heartbeat.on_pong(&second).is_some());
+        assert!(heartbeat.on_pong(&second).is_none());
+    }
+}
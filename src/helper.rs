@@ -1,60 +1,201 @@
 use std::{
-    fs::File,
-    io::{self, BufReader, Read},
+    fs,
+    io::{self, BufReader},
 };
 
+use base64::{engine::general_purpose::STANDARD, Engine};
+use pkcs8::EncryptedPrivateKeyInfo;
+use rustls_pemfile::Item;
 use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer};
 
+use crate::{NetResult, TlsError};
+
 pub struct Helper;
 
 impl Helper {
-    pub fn load_certs(path: &String) -> io::Result<Vec<CertificateDer<'static>>> {
-        match File::open(&path) {
-            Ok(mut file) => {
-                let mut content = String::new();
-                file.read_to_string(&mut content)?;
-                let mut reader = BufReader::new(content.as_bytes());
-                let certs = rustls_pemfile::certs(&mut reader);
-                Ok(certs.into_iter().collect::<Result<Vec<_>, _>>()?)
-            }
-            Err(e) => {
-                log::warn!("加载公钥{}出错，错误内容:{:?}", path, e);
-                return Err(e);
-            }
+    fn read_file(path: &String, name: &str) -> io::Result<Vec<u8>> {
+        fs::read(path).map_err(|e| {
+            log::warn!("加载{}{}出错，错误内容:{:?}", name, path, e);
+            e
+        })
+    }
+
+    fn is_pem(data: &[u8]) -> bool {
+        data.windows(11).any(|w| w == b"-----BEGIN ")
+    }
+
+    /// 取出PEM中指定标签的内容
+    fn pem_section(data: &[u8], label: &str) -> Option<Vec<u8>> {
+        let text = std::str::from_utf8(data).ok()?;
+        let begin = format!("-----BEGIN {label}-----");
+        let end = format!("-----END {label}-----");
+        let start = text.find(&begin)? + begin.len();
+        let stop = start + text[start..].find(&end)?;
+        let body = text[start..stop]
+            .chars()
+            .filter(|c| !c.is_whitespace())
+            .collect::<String>();
+        STANDARD.decode(body).ok()
+    }
+
+    pub fn load_certs(path: &String) -> NetResult<Vec<CertificateDer<'static>>> {
+        let data = Self::read_file(path, "公钥")?;
+        Self::parse_certs(&data)
+    }
+
+    /// 解析PEM或DER格式的证书
+    pub fn parse_certs(data: &[u8]) -> NetResult<Vec<CertificateDer<'static>>> {
+        let certs = if Self::is_pem(data) {
+            rustls_pemfile::certs(&mut BufReader::new(data))
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|_| TlsError::BadCertificate)?
+        } else if data.first() == Some(&0x30) {
+            vec![CertificateDer::from(data.to_vec())]
+        } else {
+            return Err(TlsError::BadCertificate.into());
+        };
+        if certs.is_empty() {
+            return Err(TlsError::NoCertificate.into());
         }
+        Ok(certs)
     }
 
-    pub fn load_keys(path: &String) -> io::Result<PrivateKeyDer<'static>> {
-        match File::open(&path) {
-            Ok(mut file) => {
-                let mut content = String::new();
-                file.read_to_string(&mut content)?;
-                {
-                    let mut reader = BufReader::new(content.as_bytes());
-                    let mut keys = rustls_pemfile::pkcs8_private_keys(&mut reader)
-                        .collect::<Result<Vec<_>, _>>()?;
-                    if keys.len() == 1 {
-                        return Ok(PrivateKeyDer::from(keys.remove(0)));
-                    }
-                }
-                {
-                    let mut reader = BufReader::new(content.as_bytes());
-                    let mut keys =
-                        rustls_pemfile::rsa_private_keys(&mut reader)
-                            .collect::<Result<Vec<_>, _>>()?;
-                    if keys.len() == 1 {
-                        return Ok(PrivateKeyDer::from(keys.remove(0)));
-                    }
+    pub fn load_keys(path: &String, password: Option<&str>) -> NetResult<PrivateKeyDer<'static>> {
+        let data = Self::read_file(path, "私钥")?;
+        Self::parse_key(&data, password)
+    }
+
+    /// 解析PEM或DER格式的私钥, 支持PKCS#1, SEC1, PKCS#8及加密的PKCS#8
+    pub fn parse_key(data: &[u8], password: Option<&str>) -> NetResult<PrivateKeyDer<'static>> {
+        if !Self::is_pem(data) {
+            return match PrivateKeyDer::try_from(data.to_vec()) {
+                Ok(key) => Ok(key),
+                Err(_) if EncryptedPrivateKeyInfo::try_from(data).is_ok() => {
+                    Self::decrypt_key(data, password)
                 }
-                return Err(io::Error::new(
-                    io::ErrorKind::InvalidInput,
-                    format!("No pkcs8 or rsa private key found"),
-                ));
-            }
-            Err(e) => {
-                log::warn!("加载私钥{}出错，错误内容:{:?}", path, e);
-                return Err(e);
+                Err(_) => Err(TlsError::BadPrivateKey.into()),
+            };
+        }
+        if let Some(der) = Self::pem_section(data, "ENCRYPTED PRIVATE KEY") {
+            return Self::decrypt_key(&der, password);
+        }
+        if data.windows(22).any(|w| w == b"Proc-Type: 4,ENCRYPTED") {
+            log::warn!("不支持传统格式的加密私钥, 请转换为加密的PKCS#8格式");
+            return Err(TlsError::BadPrivateKey.into());
+        }
+        for item in rustls_pemfile::read_all(&mut BufReader::new(data)) {
+            match item.map_err(|_| TlsError::BadPrivateKey)? {
+                Item::Pkcs1Key(key) => return Ok(key.into()),
+                Item::Pkcs8Key(key) => return Ok(key.into()),
+                Item::Sec1Key(key) => return Ok(key.into()),
+                _ => {}
             }
         }
+        Err(TlsError::NoPrivateKey.into())
+    }
+
+    fn decrypt_key(der: &[u8], password: Option<&str>) -> NetResult<PrivateKeyDer<'static>> {
+        let password = unwrap_or!(password, return Err(TlsError::KeyPasswordRequired.into()));
+        let info = EncryptedPrivateKeyInfo::try_from(der).map_err(|_| TlsError::BadPrivateKey)?;
+        let doc = info.decrypt(password).map_err(|_| TlsError::KeyDecrypt)?;
+        Ok(PrivateKeyDer::Pkcs8(doc.as_bytes().to_vec().into()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use base64::{engine::general_purpose::STANDARD, Engine};
+    use pkcs8::{pkcs5::pbes2, PrivateKeyInfo};
+    use rcgen::{CertificateParams, KeyPair, PKCS_ECDSA_P256_SHA256};
+    use tokio_rustls::rustls::{crypto::aws_lc_rs, pki_types::PrivateKeyDer};
+
+    use super::Helper;
+    use crate::{NetError, TlsError};
+
+    fn pem(label: &str, der: &[u8]) -> Vec<u8> {
+        format!(
+            "-----BEGIN {label}-----\n{}\n-----END {label}-----\n",
+            STANDARD.encode(der)
+        )
+        .into_bytes()
+    }
+
+    /// 私钥可被加密实现加载
+    fn assert_usable(key: PrivateKeyDer<'static>) {
+        let provider = aws_lc_rs::default_provider();
+        provider.key_provider.load_private_key(key).unwrap();
+    }
+
+    /// 以PBES2加密的PKCS#8私钥
+    fn encrypt(pkcs8: &[u8], password: &str) -> Vec<u8> {
+        let info = PrivateKeyInfo::try_from(pkcs8).unwrap();
+        let params =
+            pbes2::Parameters::pbkdf2_sha256_aes256cbc(2048, b"hcnet-salt", &[7; 16]).unwrap();
+        info.encrypt_with_params(params, password)
+            .unwrap()
+            .as_bytes()
+            .to_vec()
+    }
+
+    #[test]
+    fn parse_pem_and_der() {
+        let key = KeyPair::generate_for(&PKCS_ECDSA_P256_SHA256).unwrap();
+        let cert = CertificateParams::new(vec!["helper.test".to_string()])
+            .unwrap()
+            .self_signed(&key)
+            .unwrap();
+
+        let certs = Helper::parse_certs(cert.pem().as_bytes()).unwrap();
+        assert_eq!(certs[0].as_ref(), cert.der().as_ref());
+        let certs = Helper::parse_certs(cert.der()).unwrap();
+        assert_eq!(certs[0].as_ref(), cert.der().as_ref());
+        assert!(matches!(
+            Helper::parse_certs(b"not a certificate"),
+            Err(NetError::Tls(TlsError::BadCertificate))
+        ));
+
+        let parsed = Helper::parse_key(key.serialize_pem().as_bytes(), None).unwrap();
+        assert!(matches!(parsed, PrivateKeyDer::Pkcs8(_)));
+        assert_usable(parsed);
+        let parsed = Helper::parse_key(&key.serialize_der(), None).unwrap();
+        assert!(matches!(parsed, PrivateKeyDer::Pkcs8(_)));
+        assert_usable(parsed);
+    }
+
+    #[test]
+    fn parse_sec1() {
+        let key = KeyPair::generate_for(&PKCS_ECDSA_P256_SHA256).unwrap();
+        let der = key.serialize_der();
+        let sec1 = PrivateKeyInfo::try_from(der.as_slice())
+            .unwrap()
+            .private_key
+            .to_vec();
+        let parsed = Helper::parse_key(&pem("EC PRIVATE KEY", &sec1), None).unwrap();
+        assert!(matches!(parsed, PrivateKeyDer::Sec1(_)));
+        assert_usable(parsed);
+        let parsed = Helper::parse_key(&sec1, None).unwrap();
+        assert!(matches!(parsed, PrivateKeyDer::Sec1(_)));
+        assert_usable(parsed);
+    }
+
+    #[test]
+    fn parse_encrypted_pkcs8() {
+        let key = KeyPair::generate_for(&PKCS_ECDSA_P256_SHA256).unwrap();
+        let encrypted = encrypt(&key.serialize_der(), "secret");
+        let encrypted_pem = pem("ENCRYPTED PRIVATE KEY", &encrypted);
+
+        for data in [&encrypted_pem, &encrypted] {
+            let parsed = Helper::parse_key(data, Some("secret")).unwrap();
+            assert_eq!(parsed.secret_der(), key.serialize_der().as_slice());
+            assert_usable(parsed);
+            assert!(matches!(
+                Helper::parse_key(data, Some("wrong")),
+                Err(NetError::Tls(TlsError::KeyDecrypt))
+            ));
+            assert!(matches!(
+                Helper::parse_key(data, None),
+                Err(NetError::Tls(TlsError::KeyPasswordRequired))
+            ));
+        }
     }
 }
@@ -0,0 +1,391 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    sync::{Arc, RwLock},
+};
+
+use crate::{CloseCode, Message, NetError, NetResult, NetSender, PreparedMessage};
+
+/// 广播时成员发送队列已满的处理方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Backpressure {
+    /// 丢弃该成员的此条消息
+    #[default]
+    Drop,
+    /// 丢弃消息并将该成员移出房间
+    Leave,
+}
+
+#[derive(Default)]
+struct HubInner {
+    senders: HashMap<u64, NetSender>,
+    rooms: HashMap<String, HashSet<u64>>,
+    backpressure: Backpressure,
+}
+
+/// 连接的注册中心, 配置到[`crate::Settings`]后监听所接受的连接将自动注册,
+/// 连接关闭后自动移除, 以便通过连接id向任意连接发送消息
+/// 连接可加入命名的房间, 对房间内所有成员广播消息
+#[derive(Clone, Default)]
+pub struct NetHub {
+    inner: Arc<RwLock<HubInner>>,
+}
+
+impl fmt::Debug for NetHub {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NetHub").field("len", &self.len()).finish()
+    }
+}
+
+impl NetHub {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_backpressure(backpressure: Backpressure) -> Self {
+        let hub = Self::default();
+        hub.set_backpressure(backpressure);
+        hub
+    }
+
+    pub fn set_backpressure(&self, backpressure: Backpressure) {
+        if let Ok(mut inner) = self.inner.write() {
+            inner.backpressure = backpressure;
+        }
+    }
+
+    /// 注册连接, 相同id的连接将被替换
+    pub fn register(&self, sender: NetSender) {
+        if let Ok(mut inner) = self.inner.write() {
+            inner.senders.insert(sender.get_connection_id(), sender);
+        }
+    }
+
+    /// 移除连接, 同时离开所有的房间
+    pub fn remove(&self, id: u64) -> Option<NetSender> {
+        let mut inner = self.inner.write().ok()?;
+        inner.rooms.retain(|_, members| {
+            members.remove(&id);
+            !members.is_empty()
+        });
+        inner.senders.remove(&id)
+    }
+
+    /// 获取连接的发送端
+    pub fn get(&self, id: u64) -> Option<NetSender> {
+        self.inner.read().ok()?.senders.get(&id).cloned()
+    }
+
+    pub fn contains(&self, id: u64) -> bool {
+        self.inner
+            .read()
+            .map(|s| s.senders.contains_key(&id))
+            .unwrap_or(false)
+    }
+
+    pub fn len(&self) -> usize {
+        self.inner.read().map(|s| s.senders.len()).unwrap_or(0)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// 当前所有的连接id
+    pub fn ids(&self) -> Vec<u64> {
+        self.inner
+            .read()
+            .map(|s| s.senders.keys().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// 当前所有连接的发送端快照, 遍历时不持有锁
+    pub fn senders(&self) -> Vec<NetSender> {
+        self.inner
+            .read()
+            .map(|s| s.senders.values().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// 遍历所有连接, 遍历期间持有读锁, 回调中不可再注册或移除连接
+    pub fn for_each<F: FnMut(u64, &NetSender)>(&self, mut f: F) {
+        if let Ok(inner) = self.inner.read() {
+            for (id, sender) in inner.senders.iter() {
+                f(*id, sender);
+            }
+        }
+    }
+
+    /// 向指定的连接发送消息
+    pub fn send_to(&self, id: u64, msg: Message) -> NetResult<()> {
+        let mut sender = unwrap_or!(self.get(id), return Err(NetError::NoConnection(id)));
+        sender.send_message(msg)
+    }
+
+    /// 向所有连接发送消息, 消息只编码一次, 返回发送队列已满的连接id
+    pub fn broadcast(&self, msg: Message) -> Vec<u64> {
+        let senders = self.senders();
+        self.fan_out(None, senders, msg)
+    }
+
+    /// 关闭指定的连接
+    pub fn close(&self, id: u64, code: CloseCode, reason: String) -> NetResult<()> {
+        let mut sender = unwrap_or!(self.get(id), return Err(NetError::NoConnection(id)));
+        sender.close_with_reason(code, reason)
+    }
+
+    /// 将连接加入房间, 连接需已注册
+    pub fn join(&self, room: &str, id: u64) -> NetResult<()> {
+        let mut inner = self.inner.write().map_err(|_| NetError::NoConnection(id))?;
+        if !inner.senders.contains_key(&id) {
+            return Err(NetError::NoConnection(id));
+        }
+        inner.rooms.entry(room.to_string()).or_default().insert(id);
+        Ok(())
+    }
+
+    /// 将连接移出房间, 返回连接是否在房间内
+    pub fn leave(&self, room: &str, id: u64) -> bool {
+        let mut inner = unwrap_or!(self.inner.write().ok(), return false);
+        let members = unwrap_or!(inner.rooms.get_mut(room), return false);
+        let ret = members.remove(&id);
+        if members.is_empty() {
+            inner.rooms.remove(room);
+        }
+        ret
+    }
+
+    /// 房间内的所有成员
+    pub fn members(&self, room: &str) -> Vec<u64> {
+        self.inner
+            .read()
+            .ok()
+            .and_then(|s| s.rooms.get(room).map(|m| m.iter().cloned().collect()))
+            .unwrap_or_default()
+    }
+
+    /// 当前所有的房间名
+    pub fn rooms(&self) -> Vec<String> {
+        self.inner
+            .read()
+            .map(|s| s.rooms.keys().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// 向房间内所有成员发送消息, 消息只编码一次,
+    /// 返回发送队列已满的连接id, 并按[`Backpressure`]处理
+    pub fn send_room(&self, room: &str, msg: Message) -> Vec<u64> {
+        let senders = {
+            let inner = unwrap_or!(self.inner.read().ok(), return vec![]);
+            let members = unwrap_or!(inner.rooms.get(room), return vec![]);
+            members
+                .iter()
+                .filter_map(|id| inner.senders.get(id).cloned())
+                .collect::<Vec<_>>()
+        };
+        self.fan_out(Some(room), senders, msg)
+    }
+
+    fn fan_out(&self, room: Option<&str>, senders: Vec<NetSender>, msg: Message) -> Vec<u64> {
+        let msg = Message::Prepared(PreparedMessage::new(msg));
+        let mut full = vec![];
+        let mut closed = vec![];
+        for mut sender in senders {
+            match sender.send_message(msg.clone()) {
+                Ok(_) => {}
+                Err(NetError::SendFull(_)) => full.push(sender.get_connection_id()),
+                Err(_) => closed.push(sender.get_connection_id()),
+            }
+        }
+        for id in closed {
+            self.remove(id);
+        }
+        if let Some(room) = room {
+            let backpressure = self
+                .inner
+                .read()
+                .map(|s| s.backpressure)
+                .unwrap_or_default();
+            if backpressure == Backpressure::Leave {
+                for id in &full {
+                    self.leave(room, *id);
+                }
+            }
+        }
+        full
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Backpressure, NetHub};
+    use crate::{CodecType, Message, NetError, NetReceiver, NetSender, Settings};
+
+    fn register(hub: &NetHub, ids: &[u64], capacity: usize) -> Vec<NetReceiver> {
+        ids.iter()
+            .map(|id| {
+                let (sender, receiver) = NetSender::new(capacity, *id);
+                hub.register(sender);
+                receiver
+            })
+            .collect()
+    }
+
+    fn recv_text(receiver: &mut NetReceiver) -> Option<String> {
+        match receiver.try_recv().ok()?.msg {
+            Message::Text(t) => Some(t),
+            Message::Prepared(p) => match p.message() {
+                Message::Text(t) => Some(t.clone()),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn register_and_send_to() {
+        let hub = NetHub::new();
+        let mut receivers = register(&hub, &[1, 2], 4);
+        assert_eq!(hub.len(), 2);
+        assert!(hub.contains(1));
+        assert_eq!(hub.get(2).unwrap().get_connection_id(), 2);
+        let mut ids = hub.ids();
+        ids.sort();
+        assert_eq!(ids, vec![1, 2]);
+
+        hub.send_to(2, Message::Text("two".to_string())).unwrap();
+        assert_eq!(recv_text(&mut receivers[1]).as_deref(), Some("two"));
+        assert!(recv_text(&mut receivers[0]).is_none());
+        assert!(matches!(
+            hub.send_to(3, Message::Text("none".to_string())),
+            Err(NetError::NoConnection(3))
+        ));
+
+        assert!(hub.remove(1).is_some());
+        assert!(!hub.contains(1));
+        assert_eq!(hub.len(), 1);
+    }
+
+    #[test]
+    fn broadcast_fan_out() {
+        let hub = NetHub::new();
+        let mut receivers = register(&hub, &[1, 2, 3], 4);
+        assert!(hub.broadcast(Message::Text("all".to_string())).is_empty());
+        let mut first = None;
+        for receiver in &mut receivers {
+            let p = match receiver.try_recv().unwrap().msg {
+                Message::Prepared(p) => p,
+                _ => unreachable!(),
+            };
+            // 所有成员共享同一条预编码的消息
+            let ptr = p.message() as *const Message;
+            assert_eq!(*first.get_or_insert(ptr), ptr);
+        }
+    }
+
+    #[test]
+    fn rooms_and_remove() {
+        let hub = NetHub::new();
+        let mut receivers = register(&hub, &[1, 2, 3], 4);
+        hub.join("a", 1).unwrap();
+        hub.join("a", 2).unwrap();
+        hub.join("b", 2).unwrap();
+        hub.join("b", 3).unwrap();
+        assert!(matches!(hub.join("a", 9), Err(NetError::NoConnection(9))));
+
+        let mut rooms = hub.rooms();
+        rooms.sort();
+        assert_eq!(rooms, vec!["a".to_string(), "b".to_string()]);
+        assert!(hub
+            .send_room("a", Message::Text("room".to_string()))
+            .is_empty());
+        assert_eq!(recv_text(&mut receivers[0]).as_deref(), Some("room"));
+        assert_eq!(recv_text(&mut receivers[1]).as_deref(), Some("room"));
+        assert!(recv_text(&mut receivers[2]).is_none());
+
+        assert!(hub.leave("a", 1));
+        assert!(!hub.leave("a", 1));
+        assert_eq!(hub.members("a"), vec![2]);
+
+        // 连接断开后离开所有的房间, 空房间被移除
+        hub.remove(2);
+        assert!(hub.members("a").is_empty());
+        assert_eq!(hub.members("b"), vec![3]);
+        assert_eq!(hub.rooms(), vec!["b".to_string()]);
+    }
+
+    #[test]
+    fn closed_member_removed() {
+        let hub = NetHub::new();
+        let mut receivers = register(&hub, &[1, 2], 4);
+        hub.join("a", 1).unwrap();
+        hub.join("a", 2).unwrap();
+        receivers[0].close();
+        assert!(hub
+            .send_room("a", Message::Text("room".to_string()))
+            .is_empty());
+        assert!(!hub.contains(1));
+        assert_eq!(hub.members("a"), vec![2]);
+    }
+
+    #[test]
+    fn backpressure_drop() {
+        let hub = NetHub::new();
+        let mut receivers = register(&hub, &[1, 2], 1);
+        hub.join("a", 1).unwrap();
+        hub.join("a", 2).unwrap();
+        hub.send_to(1, Message::Text("busy".to_string())).unwrap();
+        assert_eq!(
+            hub.send_room("a", Message::Text("room".to_string())),
+            vec![1]
+        );
+        let mut members = hub.members("a");
+        members.sort();
+        assert_eq!(members, vec![1, 2]);
+        assert_eq!(recv_text(&mut receivers[0]).as_deref(), Some("busy"));
+        assert!(recv_text(&mut receivers[0]).is_none());
+        assert_eq!(recv_text(&mut receivers[1]).as_deref(), Some("room"));
+    }
+
+    #[test]
+    fn backpressure_leave() {
+        let hub = NetHub::with_backpressure(Backpressure::Leave);
+        let _receivers = register(&hub, &[1, 2], 1);
+        hub.join("a", 1).unwrap();
+        hub.join("a", 2).unwrap();
+        hub.send_to(1, Message::Text("busy".to_string())).unwrap();
+        assert_eq!(
+            hub.send_room("a", Message::Text("room".to_string())),
+            vec![1]
+        );
+        assert_eq!(hub.members("a"), vec![2]);
+        assert!(hub.contains(1));
+    }
+
+    #[test]
+    fn prepared_encoded_once() {
+        let hub = NetHub::new();
+        let mut receivers = register(&hub, &[1, 2], 4);
+        hub.broadcast(Message::Binary(vec![7; 64]));
+        let settings = Settings::default();
+        let mut cached = vec![];
+        for receiver in &mut receivers {
+            let p = match receiver.try_recv().unwrap().msg {
+                Message::Prepared(p) => p,
+                _ => unreachable!(),
+            };
+            let hcnet = p.encoded(CodecType::Hcnet, &settings).unwrap().unwrap();
+            let u32be = p.encoded(CodecType::U32Be, &settings).unwrap().unwrap();
+            assert_ne!(hcnet, u32be);
+            cached.push((hcnet.as_ptr(), u32be.as_ptr()));
+            // 配置不同的连接不使用缓存, 由连接自行编码
+            let other = Settings {
+                fragment_size: 16,
+                ..Settings::default()
+            };
+            assert!(p.encoded(CodecType::Hcnet, &other).unwrap().is_none());
+        }
+        // 相同分包方式及配置的连接复用同一份编码结果
+        assert_eq!(cached[0], cached[1]);
+    }
+}
@@ -1,7 +1,10 @@
-use std::net::SocketAddr;
+use std::{net::SocketAddr, sync::Arc};
 
 
 use tokio_kcp::{KcpListener, KcpStream};
+use tokio_rustls::TlsAcceptor;
+
+use crate::{Settings, TlsReloader};
 
 use super::NetResult;
 
@@ -9,20 +12,24 @@ pub struct WrapKcpListener {
     pub listener: KcpListener,
     pub server_id: u64,
     pub next_connection_id: u32,
+    pub accepter: TlsReloader,
 }
 
 impl WrapKcpListener {
-    pub fn new(server_id: u64, listener: KcpListener) -> Self {
-        Self {
+    pub fn new(server_id: u64, listener: KcpListener, settings: &Settings) -> NetResult<Self> {
+        Ok(Self {
             listener,
             server_id: server_id << 32,
             next_connection_id: 0,
-        }
+            accepter: TlsReloader::acceptor(settings)?,
+        })
     }
 
-    pub async fn accept(&mut self) -> NetResult<(KcpStream, SocketAddr, u64)> {
+    pub async fn accept(
+        &mut self,
+    ) -> NetResult<(KcpStream, SocketAddr, u64, Option<Arc<TlsAcceptor>>)> {
         let (stream, addr) = self.listener.accept().await?;
         self.next_connection_id = self.next_connection_id.wrapping_add(1);
-        Ok((stream, addr, self.server_id + self.next_connection_id as u64))
+        Ok((stream, addr, self.server_id + self.next_connection_id as u64, self.accepter.current()))
     }
 }
@@ -1,37 +1,73 @@
 use algorithm::buf::{BinaryMut, Bt, BtMut};
-use std::{net::SocketAddr, time::Duration};
+use std::{net::SocketAddr, sync::Arc, time::Duration};
 use tokio::{
     io::{split, AsyncReadExt, AsyncWriteExt, ReadBuf},
     net::{lookup_host, ToSocketAddrs},
     time,
 };
 use tokio_kcp::{KcpConfig, KcpListener, KcpStream};
+use tokio_rustls::{rustls::pki_types::CertificateDer, TlsAcceptor};
 mod listener;
 mod state;
 use listener::WrapKcpListener;
 pub use state::KcpState;
 
-use crate::{id_center::IdCenter, NetConn, NetReceiver};
+use crate::{
+    heartbeat::Heartbeat,
+    id_center::IdCenter,
+    reconnect::{Disconnect, FrameMarks},
+    NetConn, NetReceiver,
+};
 
-use super::{decode_message, encode_message, CloseCode, NetError, Settings};
+use super::{CloseCode, MaybeTlsStream, NetError, Settings, TlsInfo, TlsReloader};
 
-use super::{handler::Handler, message::Message, NetResult, NetSender};
+use super::{
+    codec::{encode_with_codec, Codec, CodecType},
+    handler::Handler,
+    message::Message,
+    NetResult,
+};
 
 enum Kcp {
-    Stream(KcpStream),
+    Stream(MaybeTlsStream<KcpStream>),
+    /// 监听接受的连接, 运行时再进行tls握手
+    Accept(KcpStream, Option<Arc<TlsAcceptor>>),
     Listener(WrapKcpListener),
     Unconnect(Vec<SocketAddr>),
     Uninit,
 }
 
+impl Kcp {
+    pub async fn try_accept(&mut self, timeout: Duration) -> NetResult<()> {
+        match std::mem::replace(self, Kcp::Uninit) {
+            Kcp::Accept(stream, Some(accepter)) => {
+                let accept = MaybeTlsStream::accept_tls(&accepter, stream);
+                match time::timeout(timeout, accept).await {
+                    Ok(v) => *self = Kcp::Stream(v?),
+                    Err(_) => return Err(NetError::Timeout),
+                }
+            }
+            Kcp::Accept(stream, None) => *self = Kcp::Stream(MaybeTlsStream::Stream(stream)),
+            kcp => *self = kcp,
+        }
+        Ok(())
+    }
+}
+
 pub struct KcpConn {
     kcp: Kcp,
     settings: Settings,
+    codec: Box<dyn Codec>,
     id: u64,
     state: KcpState,
     addr: Option<SocketAddr>,
     read: BinaryMut,
     write: BinaryMut,
+    /// 写缓存中各消息的边界
+    marks: FrameMarks,
+    /// 客户端连接的目标地址, 用于断线重连
+    target: Option<Vec<SocketAddr>>,
+    disconnect: Disconnect,
 }
 
 enum TcpReceiver {
@@ -51,8 +87,12 @@ impl Default for KcpConn {
             id: 0,
             state: KcpState::Open,
             settings: Settings::default(),
+            codec: CodecType::default().build(),
             read: BinaryMut::new(),
             write: BinaryMut::new(),
+            marks: FrameMarks::default(),
+            target: None,
+            disconnect: Disconnect::default(),
         }
     }
 }
@@ -60,6 +100,7 @@ impl Default for KcpConn {
 impl KcpConn {
     pub fn new(addrs: Vec<SocketAddr>) -> KcpConn {
         KcpConn {
+            target: Some(addrs.clone()),
             kcp: Kcp::Unconnect(addrs),
             ..Default::default()
         }
@@ -67,20 +108,22 @@ impl KcpConn {
 
     pub async fn bind_with_listener(
         listener: KcpListener,
-        _settings: Settings,
+        settings: Settings,
     ) -> NetResult<KcpConn> {
         let id = IdCenter::next_connect_id();
         Ok(KcpConn {
             id,
-            kcp: Kcp::Listener(WrapKcpListener::new(id, listener)),
+            kcp: Kcp::Listener(WrapKcpListener::new(id, listener, &settings)?),
+            codec: settings.build_codec(),
+            settings,
             ..Default::default()
         })
     }
 
-    pub async fn bind<A: ToSocketAddrs>(addr: A, _settings: Settings) -> NetResult<KcpConn> {
+    pub async fn bind<A: ToSocketAddrs>(addr: A, settings: Settings) -> NetResult<KcpConn> {
         let config = KcpConfig::default();
         let listener = KcpListener::bind(config, addr).await?;
-        Self::bind_with_listener(listener, _settings).await
+        Self::bind_with_listener(listener, settings).await
     }
 
     pub async fn connect<A: ToSocketAddrs>(addr: A) -> NetResult<KcpConn> {
@@ -89,7 +132,7 @@ impl KcpConn {
 
     pub async fn connect_with_stream(stream: KcpStream) -> NetResult<KcpConn> {
         Ok(KcpConn {
-            kcp: Kcp::Stream(stream),
+            kcp: Kcp::Stream(MaybeTlsStream::Stream(stream)),
             id: IdCenter::next_connect_id(),
             ..Default::default()
         })
@@ -114,10 +157,21 @@ impl KcpConn {
         {
             Ok(v) => {
                 let stream = v?;
+                let stream = if settings.is_client_tls() {
+                    let domain = match &settings.domain {
+                        Some(domain) => domain.clone(),
+                        None => addrs[0].ip().to_string(),
+                    };
+                    MaybeTlsStream::connect_tls_with_settings(stream, domain, &settings).await?
+                } else {
+                    MaybeTlsStream::Stream(stream)
+                };
                 Ok(KcpConn {
                     kcp: Kcp::Stream(stream),
                     id: IdCenter::next_connect_id(),
+                    codec: settings.build_codec(),
                     settings,
+                    target: Some(addrs),
                     ..Default::default()
                 })
             }
@@ -137,11 +191,13 @@ impl KcpConn {
     async fn process(&mut self) -> NetResult<TcpReceiver> {
         match &mut self.kcp {
             Kcp::Listener(listener) => {
-                let (stream, addr, id) = listener.accept().await?;
+                let (stream, addr, id, accepter) = listener.accept().await?;
                 Ok(TcpReceiver::Accept(KcpConn {
-                    kcp: Kcp::Stream(stream),
+                    kcp: Kcp::Accept(stream, accepter),
                     addr: Some(addr),
                     id,
+                    codec: self.settings.build_codec(),
+                    settings: self.settings.clone(),
                     ..Default::default()
                 }))
             }
@@ -150,7 +206,7 @@ impl KcpConn {
                     KcpState::Open => {
                         let (mut reader, mut writer) = split(stream);
                         loop {
-                            if let Some(v) = decode_message(&mut self.read, &self.settings)? {
+                            if let Some(v) = self.codec.decode(&mut self.read, &self.settings)? {
                                 return Ok(TcpReceiver::Read(v));
                             }
 
@@ -173,8 +229,10 @@ impl KcpConn {
                                 r = writer.write(self.write.chunk()), if self.write.has_remaining() => {
                                     let n = r?;
                                     self.write.advance(n);
+                                    self.marks.advance(n);
                                     if !self.write.has_remaining() {
                                         self.write.clear();
+                                        self.marks.clear();
                                     }
                                 }
                                 _ = tokio::time::sleep(Duration::from_millis(self.settings.read_timeout as u64)) => {
@@ -190,8 +248,10 @@ impl KcpConn {
                                 r = stream.write(self.write.chunk()), if self.write.has_remaining() => {
                                     let n = r?;
                                     self.write.advance(n);
+                                    self.marks.advance(n);
                                     if !self.write.has_remaining() {
                                         self.write.clear();
+                                        self.marks.clear();
                                         self.state = KcpState::Closed;
                                         return Ok(TcpReceiver::Read(Message::Shutdown));
                                     }
@@ -207,11 +267,7 @@ impl KcpConn {
                 }
             }
             Kcp::Unconnect(addr) => {
-                let stream = Self::connect_with_timeout(
-                    &addr[..],
-                    Duration::from_millis(self.settings.connect_timeout as u64),
-                )
-                .await?;
+                let stream = Self::connect_with_settings(&addr[..], self.settings.clone()).await?;
                 self.kcp = stream.kcp;
                 return Ok(TcpReceiver::Pending);
             }
@@ -227,12 +283,40 @@ impl KcpConn {
         self.addr
     }
 
+    pub(crate) fn can_reconnect(&self) -> bool {
+        self.target.is_some() && self.disconnect.should_reconnect()
+    }
+
+    /// 重新连接目标地址, 重置连接状态及读缓存, 写缓存中完整未发送的消息将在重连后发出
+    pub(crate) async fn reconnect(&mut self) -> NetResult<()> {
+        let addrs = unwrap_or!(
+            self.target.clone(),
+            return Err(NetError::Extension("no target to reconnect"))
+        );
+        let conn = Self::connect_with_settings(&addrs[..], self.settings.clone()).await?;
+        self.kcp = conn.kcp;
+        self.state = KcpState::Open;
+        self.disconnect = Disconnect::default();
+        self.read.clear();
+        // 丢弃已部分写出的消息, 保留完整未发送的消息
+        let partial = self.marks.take_partial();
+        self.write.advance(partial);
+        if self.codec.codec_type().is_some() {
+            self.codec = conn.codec;
+        }
+        Ok(())
+    }
+
+    /// 编码消息至写缓存, 并记录消息边界
+    fn encode(&mut self, msg: Message) -> NetResult<()> {
+        let len = self.write.remaining();
+        encode_with_codec(&mut *self.codec, &mut self.write, msg, &self.settings)?;
+        self.marks.push(self.write.remaining() - len);
+        Ok(())
+    }
+
     pub(crate) fn close(&mut self, code: CloseCode, reason: String) -> NetResult<()> {
-        encode_message(
-            &mut self.write,
-            Message::Close(code, reason.clone()),
-            self.settings.is_raw,
-        )?;
+        self.encode(Message::Close(code, reason.clone()))?;
         self.state = KcpState::Closing((code, reason));
         Ok(())
     }
@@ -240,34 +324,51 @@ impl KcpConn {
     pub(crate) async fn inner_run_with_handler<H>(
         &mut self,
         handler: &mut H,
-        mut receiver: NetReceiver,
+        receiver: &mut NetReceiver,
     ) -> NetResult<()>
     where
         H: Handler + 'static + Sync + Send,
     {
+        self.kcp
+            .try_accept(Duration::from_millis(self.settings.shake_timeout as u64))
+            .await?;
+        if let Some(info) = self.tls_info() {
+            handler.on_tls_handshake(info).await?;
+        }
+        if let Some(certs) = self.peer_certificates() {
+            handler.on_peer_certificates(certs).await?;
+        }
         handler.on_open().await?;
         let is_listen = self.is_listen();
+        let mut heartbeat = Heartbeat::new(&self.settings, self.codec.has_control());
         loop {
+            let heartbeat_time = heartbeat.deadline();
             tokio::select! {
                 ret = self.process() => {
                     let r = ret?;
                     match r {
                         TcpReceiver::Accept(tcp) => {
-                            handler.on_accept(NetConn::from((tcp, self.settings.clone()))).await?
+                            handler.on_accept(NetConn::from(tcp)).await?
                         },
                         TcpReceiver::Read(msg) => {
                             match msg {
                                 Message::Text(_) | Message::Binary(_) => handler.on_message(msg).await?,
                                 Message::Close(code, reason) => {
+                                    self.disconnect = Disconnect::Remote(code);
                                     handler.on_close(code, reason).await;
                                     return Ok(())
                                 }
                                 Message::Ping(data) => {
                                     if let Some(ret) = handler.on_ping(data).await? {
-                                        encode_message(&mut self.write, Message::Pong(ret), self.settings.is_raw)?;
+                                        self.encode(Message::Pong(ret))?;
                                     }
                                 },
-                                Message::Pong(data) => handler.on_pong(data).await?,
+                                Message::Pong(data) => {
+                                    if let Some(rtt) = heartbeat.on_pong(&data) {
+                                        handler.on_heartbeat(rtt).await?;
+                                    }
+                                    handler.on_pong(data).await?
+                                },
                                 _ => return Ok(()),
                             }
                         },
@@ -278,19 +379,35 @@ impl KcpConn {
                     if is_listen {
                         return Ok(())
                     }
-                    let c = unwrap_or!(c, return Ok(()));
+                    let c = unwrap_or!(c, {
+                        self.disconnect = Disconnect::Local;
+                        return Ok(())
+                    });
                     match c.msg {
                         Message::Close(code, reason) => {
+                            self.disconnect = Disconnect::Local;
                             self.close(code, reason)?;
                             continue;
                         },
                         Message::Shutdown => {
+                            self.disconnect = Disconnect::Local;
                             self.close(CloseCode::Away, "Shutdown".to_string())?;
                             continue;
                         },
                         _ => {}
                     }
-                    encode_message(&mut self.write, c.msg, self.settings.is_raw)?;
+                    self.encode(c.msg)?;
+                }
+                _ = time::sleep_until(heartbeat_time), if heartbeat.is_enable() && !is_listen => {
+                    match heartbeat.on_tick() {
+                        Some(data) => self.encode(Message::Ping(data))?,
+                        None => {
+                            let reason = "heartbeat timeout".to_string();
+                            self.disconnect = Disconnect::Heartbeat;
+                            handler.on_close(CloseCode::Heartbeat, reason.clone()).await;
+                            self.close(CloseCode::Heartbeat, reason)?;
+                        }
+                    }
                 }
                 r = handler.on_logic() => {
                     let _ = r?;
@@ -299,21 +416,28 @@ impl KcpConn {
         }
     }
 
-    pub(crate) async fn inner_run_handler<F, H>(&mut self, factory: F) -> NetResult<()>
-    where
-        F: FnOnce(NetSender) -> H + Send + 'static,
-        H: Handler + 'static + Sync + Send,
-    {
-        let (sender, receiver) = NetSender::new(self.settings.queue_size, self.id);
-        let _avoid = sender.clone();
-        let mut handler = factory(sender);
-        if let Err(e) = self.inner_run_with_handler(&mut handler, receiver).await {
-            handler
-                .on_close(CloseCode::Error, "NetError".to_string())
-                .await;
-            return Err(e);
+    /// 对端出示的证书链, 仅在kcps连接且对端出示了证书时存在
+    pub fn peer_certificates(&self) -> Option<Vec<CertificateDer<'static>>> {
+        match &self.kcp {
+            Kcp::Stream(stream) => stream.peer_certificates(),
+            _ => None,
+        }
+    }
+
+    /// 监听的证书热更新句柄
+    pub fn tls_reloader(&self) -> Option<TlsReloader> {
+        match &self.kcp {
+            Kcp::Listener(listener) => Some(listener.accepter.clone()),
+            _ => None,
+        }
+    }
+
+    /// tls握手协商的信息, 仅在kcps连接时存在
+    pub fn tls_info(&self) -> Option<TlsInfo> {
+        match &self.kcp {
+            Kcp::Stream(stream) => stream.tls_info(),
+            _ => None,
         }
-        Ok(())
     }
 
     pub fn get_settings(&mut self) -> &mut Settings {
@@ -321,9 +445,15 @@ impl KcpConn {
     }
 
     pub fn set_settings(&mut self, settings: Settings) {
+        self.codec = settings.build_codec();
         self.settings = settings
     }
 
+    /// 设置自定义的分包方式, 之后调用set_settings将恢复为配置中的分包方式
+    pub fn set_codec(&mut self, codec: Box<dyn Codec>) {
+        self.codec = codec;
+    }
+
     pub fn get_connection_id(&self) -> u64 {
         self.id
     }
@@ -335,3 +465,130 @@ impl KcpConn {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{net::SocketAddr, time::Duration};
+
+    use rcgen::{CertificateParams, KeyPair};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio_kcp::{KcpConfig, KcpListener};
+
+    use super::{Kcp, KcpConn, MaybeTlsStream, TcpReceiver};
+    use crate::{Settings, TlsMode};
+
+    /// 配置了内存中自签名证书的监听
+    async fn bind(tls_mode: TlsMode) -> (KcpConn, SocketAddr) {
+        let key = KeyPair::generate().unwrap();
+        let cert = CertificateParams::new(vec!["kcp.test".to_string()])
+            .unwrap()
+            .self_signed(&key)
+            .unwrap();
+        let settings = Settings {
+            tls_mode,
+            cert_data: Some(cert.pem().into_bytes()),
+            key_data: Some(key.serialize_pem().into_bytes()),
+            ..Settings::default()
+        };
+        let listener = KcpListener::bind(KcpConfig::default(), "127.0.0.1:0")
+            .await
+            .unwrap();
+        let addr = listener.local_addr().unwrap();
+        let conn = KcpConn::bind_with_listener(listener, settings)
+            .await
+            .unwrap();
+        (conn, addr)
+    }
+
+    /// 接受连接并完成握手
+    async fn accept(listener: &mut KcpConn) -> KcpConn {
+        let mut conn = match listener.process().await.unwrap() {
+            TcpReceiver::Accept(conn) => conn,
+            _ => unreachable!(),
+        };
+        conn.kcp.try_accept(Duration::from_secs(5)).await.unwrap();
+        conn
+    }
+
+    /// 互相发送数据, 确认两端的流可用
+    async fn exchange(client: &mut KcpConn, server: &mut KcpConn) {
+        let (Kcp::Stream(client), Kcp::Stream(server)) = (&mut client.kcp, &mut server.kcp) else {
+            unreachable!()
+        };
+        let mut buf = [0u8; 4];
+        client.write_all(b"ping").await.unwrap();
+        client.flush().await.unwrap();
+        server.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"ping");
+        server.write_all(b"pong").await.unwrap();
+        server.flush().await.unwrap();
+        client.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"pong");
+    }
+
+    #[tokio::test]
+    async fn kcps_loopback() {
+        let (mut listener, addr) = bind(TlsMode::Server).await;
+        let settings = Settings {
+            tls_mode: TlsMode::Client,
+            domain: Some("kcp.test".to_string()),
+            insecure_skip_verify: true,
+            ..Settings::default()
+        };
+        let (mut server, client) = tokio::join!(
+            accept(&mut listener),
+            KcpConn::connect_with_settings(addr, settings)
+        );
+        let mut client = client.unwrap();
+        assert!(matches!(
+            &client.kcp,
+            Kcp::Stream(MaybeTlsStream::TlsStream(_))
+        ));
+        assert!(matches!(
+            &server.kcp,
+            Kcp::Stream(MaybeTlsStream::TlsServer(_))
+        ));
+        let info = server.tls_info().unwrap();
+        assert_eq!(info.server_name.as_deref(), Some("kcp.test"));
+        assert!(client.peer_certificates().is_some());
+        exchange(&mut client, &mut server).await;
+    }
+
+    #[tokio::test]
+    async fn off_with_cert_is_plain() {
+        let (mut listener, addr) = bind(TlsMode::Off).await;
+        assert!(listener.tls_reloader().unwrap().current().is_none());
+        let settings = Settings {
+            tls_mode: TlsMode::Off,
+            domain: Some("kcp.test".to_string()),
+            ..Settings::default()
+        };
+        let mut client = KcpConn::connect_with_settings(addr, settings)
+            .await
+            .unwrap();
+        assert!(matches!(
+            &client.kcp,
+            Kcp::Stream(MaybeTlsStream::Stream(_))
+        ));
+        // 明文连接由客户端的首个数据建立
+        let (mut server, _) = tokio::join!(accept(&mut listener), async {
+            let Kcp::Stream(stream) = &mut client.kcp else {
+                unreachable!()
+            };
+            stream.write_all(b"open").await.unwrap();
+            stream.flush().await.unwrap();
+        });
+        assert!(matches!(
+            &server.kcp,
+            Kcp::Stream(MaybeTlsStream::Stream(_))
+        ));
+        assert!(server.tls_info().is_none());
+        let Kcp::Stream(stream) = &mut server.kcp else {
+            unreachable!()
+        };
+        let mut buf = [0u8; 4];
+        stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"open");
+        exchange(&mut client, &mut server).await;
+    }
+}
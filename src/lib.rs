@@ -3,37 +3,57 @@ mod macros;
 
 mod accept_server;
 mod builder;
+mod codec;
 mod conn;
 mod decode;
 mod encode;
 mod error;
 mod handler;
+mod heartbeat;
 mod helper;
+mod hub;
 mod id_center;
 mod kcp;
 mod listener;
 mod message;
+mod net_url;
 mod online_count;
 mod protocol;
+mod proxy;
+mod quic;
+mod reconnect;
 mod sender;
 mod settings;
 mod stream;
 mod tcp;
+mod tls;
+mod tunnel;
+#[cfg(unix)]
+mod unix;
 mod ws;
 
 use algorithm::buf::{Bt, BtMut};
 pub use builder::Builder;
+pub use codec::{Codec, CodecType, HcnetCodec, LengthCodec, LineCodec, RawCodec, VarintCodec};
 pub use conn::{NetConn, NetType};
 pub use decode::*;
 pub use encode::*;
 pub use error::NetError;
 pub use handler::Handler;
+pub use hub::{Backpressure, NetHub};
 pub use listener::WrapListener;
-pub use message::{Message, OpCode};
+pub use message::{Message, OpCode, PreparedMessage};
+pub use net_url::NetUrl;
 pub use protocol::CloseCode;
-pub use sender::{NetReceiver, NetSender};
-pub use settings::Settings;
+pub use proxy::ProxyHeader;
+pub use sender::{Command, NetReceiver, NetSender, SendPermit};
+pub use settings::{
+    CompressType, DeflateSettings, QueueType, ReconnectSettings, Settings, SniCert, TlsMode,
+    TrustRoots,
+};
 pub use tcp::TcpConn;
+pub use tls::{TlsError, TlsInfo, TlsReloader};
+pub use tunnel::{ProxyConfig, ProxyKind, Target, ToTarget};
 
 pub use stream::MaybeTlsStream;
 
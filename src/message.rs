@@ -1,11 +1,17 @@
-use std::fmt;
+use std::{
+    fmt,
+    sync::{Arc, OnceLock},
+};
 
-use webparse::ws::{CloseData, OwnedMessage};
+use algorithm::buf::{BinaryMut, Bt};
+use webparse::ws::{CloseData, DataFrameable, OwnedMessage};
 use OpCode::*;
 
-use super::protocol::CloseCode;
+use super::{protocol::CloseCode, CodecType, CompressType, NetResult, Settings};
 
 pub enum OpCode {
+    /// 分片消息的后续分片
+    Continuation = 0,
     Text = 1,
     Binary = 2,
     Close = 8,
@@ -19,7 +25,7 @@ impl OpCode {
     /// Test whether the opcode indicates a control frame.
     pub fn is_control(&self) -> bool {
         match *self {
-            Text | Binary => false,
+            Continuation | Text | Binary => false,
             _ => true,
         }
     }
@@ -28,6 +34,7 @@ impl OpCode {
 impl fmt::Display for OpCode {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
+            Continuation => write!(f, "CONTINUATION"),
             Text => write!(f, "TEXT"),
             Binary => write!(f, "BINARY"),
             Close => write!(f, "CLOSE"),
@@ -42,6 +49,7 @@ impl fmt::Display for OpCode {
 impl Into<u8> for OpCode {
     fn into(self) -> u8 {
         match self {
+            Continuation => 0,
             Text => 1,
             Binary => 2,
             Close => 8,
@@ -62,6 +70,7 @@ impl Into<u8> for OpCode {
 impl From<u8> for OpCode {
     fn from(byte: u8) -> OpCode {
         match byte {
+            0 => Continuation,
             1 => Text,
             2 => Binary,
             8 => Close,
@@ -89,6 +98,98 @@ pub enum Message {
     Shutdown,
     /// 关闭, 则表示进行了不写模式
     Unvaid,
+    /// 预编码的消息, 广播时只编码一次
+    Prepared(PreparedMessage),
+}
+
+/// 影响分包编码结果的配置
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct EncodeKey {
+    compress: CompressType,
+    compress_threshold: usize,
+    fragment_size: usize,
+}
+
+impl From<&Settings> for EncodeKey {
+    fn from(settings: &Settings) -> Self {
+        EncodeKey {
+            compress: settings.compress,
+            compress_threshold: settings.compress_threshold,
+            fragment_size: settings.fragment_size,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct PreparedInner {
+    msg: Message,
+    encoded: [OnceLock<(EncodeKey, Vec<u8>)>; CodecType::COUNT],
+    ws: OnceLock<Vec<u8>>,
+}
+
+/// 预编码的消息, 克隆代价低, 各协议的编码结果在首次使用时生成并缓存,
+/// 同一条消息发送给多个配置相同的连接时不重复编码
+#[derive(Debug, Clone)]
+pub struct PreparedMessage {
+    inner: Arc<PreparedInner>,
+}
+
+impl PreparedMessage {
+    pub fn new(msg: Message) -> Self {
+        if let Message::Prepared(p) = msg {
+            return p;
+        }
+        Self {
+            inner: Arc::new(PreparedInner {
+                msg,
+                encoded: std::array::from_fn(|_| OnceLock::new()),
+                ws: OnceLock::new(),
+            }),
+        }
+    }
+
+    pub fn message(&self) -> &Message {
+        &self.inner.msg
+    }
+
+    /// tcp/kcp等协议在指定分包方式下的编码结果
+    /// 缓存以首个连接的压缩及分片配置生成, 配置不同的连接返回None, 由连接自行编码
+    pub(crate) fn encoded(
+        &self,
+        codec_type: CodecType,
+        settings: &Settings,
+    ) -> NetResult<Option<&[u8]>> {
+        let key = EncodeKey::from(settings);
+        let cell = &self.inner.encoded[codec_type as usize];
+        if cell.get().is_none() {
+            let mut data = BinaryMut::new();
+            codec_type
+                .build()
+                .encode(&mut data, self.inner.msg.clone(), settings)?;
+            let _ = cell.set((key, data.chunk().to_vec()));
+        }
+        match cell.get() {
+            Some((k, data)) if *k == key => Ok(Some(data)),
+            _ => Ok(None),
+        }
+    }
+
+    /// websocket服务端的帧格式, 服务端的帧不需要掩码故可复用
+    pub(crate) fn ws_frame(&self) -> NetResult<&[u8]> {
+        if let Some(data) = self.inner.ws.get() {
+            return Ok(data);
+        }
+        let mut data = BinaryMut::new();
+        let msg: OwnedMessage = self.inner.msg.clone().into();
+        msg.write_to(&mut data, None)?;
+        Ok(self.inner.ws.get_or_init(|| data.chunk().to_vec()))
+    }
+}
+
+impl From<Message> for PreparedMessage {
+    fn from(value: Message) -> Self {
+        PreparedMessage::new(value)
+    }
 }
 
 impl Message {
@@ -102,6 +203,7 @@ impl Message {
             Message::Pong(_) => OpCode::Pong,
             Message::Shutdown => OpCode::Shutdown,
             Message::Unvaid => OpCode::Bad,
+            Message::Prepared(p) => p.message().op_code(),
         }
     }
 
@@ -114,6 +216,7 @@ impl Message {
             Message::Pong(_) => OpCode::Pong as u8,
             Message::Shutdown => OpCode::Shutdown as u8,
             Message::Unvaid => OpCode::Bad as u8,
+            Message::Prepared(p) => p.message().get_type(),
         }
     }
 }
@@ -144,7 +247,44 @@ impl Into<OwnedMessage> for Message {
             Message::Close(code, reason) => OwnedMessage::Close(Some(CloseData::new(code, reason))),
             Message::Ping(vec) => OwnedMessage::Ping(vec),
             Message::Pong(vec) => OwnedMessage::Pong(vec),
+            Message::Prepared(p) => p.message().clone().into(),
             _ => OwnedMessage::Close(None),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use algorithm::buf::{BinaryMut, Bt};
+
+    use super::{Message, PreparedMessage};
+    use crate::{codec::encode_with_codec, CodecType, NetResult, Settings};
+
+    fn encode(p: &PreparedMessage, codec_type: CodecType, settings: &Settings) -> NetResult<Vec<u8>> {
+        let mut data = BinaryMut::new();
+        let msg = Message::Prepared(p.clone());
+        encode_with_codec(&mut *codec_type.build(), &mut data, msg, settings)?;
+        Ok(data.chunk().to_vec())
+    }
+
+    #[test]
+    fn prepared_follows_each_settings() {
+        let p = PreparedMessage::new(Message::Binary(vec![1; 100]));
+        let whole = encode(&p, CodecType::Hcnet, &Settings::default()).unwrap();
+        let fragmented = Settings {
+            fragment_size: 10,
+            ..Settings::default()
+        };
+        let parts = encode(&p, CodecType::Hcnet, &fragmented).unwrap();
+        assert_ne!(whole, parts);
+        let again = encode(&p, CodecType::Hcnet, &Settings::default()).unwrap();
+        assert_eq!(whole, again);
+    }
+
+    #[test]
+    fn prepared_encode_error_not_cached() {
+        let p = PreparedMessage::new(Message::Binary(vec![1; u16::MAX as usize + 1]));
+        assert!(encode(&p, CodecType::U16Be, &Settings::default()).is_err());
+        assert!(encode(&p, CodecType::U16Be, &Settings::default()).is_err());
+    }
+}
@@ -0,0 +1,188 @@
+use crate::{NetError, NetResult, Settings};
+
+/// 统一的连接地址, 格式为`scheme://host:port/path?key=value`
+/// 支持的scheme为tcp, tls, ws, wss, kcp, kcps, quic, unix, 监听时还支持auto
+/// query中与[`Settings`]同名的字段将覆盖配置, 其余参数在ws中保留
+#[derive(Debug, Clone)]
+pub struct NetUrl {
+    /// 协议名, 统一为小写
+    pub scheme: String,
+    /// host:port部分, unix下为空
+    pub authority: String,
+    /// 路径部分, unix下为socket文件路径
+    pub path: String,
+    /// 查询参数
+    pub query: Vec<(String, String)>,
+}
+
+/// 解码查询参数, `%`后需跟两位十六进制数, `+`解码为空格
+fn percent_decode(val: &str) -> NetResult<String> {
+    let bytes = val.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' => {
+                let hex = unwrap_or!(
+                    bytes.get(i + 1..i + 3),
+                    return Err(NetError::BadUrl("invalid percent encoding"))
+                );
+                if !hex.iter().all(u8::is_ascii_hexdigit) {
+                    return Err(NetError::BadUrl("invalid percent encoding"));
+                }
+                let hex = std::str::from_utf8(hex).unwrap_or("");
+                out.push(u8::from_str_radix(hex, 16).unwrap_or(0));
+                i += 3;
+                continue;
+            }
+            b'+' => out.push(b' '),
+            b => out.push(b),
+        }
+        i += 1;
+    }
+    Ok(String::from_utf8_lossy(&out).to_string())
+}
+
+/// 编码查询参数, 除字母数字及`-._~`外均编码为`%XX`
+fn percent_encode(val: &str) -> String {
+    let mut out = String::with_capacity(val.len());
+    for &b in val.as_bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                out.push(b as char)
+            }
+            _ => out.push_str(&format!("%{b:02X}")),
+        }
+    }
+    out
+}
+
+impl NetUrl {
+    pub fn parse(url: &str) -> NetResult<NetUrl> {
+        let (scheme, rest) = unwrap_or!(
+            url.split_once("://"),
+            return Err(NetError::BadUrl("miss scheme"))
+        );
+        let scheme = scheme.to_ascii_lowercase();
+        let (rest, query) = match rest.split_once('?') {
+            Some((rest, query)) => (rest, query),
+            None => (rest, ""),
+        };
+        let query = query
+            .split('&')
+            .filter(|s| !s.is_empty())
+            .map(|s| match s.split_once('=') {
+                Some((k, v)) => Ok((percent_decode(k)?, percent_decode(v)?)),
+                None => Ok((percent_decode(s)?, String::new())),
+            })
+            .collect::<NetResult<Vec<_>>>()?;
+        let (authority, path) = if scheme == "unix" {
+            (String::new(), percent_decode(rest)?)
+        } else {
+            match rest.find('/') {
+                Some(idx) => (rest[..idx].to_string(), rest[idx..].to_string()),
+                None => (rest.to_string(), String::new()),
+            }
+        };
+        if scheme == "unix" && path.is_empty() {
+            return Err(NetError::BadUrl("miss unix path"));
+        }
+        if scheme != "unix" && authority.is_empty() {
+            return Err(NetError::BadUrl("miss host"));
+        }
+        Ok(NetUrl {
+            scheme,
+            authority,
+            path,
+            query,
+        })
+    }
+
+    /// 不带端口的主机名
+    pub fn host(&self) -> &str {
+        if self.authority.starts_with('[') {
+            if let Some(idx) = self.authority.find(']') {
+                return &self.authority[1..idx];
+            }
+        }
+        match self.authority.rsplit_once(':') {
+            Some((host, _)) => host,
+            None => &self.authority,
+        }
+    }
+
+    /// 将query中的配置项写入settings, 返回未被识别的参数
+    pub fn apply_settings(&self, settings: &mut Settings) -> NetResult<Vec<(String, String)>> {
+        let mut unknown = vec![];
+        for (k, v) in &self.query {
+            if !settings.set_value(k, v)? {
+                unknown.push((k.clone(), v.clone()));
+            }
+        }
+        Ok(unknown)
+    }
+
+    /// 去除配置项后重新组装的url, 用于websocket的握手
+    pub fn to_ws_url(&self, unknown: &[(String, String)]) -> String {
+        let mut url = format!("{}://{}{}", self.scheme, self.authority, self.path);
+        if !unknown.is_empty() {
+            let query = unknown
+                .iter()
+                .map(|(k, v)| {
+                    if v.is_empty() {
+                        percent_encode(k)
+                    } else {
+                        format!("{}={}", percent_encode(k), percent_encode(v))
+                    }
+                })
+                .collect::<Vec<String>>()
+                .join("&");
+            url.push('?');
+            url.push_str(&query);
+        }
+        url
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::NetUrl;
+
+    #[test]
+    fn ws_url_round_trip() {
+        let url = NetUrl::parse(
+            "ws://example.com:80/ws?token=a%2Bb%26c%3Dd&name=x+y&k%20v=%E4%BD%A0&flag",
+        )
+        .unwrap();
+        assert_eq!(
+            url.query,
+            vec![
+                ("token".to_string(), "a+b&c=d".to_string()),
+                ("name".to_string(), "x y".to_string()),
+                ("k v".to_string(), "你".to_string()),
+                ("flag".to_string(), String::new()),
+            ]
+        );
+        let ws = url.to_ws_url(&url.query);
+        assert_eq!(
+            ws,
+            "ws://example.com:80/ws?token=a%2Bb%26c%3Dd&name=x%20y&k%20v=%E4%BD%A0&flag"
+        );
+        let again = NetUrl::parse(&ws).unwrap();
+        assert_eq!(again.query, url.query);
+        assert_eq!(again.to_ws_url(&again.query), ws);
+    }
+
+    #[test]
+    fn reject_malformed_escape() {
+        for url in [
+            "tcp://a:1?k=%+1",
+            "tcp://a:1?k=%4",
+            "tcp://a:1?k=%zz",
+            "tcp://a:1?%-1=v",
+        ] {
+            assert!(NetUrl::parse(url).is_err(), "{url}");
+        }
+        assert!(NetUrl::parse("tcp://a:1?k=%41").is_ok());
+    }
+}
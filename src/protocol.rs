@@ -60,6 +60,9 @@ pub enum CloseCode {
     /// to a different IP (when multiple targets exist), or reconnect to the same IP
     /// when a user has performed an action.
     Again,
+    /// Indicates that the connection was closed because the peer stopped
+    /// answering heartbeat pings. This is a private-use code.
+    Heartbeat,
     #[doc(hidden)]
     Tls,
     #[doc(hidden)]
@@ -85,6 +88,7 @@ impl Into<u16> for CloseCode {
             Restart => 1012,
             Again => 1013,
             Tls => 1015,
+            Heartbeat => 4000,
             Empty => 0,
             Other(code) => code,
         }
@@ -108,6 +112,7 @@ impl From<u16> for CloseCode {
             1012 => Restart,
             1013 => Again,
             1015 => Tls,
+            4000 => Heartbeat,
             0 => Empty,
             _ => Other(code),
         }
@@ -0,0 +1,153 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+use crate::{NetError, NetResult};
+
+/// PROXY protocol v2的签名
+const V2_SIGNATURE: [u8; 12] = *b"\r\n\r\n\0\r\nQUIT\n";
+/// v1头的最大长度, 包含结尾的\r\n
+const V1_MAX_LEN: usize = 107;
+
+/// 负载均衡通过PROXY protocol(v1或v2)传递的连接信息
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ProxyHeader {
+    /// 协议版本, 1或2
+    pub version: u8,
+    /// 真实的客户端地址, 负载均衡自身的连接(LOCAL/UNKNOWN)或unix地址时为None
+    pub source: Option<SocketAddr>,
+    /// 客户端连接的目标地址
+    pub destination: Option<SocketAddr>,
+    /// v2头中附带的TLV, 按(类型, 值)的顺序保存
+    pub tlvs: Vec<(u8, Vec<u8>)>,
+}
+
+impl ProxyHeader {
+    /// 指定类型的TLV值, 如0x01为ALPN, 0x02为authority
+    pub fn tlv(&self, kind: u8) -> Option<&[u8]> {
+        self.tlvs
+            .iter()
+            .find(|(k, _)| *k == kind)
+            .map(|(_, v)| &v[..])
+    }
+
+    /// 从连接中读取头, 仅读取头本身的数据, 后续数据保留给tls或websocket处理
+    pub(crate) async fn read_from<R: AsyncRead + Unpin>(reader: &mut R) -> NetResult<ProxyHeader> {
+        // v1的最短头`PROXY UNKNOWN\r\n`也超过12字节
+        let mut head = [0u8; 12];
+        reader.read_exact(&mut head).await?;
+        if head == V2_SIGNATURE {
+            return Self::read_v2(reader).await;
+        }
+        if !head.starts_with(b"PROXY ") {
+            return Err(NetError::BadProxyHeader("missing signature"));
+        }
+        let mut line = head.to_vec();
+        while !line.ends_with(b"\r\n") {
+            if line.len() >= V1_MAX_LEN {
+                return Err(NetError::BadProxyHeader("v1 header too long"));
+            }
+            line.push(reader.read_u8().await?);
+        }
+        Self::parse_v1(&line[..line.len() - 2])
+    }
+
+    fn parse_v1(line: &[u8]) -> NetResult<ProxyHeader> {
+        let err = || NetError::BadProxyHeader("invalid v1 header");
+        let line = std::str::from_utf8(line).map_err(|_| err())?;
+        let parts = line.split(' ').collect::<Vec<&str>>();
+        match parts.get(1) {
+            Some(&"UNKNOWN") => Ok(ProxyHeader {
+                version: 1,
+                ..Default::default()
+            }),
+            Some(&proto @ ("TCP4" | "TCP6")) if parts.len() == 6 => {
+                let src = parts[2].parse::<IpAddr>().map_err(|_| err())?;
+                let dst = parts[3].parse::<IpAddr>().map_err(|_| err())?;
+                let sport = parts[4].parse::<u16>().map_err(|_| err())?;
+                let dport = parts[5].parse::<u16>().map_err(|_| err())?;
+                let v4 = proto == "TCP4";
+                if src.is_ipv4() != v4 || dst.is_ipv4() != v4 {
+                    return Err(err());
+                }
+                Ok(ProxyHeader {
+                    version: 1,
+                    source: Some(SocketAddr::new(src, sport)),
+                    destination: Some(SocketAddr::new(dst, dport)),
+                    tlvs: vec![],
+                })
+            }
+            _ => Err(err()),
+        }
+    }
+
+    async fn read_v2<R: AsyncRead + Unpin>(reader: &mut R) -> NetResult<ProxyHeader> {
+        let mut head = [0u8; 4];
+        reader.read_exact(&mut head).await?;
+        let (ver_cmd, family) = (head[0], head[1]);
+        if ver_cmd >> 4 != 2 {
+            return Err(NetError::BadProxyHeader("unsupported version"));
+        }
+        let len = u16::from_be_bytes([head[2], head[3]]) as usize;
+        let mut data = vec![0u8; len];
+        reader.read_exact(&mut data).await?;
+
+        let mut header = ProxyHeader {
+            version: 2,
+            ..Default::default()
+        };
+        match ver_cmd & 0x0F {
+            // LOCAL为负载均衡自身的连接, 如健康检查, 忽略地址信息
+            0x00 => return Ok(header),
+            0x01 => {}
+            _ => return Err(NetError::BadProxyHeader("unsupported command")),
+        }
+        let addr_len = match family >> 4 {
+            0x0 => 0,
+            0x1 => 12,
+            0x2 => 36,
+            0x3 => 216,
+            _ => return Err(NetError::BadProxyHeader("unsupported address family")),
+        };
+        if data.len() < addr_len {
+            return Err(NetError::BadProxyHeader("address too short"));
+        }
+        let (addr, rest) = data.split_at(addr_len);
+        let port = |i: usize| u16::from_be_bytes([addr[i], addr[i + 1]]);
+        match family >> 4 {
+            0x1 => {
+                let src = Ipv4Addr::new(addr[0], addr[1], addr[2], addr[3]);
+                let dst = Ipv4Addr::new(addr[4], addr[5], addr[6], addr[7]);
+                header.source = Some(SocketAddr::new(src.into(), port(8)));
+                header.destination = Some(SocketAddr::new(dst.into(), port(10)));
+            }
+            0x2 => {
+                let mut src = [0u8; 16];
+                let mut dst = [0u8; 16];
+                src.copy_from_slice(&addr[..16]);
+                dst.copy_from_slice(&addr[16..32]);
+                header.source = Some(SocketAddr::new(Ipv6Addr::from(src).into(), port(32)));
+                header.destination = Some(SocketAddr::new(Ipv6Addr::from(dst).into(), port(34)));
+            }
+            _ => {}
+        }
+        header.tlvs = Self::parse_tlvs(rest)?;
+        Ok(header)
+    }
+
+    fn parse_tlvs(mut data: &[u8]) -> NetResult<Vec<(u8, Vec<u8>)>> {
+        let mut tlvs = vec![];
+        while !data.is_empty() {
+            if data.len() < 3 {
+                return Err(NetError::BadProxyHeader("invalid tlv"));
+            }
+            let len = u16::from_be_bytes([data[1], data[2]]) as usize;
+            if data.len() < 3 + len {
+                return Err(NetError::BadProxyHeader("invalid tlv"));
+            }
+            tlvs.push((data[0], data[3..3 + len].to_vec()));
+            data = &data[3 + len..];
+        }
+        Ok(tlvs)
+    }
+}
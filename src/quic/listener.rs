@@ -0,0 +1,39 @@
+use std::net::SocketAddr;
+
+use quinn::{Endpoint, Incoming};
+
+use crate::{Settings, TlsReloader};
+
+use super::{NetError, NetResult};
+
+pub struct WrapQuicListener {
+    pub endpoint: Endpoint,
+    pub server_id: u64,
+    pub next_connection_id: u32,
+    pub reloader: TlsReloader,
+}
+
+impl WrapQuicListener {
+    pub fn new(server_id: u64, endpoint: Endpoint, settings: &Settings) -> Self {
+        Self {
+            reloader: TlsReloader::quic(endpoint.clone(), settings),
+            endpoint,
+            server_id: server_id << 32,
+            next_connection_id: 0,
+        }
+    }
+
+    pub async fn accept(&mut self) -> NetResult<(Incoming, SocketAddr, u64)> {
+        let incoming = unwrap_or!(
+            self.endpoint.accept().await,
+            return Err(NetError::Extension("quic endpoint closed"))
+        );
+        let addr = incoming.remote_address();
+        self.next_connection_id = self.next_connection_id.wrapping_add(1);
+        Ok((
+            incoming,
+            addr,
+            self.server_id + self.next_connection_id as u64,
+        ))
+    }
+}
@@ -0,0 +1,622 @@
+use algorithm::buf::{BinaryMut, Bt, BtMut};
+use log::warn;
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt, ReadBuf},
+    net::{lookup_host, ToSocketAddrs},
+    time,
+};
+
+use quinn::{
+    crypto::rustls::{HandshakeData, QuicClientConfig, QuicServerConfig},
+    ClientConfig, Connection, Endpoint, Incoming, RecvStream, SendStream, ServerConfig, VarInt,
+};
+use tokio_rustls::rustls::pki_types::CertificateDer;
+
+mod listener;
+mod state;
+use listener::WrapQuicListener;
+pub use state::QuicState;
+
+use crate::{heartbeat::Heartbeat, id_center::IdCenter, tls::TlsConfig, NetConn, NetReceiver};
+
+use super::{online_count::OnlineCount, CloseCode, NetError, Settings, TlsInfo, TlsReloader};
+
+use super::{
+    codec::{encode_with_codec, Codec, CodecType},
+    handler::Handler,
+    message::Message,
+    NetResult,
+};
+
+/// quic握手时协商的协议名
+static QUIC_ALPN: &[u8] = b"hcnet";
+
+/// 单个quic连接上两个方向的单向流, 所有的消息均在流上传输
+/// 发送流在首次写入时打开, 接收流在对端首次写入后接受, 任意一方均可先发送数据
+struct QuicStream {
+    conn: Connection,
+    send: Option<SendStream>,
+    recv: Option<RecvStream>,
+}
+
+impl QuicStream {
+    fn new(conn: Connection) -> Self {
+        QuicStream {
+            conn,
+            send: None,
+            recv: None,
+        }
+    }
+}
+
+/// 读取接收流, 接收流未建立时等待
+async fn read_recv(recv: Option<&mut RecvStream>, buf: &mut ReadBuf<'_>) -> std::io::Result<()> {
+    match recv {
+        Some(recv) => AsyncReadExt::read_buf(recv, buf).await.map(|_| ()),
+        None => std::future::pending().await,
+    }
+}
+
+/// 写入发送流, 发送流未建立时等待
+async fn write_send(send: Option<&mut SendStream>, data: &[u8]) -> std::io::Result<usize> {
+    match send {
+        Some(send) => AsyncWriteExt::write(send, data).await,
+        None => std::future::pending().await,
+    }
+}
+
+enum Quic {
+    Stream(QuicStream),
+    Listener(WrapQuicListener),
+    Incoming(Incoming),
+    Unconnect(Vec<SocketAddr>),
+    Uninit,
+}
+
+impl Quic {
+    pub async fn try_accept(&mut self) -> NetResult<()> {
+        match std::mem::replace(self, Quic::Uninit) {
+            Quic::Incoming(incoming) => {
+                let conn = incoming.await?;
+                *self = Quic::Stream(QuicStream::new(conn));
+                Ok(())
+            }
+            quic => {
+                *self = quic;
+                Ok(())
+            }
+        }
+    }
+}
+
+/// quic的封装, 复用tls的证书配置, 消息格式与tcp一致
+pub struct QuicConn {
+    quic: Quic,
+    settings: Settings,
+    codec: Box<dyn Codec>,
+    id: u64,
+    state: QuicState,
+    addr: Option<SocketAddr>,
+    read: BinaryMut,
+    write: BinaryMut,
+    count: OnlineCount,
+}
+
+enum QuicReceiver {
+    Accept(QuicConn),
+    Read(Message),
+    Next,
+}
+
+unsafe impl Sync for QuicConn {}
+unsafe impl Send for QuicConn {}
+
+impl Default for QuicConn {
+    fn default() -> Self {
+        Self {
+            quic: Quic::Uninit,
+            addr: None,
+            id: 0,
+            state: QuicState::Open,
+            settings: Settings::default(),
+            codec: CodecType::default().build(),
+            read: BinaryMut::new(),
+            write: BinaryMut::new(),
+            count: OnlineCount::default(),
+        }
+    }
+}
+
+impl QuicConn {
+    pub fn new(addrs: Vec<SocketAddr>) -> QuicConn {
+        QuicConn {
+            quic: Quic::Unconnect(addrs),
+            ..Default::default()
+        }
+    }
+
+    /// 服务端的配置, 必须配置证书的公钥及私钥
+    pub(crate) fn server_config(settings: &Settings) -> NetResult<ServerConfig> {
+        let mut config = unwrap_or!(
+            TlsConfig::server_config(settings)?,
+            return Err(NetError::Extension("quic must config cert and key"))
+        );
+        config.alpn_protocols = TlsConfig::alpn(settings, &[QUIC_ALPN]);
+        let config = QuicServerConfig::try_from(config)
+            .map_err(|_| NetError::Extension("quic no initial cipher suite"))?;
+        Ok(ServerConfig::with_crypto(Arc::new(config)))
+    }
+
+    /// 客户端的配置, 信任的根证书与tls一致, 自签名证书可配置在ca_files中
+    fn client_config(settings: &Settings) -> NetResult<ClientConfig> {
+        let mut config = TlsConfig::client_config(settings)?;
+        config.alpn_protocols = TlsConfig::alpn(settings, &[QUIC_ALPN]);
+        let config = QuicClientConfig::try_from(config)
+            .map_err(|_| NetError::Extension("quic no initial cipher suite"))?;
+        Ok(ClientConfig::new(Arc::new(config)))
+    }
+
+    pub async fn bind_with_endpoint(endpoint: Endpoint, settings: Settings) -> NetResult<QuicConn> {
+        let id = IdCenter::next_connect_id();
+        Ok(QuicConn {
+            quic: Quic::Listener(WrapQuicListener::new(id, endpoint, &settings)),
+            id,
+            settings,
+            count: OnlineCount::new(),
+            ..Default::default()
+        })
+    }
+
+    pub async fn bind<A: ToSocketAddrs>(addr: A, settings: Settings) -> NetResult<QuicConn> {
+        let addr = unwrap_or!(
+            lookup_host(addr).await?.next(),
+            return Err(NetError::Extension("quic bind address is empty"))
+        );
+        let endpoint = Endpoint::server(Self::server_config(&settings)?, addr)?;
+        Self::bind_with_endpoint(endpoint, settings).await
+    }
+
+    pub async fn connect<A: ToSocketAddrs>(addr: A) -> NetResult<QuicConn> {
+        Self::connect_with_settings(addr, Settings::default()).await
+    }
+
+    pub async fn connect_with_settings<A: ToSocketAddrs>(
+        addr: A,
+        settings: Settings,
+    ) -> NetResult<QuicConn> {
+        let addr = unwrap_or!(
+            lookup_host(addr).await?.next(),
+            return Err(NetError::Extension("quic connect address is empty"))
+        );
+        let bind: SocketAddr = if addr.is_ipv6() {
+            "[::]:0".parse().unwrap()
+        } else {
+            "0.0.0.0:0".parse().unwrap()
+        };
+        let mut endpoint = Endpoint::client(bind)?;
+        endpoint.set_default_client_config(Self::client_config(&settings)?);
+        let domain = settings
+            .domain
+            .clone()
+            .unwrap_or_else(|| "localhost".to_string());
+        let connecting = endpoint.connect(addr, &domain)?;
+        match tokio::time::timeout(
+            Duration::from_millis(settings.connect_timeout as u64),
+            connecting,
+        )
+        .await
+        {
+            Ok(v) => {
+                let conn = v?;
+                Ok(QuicConn {
+                    quic: Quic::Stream(QuicStream::new(conn)),
+                    id: IdCenter::next_connect_id(),
+                    addr: Some(addr),
+                    codec: settings.build_codec(),
+                    settings,
+                    ..Default::default()
+                })
+            }
+            Err(_) => Err(NetError::Timeout),
+        }
+    }
+
+    pub async fn connect_with_timeout<A: ToSocketAddrs>(
+        addr: A,
+        timeout: Duration,
+    ) -> NetResult<QuicConn> {
+        let settings = Settings {
+            connect_timeout: timeout.as_millis() as usize,
+            ..Settings::default()
+        };
+        Self::connect_with_settings(addr, settings).await
+    }
+
+    async fn process(&mut self) -> NetResult<QuicReceiver> {
+        match &mut self.quic {
+            Quic::Listener(listener) => {
+                let (incoming, addr, id) = listener.accept().await?;
+                let now = self.count.now();
+                if now >= self.settings.max_connections {
+                    warn!(
+                        "当前连接数:{now}, 超出最大连接数: {}, 故关闭连接",
+                        self.settings.max_connections
+                    );
+                    incoming.refuse();
+                    return Ok(QuicReceiver::Next);
+                }
+                Ok(QuicReceiver::Accept(QuicConn {
+                    quic: Quic::Incoming(incoming),
+                    addr: Some(addr),
+                    id,
+                    count: self.count.add(),
+                    codec: self.settings.build_codec(),
+                    settings: self.settings.clone(),
+                    ..Default::default()
+                }))
+            }
+            Quic::Stream(stream) => {
+                match &self.state {
+                    QuicState::Open => loop {
+                        if let Some(v) = self.codec.decode(&mut self.read, &self.settings)? {
+                            return Ok(QuicReceiver::Read(v));
+                        }
+
+                        if self.read.len() >= self.settings.in_buffer_max {
+                            return Err(NetError::OverInbufferSize);
+                        }
+                        let mut buf = ReadBuf::uninit(self.read.chunk_mut());
+                        tokio::select! {
+                            val = read_recv(stream.recv.as_mut(), &mut buf) => {
+                                val?;
+                                let s = buf.filled().len();
+                                unsafe {
+                                    self.read.advance_mut(s);
+                                }
+                                if s == 0 {
+                                    return Ok(QuicReceiver::Read(Message::Shutdown));
+                                }
+                            }
+                            r = stream.conn.accept_uni(), if stream.recv.is_none() => {
+                                stream.recv = Some(r?);
+                            }
+                            // 一旦有写数据，则尝试写入数据，写入成功后扣除相应的数据
+                            r = write_send(stream.send.as_mut(), self.write.chunk()), if self.write.has_remaining() => {
+                                let n = r?;
+                                self.write.advance(n);
+                                if !self.write.has_remaining() {
+                                    self.write.clear();
+                                }
+                            }
+                            r = stream.conn.open_uni(), if stream.send.is_none() && self.write.has_remaining() => {
+                                stream.send = Some(r?);
+                            }
+                            _ = tokio::time::sleep(Duration::from_millis(self.settings.read_timeout as u64)) => {
+                                return Err(NetError::ReadTimeout);
+                            }
+                        }
+                    },
+                    QuicState::Closing((code, reason)) => {
+                        let (code, reason) = (*code, reason.clone());
+                        let util_time = time::Instant::now()
+                            + Duration::from_millis(self.settings.closing_time as u64);
+                        if stream.send.is_none() {
+                            if let Ok(send) =
+                                time::timeout_at(util_time, stream.conn.open_uni()).await
+                            {
+                                stream.send = Some(send?);
+                            }
+                        }
+                        if let Some(send) = &mut stream.send {
+                            while self.write.has_remaining() {
+                                tokio::select! {
+                                    // 一旦有写数据，则尝试写入数据，写入成功后扣除相应的数据
+                                    r = AsyncWriteExt::write(send, self.write.chunk()) => {
+                                        let n = r?;
+                                        self.write.advance(n);
+                                    }
+                                    _ = time::sleep_until(util_time) => break,
+                                }
+                            }
+                            // 等待对端确认数据后再关闭连接, 避免数据丢失
+                            let _ = send.finish();
+                            let _ = time::timeout_at(util_time, send.stopped()).await;
+                        }
+                        self.write.clear();
+                        stream.conn.close(
+                            VarInt::from_u32(Into::<u16>::into(code) as u32),
+                            reason.as_bytes(),
+                        );
+                        self.state = QuicState::Closed;
+                        Ok(QuicReceiver::Read(Message::Shutdown))
+                    }
+                    QuicState::Closed => Ok(QuicReceiver::Read(Message::Shutdown)),
+                }
+            }
+            Quic::Unconnect(addr) => {
+                let conn = Self::connect_with_settings(&addr[..], self.settings.clone()).await?;
+                self.addr = conn.addr;
+                self.quic = conn.quic;
+                Ok(QuicReceiver::Next)
+            }
+            _ => {
+                let pend = std::future::pending();
+                let () = pend.await;
+                unreachable!()
+            }
+        }
+    }
+
+    pub fn remote_addr(&self) -> Option<SocketAddr> {
+        self.addr
+    }
+
+    pub(crate) fn close(&mut self, code: CloseCode, reason: String) -> NetResult<()> {
+        encode_with_codec(
+            &mut *self.codec,
+            &mut self.write,
+            Message::Close(code, reason.clone()),
+            &self.settings,
+        )?;
+        self.state = QuicState::Closing((code, reason));
+        Ok(())
+    }
+
+    pub(crate) async fn inner_run_with_handler<H>(
+        &mut self,
+        handler: &mut H,
+        receiver: &mut NetReceiver,
+    ) -> NetResult<()>
+    where
+        H: Handler + 'static + Sync + Send,
+    {
+        self.quic.try_accept().await?;
+        if let Some(info) = self.tls_info() {
+            handler.on_tls_handshake(info).await?;
+        }
+        if let Some(certs) = self.peer_certificates() {
+            handler.on_peer_certificates(certs).await?;
+        }
+        handler.on_open().await?;
+        let is_listen = self.is_listen();
+        let mut heartbeat = Heartbeat::new(&self.settings, self.codec.has_control());
+        loop {
+            let heartbeat_time = heartbeat.deadline();
+            tokio::select! {
+                ret = self.process() => {
+                    let r = ret?;
+                    match r {
+                        QuicReceiver::Accept(quic) => {
+                            handler.on_accept(NetConn::from(quic)).await?
+                        },
+                        QuicReceiver::Read(msg) => {
+                            match msg {
+                                Message::Text(_) | Message::Binary(_) => handler.on_message(msg).await?,
+                                Message::Close(code, reason) => {
+                                    handler.on_close(code, reason).await;
+                                    return Ok(())
+                                }
+                                Message::Ping(data) => {
+                                    if let Some(ret) = handler.on_ping(data).await? {
+                                        encode_with_codec(&mut *self.codec, &mut self.write, Message::Pong(ret), &self.settings)?;
+                                    }
+                                },
+                                Message::Pong(data) => {
+                                    if let Some(rtt) = heartbeat.on_pong(&data) {
+                                        handler.on_heartbeat(rtt).await?;
+                                    }
+                                    handler.on_pong(data).await?
+                                },
+                                _ => return Ok(()),
+                            }
+                        },
+                        QuicReceiver::Next => continue,
+                    }
+                }
+                c = receiver.recv(), if self.write.len() < self.settings.out_buffer_max => {
+                    if is_listen {
+                        return Ok(())
+                    }
+                    let c = unwrap_or!(c, return Ok(()));
+                    match c.msg {
+                        Message::Close(code, reason) => {
+                            self.close(code, reason)?;
+                            continue;
+                        },
+                        Message::Shutdown => {
+                            self.close(CloseCode::Away, "Shutdown".to_string())?;
+                            continue;
+                        },
+                        _ => {}
+                    }
+                    encode_with_codec(&mut *self.codec, &mut self.write, c.msg, &self.settings)?;
+                }
+                _ = time::sleep_until(heartbeat_time), if heartbeat.is_enable() && !is_listen => {
+                    match heartbeat.on_tick() {
+                        Some(data) => encode_with_codec(&mut *self.codec, &mut self.write, Message::Ping(data), &self.settings)?,
+                        None => {
+                            let reason = "heartbeat timeout".to_string();
+                            handler.on_close(CloseCode::Heartbeat, reason.clone()).await;
+                            self.close(CloseCode::Heartbeat, reason)?;
+                        }
+                    }
+                }
+                r = handler.on_logic() => {
+                    r?;
+                }
+            };
+        }
+    }
+
+    /// 对端的证书链, 仅在对端出示了证书时存在
+    pub fn peer_certificates(&self) -> Option<Vec<CertificateDer<'static>>> {
+        match &self.quic {
+            Quic::Stream(stream) => stream
+                .conn
+                .peer_identity()?
+                .downcast::<Vec<CertificateDer<'static>>>()
+                .ok()
+                .map(|c| *c)
+                .filter(|c| !c.is_empty()),
+            _ => None,
+        }
+    }
+
+    /// 监听的证书热更新句柄
+    pub fn tls_reloader(&self) -> Option<TlsReloader> {
+        match &self.quic {
+            Quic::Listener(listener) => Some(listener.reloader.clone()),
+            _ => None,
+        }
+    }
+
+    /// tls握手协商的信息
+    pub fn tls_info(&self) -> Option<TlsInfo> {
+        match &self.quic {
+            Quic::Stream(stream) => {
+                let data = stream
+                    .conn
+                    .handshake_data()?
+                    .downcast::<HandshakeData>()
+                    .ok()?;
+                Some(TlsInfo {
+                    server_name: data.server_name,
+                    alpn_protocol: data
+                        .protocol
+                        .map(|p| String::from_utf8_lossy(&p).to_string()),
+                })
+            }
+            _ => None,
+        }
+    }
+
+    pub fn get_settings(&mut self) -> &mut Settings {
+        &mut self.settings
+    }
+
+    pub fn set_settings(&mut self, settings: Settings) {
+        self.codec = settings.build_codec();
+        self.settings = settings
+    }
+
+    /// 设置自定义的分包方式, 之后调用set_settings将恢复为配置中的分包方式
+    pub fn set_codec(&mut self, codec: Box<dyn Codec>) {
+        self.codec = codec;
+    }
+
+    pub fn get_connection_id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn is_listen(&self) -> bool {
+        matches!(&self.quic, Quic::Listener(_))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use async_trait::async_trait;
+    use quinn::Endpoint;
+    use tokio::sync::mpsc;
+
+    use super::QuicConn;
+    use crate::{Handler, Message, NetConn, NetResult, NetSender, Settings, TrustRoots};
+
+    /// 服务端在连接建立后先发送消息, 之后回显
+    struct Echo {
+        sender: NetSender,
+    }
+
+    #[async_trait]
+    impl Handler for Echo {
+        async fn on_open(&mut self) -> NetResult<()> {
+            self.sender
+                .send_message(Message::Text("welcome".to_string()))
+        }
+
+        async fn on_message(&mut self, msg: Message) -> NetResult<()> {
+            self.sender.send_message(msg)
+        }
+    }
+
+    struct Server;
+
+    #[async_trait]
+    impl Handler for Server {
+        async fn on_accept(&mut self, conn: NetConn) -> NetResult<()> {
+            let _ = conn.run_handler(|sender| Echo { sender }).await;
+            Ok(())
+        }
+    }
+
+    /// 收到服务端的消息后才发送消息
+    struct Client {
+        sender: NetSender,
+        tx: mpsc::UnboundedSender<Message>,
+    }
+
+    #[async_trait]
+    impl Handler for Client {
+        async fn on_message(&mut self, msg: Message) -> NetResult<()> {
+            if let Message::Text(text) = &msg {
+                if text == "welcome" {
+                    self.sender
+                        .send_message(Message::Text("hello".to_string()))?;
+                }
+            }
+            let _ = self.tx.send(msg);
+            Ok(())
+        }
+    }
+
+    async fn recv_text(rx: &mut mpsc::UnboundedReceiver<Message>) -> String {
+        match tokio::time::timeout(Duration::from_secs(5), rx.recv()).await {
+            Ok(Some(Message::Text(text))) => text,
+            v => panic!("unexpected {v:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn self_signed_loopback() {
+        let key = rcgen::KeyPair::generate().unwrap();
+        let cert = rcgen::CertificateParams::new(vec!["localhost".to_string()])
+            .unwrap()
+            .self_signed(&key)
+            .unwrap();
+        let server = Settings {
+            cert_data: Some(cert.pem().into_bytes()),
+            key_data: Some(key.serialize_pem().into_bytes()),
+            ..Settings::default()
+        };
+        let config = QuicConn::server_config(&server).unwrap();
+        let endpoint = Endpoint::server(config, "127.0.0.1:0".parse().unwrap()).unwrap();
+        let addr = endpoint.local_addr().unwrap();
+        NetConn::quic_bind_with_endpoint(endpoint, server)
+            .await
+            .unwrap()
+            .run_handler(|_| Server)
+            .await
+            .unwrap();
+
+        let ca = std::env::temp_dir().join(format!("hcnet_quic_{}.pem", addr.port()));
+        std::fs::write(&ca, cert.pem()).unwrap();
+        let client = Settings {
+            domain: Some("localhost".to_string()),
+            trust_roots: TrustRoots::Custom,
+            ca_files: vec![ca.to_string_lossy().to_string()],
+            ..Settings::default()
+        };
+        let conn = NetConn::quic_connect_with_settings(addr, client).await;
+        let _ = std::fs::remove_file(&ca);
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        conn.unwrap()
+            .run_handler(|sender| Client { sender, tx })
+            .await
+            .unwrap();
+        assert_eq!(recv_text(&mut rx).await, "welcome");
+        assert_eq!(recv_text(&mut rx).await, "hello");
+    }
+}
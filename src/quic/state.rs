@@ -0,0 +1,7 @@
+use crate::CloseCode;
+
+pub enum QuicState {
+    Open,
+    Closing((CloseCode, String)),
+    Closed,
+}
@@ -0,0 +1,94 @@
+use std::collections::VecDeque;
+
+use crate::CloseCode;
+
+/// 连接断开的原因, 用于判断客户端是否需要重连
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub(crate) enum Disconnect {
+    /// 连接异常断开或者出错
+    #[default]
+    Lost,
+    /// 本地主动关闭
+    Local,
+    /// 收到远端的关闭消息
+    Remote(CloseCode),
+    /// 心跳超时, 对端已失效
+    Heartbeat,
+}
+
+impl Disconnect {
+    /// 主动关闭或远端正常关闭时不重连, 远端重启或过载时重连
+    pub fn should_reconnect(&self) -> bool {
+        match self {
+            Disconnect::Lost | Disconnect::Heartbeat => true,
+            Disconnect::Local => false,
+            Disconnect::Remote(code) => matches!(code, CloseCode::Restart | CloseCode::Again),
+        }
+    }
+}
+
+/// 写缓存中各消息的边界, 重连时丢弃已部分写出的消息, 保留完整未发送的消息
+#[derive(Debug, Default)]
+pub(crate) struct FrameMarks {
+    /// 写缓存中各消息编码后的长度
+    lens: VecDeque<usize>,
+    /// 首个消息已写出的长度
+    sent: usize,
+}
+
+impl FrameMarks {
+    /// 记录新写入写缓存的消息
+    pub fn push(&mut self, len: usize) {
+        if len > 0 {
+            self.lens.push_back(len);
+        }
+    }
+
+    /// 写出了n个字节
+    pub fn advance(&mut self, n: usize) {
+        self.sent += n;
+        while let Some(&len) = self.lens.front() {
+            if self.sent < len {
+                break;
+            }
+            self.sent -= len;
+            self.lens.pop_front();
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.lens.clear();
+        self.sent = 0;
+    }
+
+    /// 取出已部分写出的消息剩余的长度, 该部分需从写缓存中丢弃
+    pub fn take_partial(&mut self) -> usize {
+        if self.sent == 0 {
+            return 0;
+        }
+        let len = self.lens.pop_front().unwrap_or(self.sent);
+        let rest = len - self.sent;
+        self.sent = 0;
+        rest
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FrameMarks;
+
+    #[test]
+    fn frame_marks_partial() {
+        let mut marks = FrameMarks::default();
+        marks.push(5);
+        marks.push(0);
+        marks.push(3);
+        marks.push(4);
+        // 首个消息完整写出, 第二个消息写出1个字节
+        marks.advance(6);
+        assert_eq!(marks.take_partial(), 2);
+        assert_eq!(marks.take_partial(), 0);
+        marks.advance(4);
+        assert_eq!(marks.take_partial(), 0);
+    }
+}
@@ -1,22 +1,251 @@
-use std::usize;
+use std::{
+    sync::Arc,
+    task::{Context, Poll},
+    time::Duration,
+};
 
-use tokio::sync::mpsc::{channel, error::TrySendError};
+use tokio::sync::{
+    mpsc::{
+        channel,
+        error::{SendTimeoutError, TryRecvError, TrySendError},
+        unbounded_channel, OwnedPermit, Receiver, Sender, UnboundedReceiver, UnboundedSender,
+    },
+    OwnedSemaphorePermit, Semaphore, TryAcquireError,
+};
 
-use super::{CloseCode, Message, NetError, NetResult};
+use super::{CloseCode, Message, NetError, NetResult, QueueType, Settings};
 
+/// 发送队列中的消息, 含有内部字段, 需通过Command::new构造
 #[derive(Debug)]
 pub struct Command {
     pub msg: Message,
+    /// 字节限制的队列中该消息所占用的额度, 仅持有不读取, 消息取出后随Command释放
+    #[allow(dead_code)]
+    pub(crate) permit: Option<OwnedSemaphorePermit>,
 }
 
-pub type NetReceiver = tokio::sync::mpsc::Receiver<Command>;
+impl Command {
+    pub fn new(msg: Message) -> Self {
+        Self { msg, permit: None }
+    }
+}
+
+#[derive(Debug)]
+enum ChannelReceiver {
+    Bounded(Receiver<Command>),
+    Unbounded(UnboundedReceiver<Command>),
+}
+
+/// 发送队列的接收端, 与tokio的mpsc接收端的接口一致, 可由tokio的接收端转换
+#[derive(Debug)]
+pub struct NetReceiver {
+    channel: ChannelReceiver,
+}
+
+impl From<Receiver<Command>> for NetReceiver {
+    fn from(rv: Receiver<Command>) -> Self {
+        NetReceiver {
+            channel: ChannelReceiver::Bounded(rv),
+        }
+    }
+}
+
+impl From<UnboundedReceiver<Command>> for NetReceiver {
+    fn from(rv: UnboundedReceiver<Command>) -> Self {
+        NetReceiver {
+            channel: ChannelReceiver::Unbounded(rv),
+        }
+    }
+}
+
+impl NetReceiver {
+    pub async fn recv(&mut self) -> Option<Command> {
+        match &mut self.channel {
+            ChannelReceiver::Bounded(rv) => rv.recv().await,
+            ChannelReceiver::Unbounded(rv) => rv.recv().await,
+        }
+    }
+
+    pub async fn recv_many(&mut self, buffer: &mut Vec<Command>, limit: usize) -> usize {
+        match &mut self.channel {
+            ChannelReceiver::Bounded(rv) => rv.recv_many(buffer, limit).await,
+            ChannelReceiver::Unbounded(rv) => rv.recv_many(buffer, limit).await,
+        }
+    }
+
+    pub fn try_recv(&mut self) -> Result<Command, TryRecvError> {
+        match &mut self.channel {
+            ChannelReceiver::Bounded(rv) => rv.try_recv(),
+            ChannelReceiver::Unbounded(rv) => rv.try_recv(),
+        }
+    }
+
+    pub fn blocking_recv(&mut self) -> Option<Command> {
+        match &mut self.channel {
+            ChannelReceiver::Bounded(rv) => rv.blocking_recv(),
+            ChannelReceiver::Unbounded(rv) => rv.blocking_recv(),
+        }
+    }
+
+    pub fn blocking_recv_many(&mut self, buffer: &mut Vec<Command>, limit: usize) -> usize {
+        match &mut self.channel {
+            ChannelReceiver::Bounded(rv) => rv.blocking_recv_many(buffer, limit),
+            ChannelReceiver::Unbounded(rv) => rv.blocking_recv_many(buffer, limit),
+        }
+    }
+
+    pub fn poll_recv(&mut self, cx: &mut Context<'_>) -> Poll<Option<Command>> {
+        match &mut self.channel {
+            ChannelReceiver::Bounded(rv) => rv.poll_recv(cx),
+            ChannelReceiver::Unbounded(rv) => rv.poll_recv(cx),
+        }
+    }
+
+    pub fn poll_recv_many(
+        &mut self,
+        cx: &mut Context<'_>,
+        buffer: &mut Vec<Command>,
+        limit: usize,
+    ) -> Poll<usize> {
+        match &mut self.channel {
+            ChannelReceiver::Bounded(rv) => rv.poll_recv_many(cx, buffer, limit),
+            ChannelReceiver::Unbounded(rv) => rv.poll_recv_many(cx, buffer, limit),
+        }
+    }
+
+    /// 关闭接收端, 已在队列中的消息仍可读取
+    pub fn close(&mut self) {
+        match &mut self.channel {
+            ChannelReceiver::Bounded(rv) => rv.close(),
+            ChannelReceiver::Unbounded(rv) => rv.close(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        match &self.channel {
+            ChannelReceiver::Bounded(rv) => rv.is_empty(),
+            ChannelReceiver::Unbounded(rv) => rv.is_empty(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match &self.channel {
+            ChannelReceiver::Bounded(rv) => rv.len(),
+            ChannelReceiver::Unbounded(rv) => rv.len(),
+        }
+    }
+
+    /// 队列当前可容纳的消息数量, 不限制消息数量时为usize::MAX
+    pub fn capacity(&self) -> usize {
+        match &self.channel {
+            ChannelReceiver::Bounded(rv) => rv.capacity(),
+            ChannelReceiver::Unbounded(_) => usize::MAX,
+        }
+    }
+
+    /// 队列最多可容纳的消息数量, 不限制消息数量时为usize::MAX
+    pub fn max_capacity(&self) -> usize {
+        match &self.channel {
+            ChannelReceiver::Bounded(rv) => rv.max_capacity(),
+            ChannelReceiver::Unbounded(_) => usize::MAX,
+        }
+    }
+
+    pub fn is_closed(&self) -> bool {
+        match &self.channel {
+            ChannelReceiver::Bounded(rv) => rv.is_closed(),
+            ChannelReceiver::Unbounded(rv) => rv.is_closed(),
+        }
+    }
+
+    pub fn sender_strong_count(&self) -> usize {
+        match &self.channel {
+            ChannelReceiver::Bounded(rv) => rv.sender_strong_count(),
+            ChannelReceiver::Unbounded(rv) => rv.sender_strong_count(),
+        }
+    }
+
+    pub fn sender_weak_count(&self) -> usize {
+        match &self.channel {
+            ChannelReceiver::Bounded(rv) => rv.sender_weak_count(),
+            ChannelReceiver::Unbounded(rv) => rv.sender_weak_count(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum ChannelSender {
+    /// 按消息数量限制
+    Bounded(Sender<Command>),
+    /// 不限制
+    Unbounded(UnboundedSender<Command>),
+    /// 按消息的字节数限制
+    Bytes(UnboundedSender<Command>, Arc<Semaphore>, usize),
+}
 
 #[derive(Debug, Clone)]
 pub struct NetSender {
-    channel: tokio::sync::mpsc::Sender<Command>,
+    channel: ChannelSender,
     id: u64,
 }
 
+/// 预留的发送额度, 持有期间队列中的位置不会被其它发送者占用
+#[derive(Debug)]
+pub struct SendPermit {
+    permit: PermitInner,
+}
+
+#[derive(Debug)]
+enum PermitInner {
+    Bounded(OwnedPermit<Command>),
+    Unbounded(UnboundedSender<Command>),
+    /// 字节限制的队列, 持有预留的额度
+    Bytes(UnboundedSender<Command>, OwnedSemaphorePermit, usize),
+}
+
+impl SendPermit {
+    /// 使用预留的额度发送消息
+    /// 字节限制的队列中消息超出预留的额度时需补足差额, 无法立即补足时返回SendFull
+    pub fn send(self, msg: Message) -> NetResult<()> {
+        match self.permit {
+            PermitInner::Bounded(p) => {
+                p.send(Command::new(msg));
+                Ok(())
+            }
+            PermitInner::Unbounded(s) => s
+                .send(Command::new(msg))
+                .map_err(|e| NetError::SendClosed(e.0)),
+            PermitInner::Bytes(s, mut permit, max) => {
+                let size = message_size(&msg).min(max);
+                if size > permit.num_permits() {
+                    let diff = (size - permit.num_permits()) as u32;
+                    match permit.semaphore().clone().try_acquire_many_owned(diff) {
+                        Ok(more) => permit.merge(more),
+                        Err(_) => return Err(NetError::SendFull(Command::new(msg))),
+                    }
+                }
+                s.send(Command {
+                    msg,
+                    permit: Some(permit),
+                })
+                .map_err(|e| NetError::SendClosed(e.0))
+            }
+        }
+    }
+}
+
+/// 消息在字节限制队列中所占用的额度
+fn message_size(msg: &Message) -> usize {
+    match msg {
+        Message::Text(t) => t.len(),
+        Message::Binary(b) | Message::Ping(b) | Message::Pong(b) => b.len(),
+        Message::Close(_, reason) => reason.len() + 2,
+        Message::Prepared(p) => message_size(p.message()),
+        _ => 0,
+    }
+    .max(1)
+}
+
 // unsafe impl Sync for NetSender {}
 // unsafe impl Send for NetSender {}
 
@@ -24,15 +253,184 @@ impl NetSender {
     pub fn new(mut capacity: usize, id: u64) -> (NetSender, NetReceiver) {
         capacity = capacity.min(usize::MAX >> 3);
         let (channel, rv) = channel(capacity);
-        (NetSender { channel, id }, rv)
+        (
+            NetSender {
+                channel: ChannelSender::Bounded(channel),
+                id,
+            },
+            NetReceiver {
+                channel: ChannelReceiver::Bounded(rv),
+            },
+        )
+    }
+
+    /// 不限制队列长度的发送端
+    pub fn unbounded(id: u64) -> (NetSender, NetReceiver) {
+        let (channel, rv) = unbounded_channel();
+        (
+            NetSender {
+                channel: ChannelSender::Unbounded(channel),
+                id,
+            },
+            NetReceiver {
+                channel: ChannelReceiver::Unbounded(rv),
+            },
+        )
+    }
+
+    /// 按队列中消息的总字节数限制的发送端
+    pub fn bytes_bounded(mut max_bytes: usize, id: u64) -> (NetSender, NetReceiver) {
+        max_bytes = max_bytes.clamp(1, Semaphore::MAX_PERMITS.min(u32::MAX as usize));
+        let (channel, rv) = unbounded_channel();
+        (
+            NetSender {
+                channel: ChannelSender::Bytes(
+                    channel,
+                    Arc::new(Semaphore::new(max_bytes)),
+                    max_bytes,
+                ),
+                id,
+            },
+            NetReceiver {
+                channel: ChannelReceiver::Unbounded(rv),
+            },
+        )
+    }
+
+    /// 根据配置的队列类型创建发送端
+    pub fn from_settings(settings: &Settings, id: u64) -> (NetSender, NetReceiver) {
+        match settings.queue_type {
+            QueueType::Bounded => Self::new(settings.queue_size, id),
+            QueueType::Unbounded => Self::unbounded(id),
+            QueueType::Bytes => Self::bytes_bounded(settings.out_buffer_max, id),
+        }
     }
 
     pub fn send_message(&mut self, msg: Message) -> NetResult<()> {
-        match self.channel.try_send(Command { msg }) {
-            Ok(_) => return Ok(()),
-            Err(TrySendError::Full(msg)) => return Err(NetError::SendFull(msg)),
-            Err(TrySendError::Closed(msg)) => return Err(NetError::SendClosed(msg)),
+        match &self.channel {
+            ChannelSender::Bounded(channel) => match channel.try_send(Command::new(msg)) {
+                Ok(_) => return Ok(()),
+                Err(TrySendError::Full(msg)) => return Err(NetError::SendFull(msg)),
+                Err(TrySendError::Closed(msg)) => return Err(NetError::SendClosed(msg)),
+            },
+            ChannelSender::Unbounded(channel) => channel
+                .send(Command::new(msg))
+                .map_err(|e| NetError::SendClosed(e.0)),
+            ChannelSender::Bytes(channel, semaphore, max) => {
+                let size = message_size(&msg).min(*max) as u32;
+                let permit = match semaphore.clone().try_acquire_many_owned(size) {
+                    Ok(permit) => permit,
+                    Err(TryAcquireError::NoPermits) if !channel.is_closed() => {
+                        return Err(NetError::SendFull(Command::new(msg)))
+                    }
+                    Err(_) => return Err(NetError::SendClosed(Command::new(msg))),
+                };
+                channel
+                    .send(Command {
+                        msg,
+                        permit: Some(permit),
+                    })
+                    .map_err(|e| NetError::SendClosed(e.0))
+            }
+        }
+    }
+
+    /// 发送消息, 队列已满时等待直到有空余
+    pub async fn send(&self, msg: Message) -> NetResult<()> {
+        match &self.channel {
+            ChannelSender::Bounded(channel) => channel
+                .send(Command::new(msg))
+                .await
+                .map_err(|e| NetError::SendClosed(e.0)),
+            ChannelSender::Unbounded(channel) => channel
+                .send(Command::new(msg))
+                .map_err(|e| NetError::SendClosed(e.0)),
+            ChannelSender::Bytes(channel, semaphore, max) => {
+                let size = message_size(&msg).min(*max) as u32;
+                let permit = tokio::select! {
+                    permit = semaphore.clone().acquire_many_owned(size) => permit.ok(),
+                    _ = channel.closed() => None,
+                };
+                let permit =
+                    unwrap_or!(permit, return Err(NetError::SendClosed(Command::new(msg))));
+                channel
+                    .send(Command {
+                        msg,
+                        permit: Some(permit),
+                    })
+                    .map_err(|e| NetError::SendClosed(e.0))
+            }
+        }
+    }
+
+    /// 发送消息, 超时后以SendFull返回该消息
+    pub async fn send_timeout(&self, msg: Message, timeout: Duration) -> NetResult<()> {
+        match &self.channel {
+            ChannelSender::Bounded(channel) => {
+                match channel.send_timeout(Command::new(msg), timeout).await {
+                    Ok(_) => Ok(()),
+                    Err(SendTimeoutError::Timeout(msg)) => Err(NetError::SendFull(msg)),
+                    Err(SendTimeoutError::Closed(msg)) => Err(NetError::SendClosed(msg)),
+                }
+            }
+            ChannelSender::Unbounded(_) => self.send(msg).await,
+            ChannelSender::Bytes(channel, semaphore, max) => {
+                let size = message_size(&msg).min(*max) as u32;
+                let permit = tokio::select! {
+                    permit = semaphore.clone().acquire_many_owned(size) => permit.ok(),
+                    _ = channel.closed() => None,
+                    _ = tokio::time::sleep(timeout) => {
+                        return Err(NetError::SendFull(Command::new(msg)))
+                    }
+                };
+                let permit =
+                    unwrap_or!(permit, return Err(NetError::SendClosed(Command::new(msg))));
+                channel
+                    .send(Command {
+                        msg,
+                        permit: Some(permit),
+                    })
+                    .map_err(|e| NetError::SendClosed(e.0))
+            }
+        }
+    }
+
+    /// 预留一个发送位置, 队列已满时等待
+    /// 字节限制的队列中预留一个字节的额度, 发送时补足差额, 可通过reserve_bytes预留实际的大小
+    pub async fn reserve(&self) -> NetResult<SendPermit> {
+        self.reserve_bytes(1).await
+    }
+
+    /// 预留一个发送位置, 字节限制的队列中预留size字节的额度, 队列已满时等待
+    pub async fn reserve_bytes(&self, size: usize) -> NetResult<SendPermit> {
+        let permit = match &self.channel {
+            ChannelSender::Bounded(channel) => PermitInner::Bounded(
+                channel
+                    .clone()
+                    .reserve_owned()
+                    .await
+                    .map_err(|_| NetError::SendClosed(Command::new(Message::Unvaid)))?,
+            ),
+            ChannelSender::Unbounded(channel) => {
+                if channel.is_closed() {
+                    return Err(NetError::SendClosed(Command::new(Message::Unvaid)));
+                }
+                PermitInner::Unbounded(channel.clone())
+            }
+            ChannelSender::Bytes(channel, semaphore, max) => {
+                let size = size.clamp(1, *max) as u32;
+                let permit = tokio::select! {
+                    permit = semaphore.clone().acquire_many_owned(size) => permit.ok(),
+                    _ = channel.closed() => None,
+                };
+                let permit = unwrap_or!(
+                    permit,
+                    return Err(NetError::SendClosed(Command::new(Message::Unvaid)))
+                );
+                PermitInner::Bytes(channel.clone(), permit, *max)
+            }
         };
+        Ok(SendPermit { permit })
     }
 
     pub fn set_connection_id(&mut self, id: u64) {
@@ -49,10 +447,50 @@ impl NetSender {
     }
 
     pub async fn closed(&self) {
-        self.channel.closed().await
+        match &self.channel {
+            ChannelSender::Bounded(channel) => channel.closed().await,
+            ChannelSender::Unbounded(channel) | ChannelSender::Bytes(channel, _, _) => {
+                channel.closed().await
+            }
+        }
     }
 
     pub fn is_closed(&self) -> bool {
-        self.channel.is_closed()
+        match &self.channel {
+            ChannelSender::Bounded(channel) => channel.is_closed(),
+            ChannelSender::Unbounded(channel) | ChannelSender::Bytes(channel, _, _) => {
+                channel.is_closed()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::NetSender;
+    use crate::{Message, NetError};
+
+    #[tokio::test]
+    async fn reserve_charges_message_size() {
+        let (sender, mut receiver) = NetSender::bytes_bounded(8, 1);
+        let permit = sender.reserve().await.unwrap();
+        permit.send(Message::Binary(vec![0; 6])).unwrap();
+        // 队列中已占用6字节, 超出剩余额度的消息无法发送
+        let permit = sender.reserve().await.unwrap();
+        assert!(matches!(
+            permit.send(Message::Binary(vec![0; 4])),
+            Err(NetError::SendFull(_))
+        ));
+        let permit = sender.reserve_bytes(2).await.unwrap();
+        permit.send(Message::Binary(vec![0; 2])).unwrap();
+        assert_eq!(receiver.len(), 2);
+        drop(receiver.try_recv().unwrap());
+        drop(receiver.try_recv().unwrap());
+        assert!(receiver.try_recv().is_err());
+        let permit = sender.reserve_bytes(8).await.unwrap();
+        permit.send(Message::Binary(vec![0; 8])).unwrap();
+        receiver.close();
+        assert!(receiver.recv().await.is_some());
+        assert!(receiver.recv().await.is_none());
     }
 }
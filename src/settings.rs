@@ -1,5 +1,9 @@
+use std::{str::FromStr, time::Duration};
+
 use serde::{Deserialize, Serialize};
 
+use crate::{Codec, CodecType, NetError, NetHub, NetResult, ProxyConfig, TlsError};
+
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(default)]
@@ -10,15 +14,21 @@ pub struct Settings {
     /// 默认队列大小
     /// 默认值: 10
     pub queue_size: usize,
+    /// 发送队列的限制方式, Bytes时以out_buffer_max作为字节上限
+    /// 默认值: Bounded
+    pub queue_type: QueueType,
     /// 读数据的最大容量
     /// 默认值: 1024 * 1024 * 100 = 10M
     pub in_buffer_max: usize,
     /// 写数据的最大容量
     /// 默认值: 1024 * 1024 * 100 = 10M
     pub out_buffer_max: usize,
-    /// 单信息最大的数量
+    /// 单信息最大的数量, 分片的消息以组装后的长度计算
     /// 默认值: 65535
     pub onemsg_max_size: usize,
+    /// 单帧最大的数据长度, 超出时分片发送, 不超过u24所能表示的长度
+    /// 默认值: 65531
+    pub fragment_size: usize,
     /// 最关闭状态下留给写入的最长时间, 单位毫秒
     /// 默认值: 1000ms
     pub closing_time: usize,
@@ -34,12 +44,344 @@ pub struct Settings {
     /// 是否为raw传输，即tcp默认不分包
     /// 默认值: false
     pub is_raw: bool,
+    /// tcp/kcp等流式协议的分包方式, is_raw为true时不分包
+    /// 默认值: Hcnet
+    pub codec: CodecType,
+    /// hcnet分包下消息的压缩方式, 接收端无需配置即可解压
+    /// 默认值: None
+    pub compress: CompressType,
+    /// 小于该长度的消息不压缩
+    /// 默认值: 1024
+    pub compress_threshold: usize,
     /// TLS证书所用域名, 如果有该变量则表示开启
     pub domain: Option<String>,
     /// 证书的公钥文件
     pub cert: Option<String>,
     /// 证书的私钥文件
     pub key: Option<String>,
+    /// 内存中PEM或DER格式的证书, 优先于cert文件
+    #[serde(skip)]
+    pub cert_data: Option<Vec<u8>>,
+    /// 内存中PEM或DER格式的私钥, 优先于key文件
+    #[serde(skip)]
+    pub key_data: Option<Vec<u8>>,
+    /// 加密的PKCS#8私钥的密码
+    pub key_password: Option<String>,
+    /// tcp及ws监听是否在tls握手前解析PROXY protocol v1/v2头, 用于获取负载均衡后的真实地址
+    /// 开启后未发送头或头格式错误的连接将被拒绝
+    /// 默认值: false
+    pub proxy_protocol: bool,
+    /// tcp, ws及kcp连接是否启用tls, quic总是加密不受影响
+    /// 默认值: Auto, 服务端配置了证书即加密, 客户端配置了domain即加密
+    pub tls_mode: TlsMode,
+    /// tls握手时按优先级协商的应用层协议(ALPN)
+    /// 为空时服务端tcp/ws使用http/1.1, quic两端使用hcnet, 客户端tcp/ws不发送
+    pub alpn: Vec<String>,
+    /// 服务端按SNI选择的证书, 未匹配时使用cert及key
+    pub sni_certs: Vec<SniCert>,
+    /// 监听检查证书文件变化的间隔, 单位毫秒, 变化后新的连接将使用新证书, 0表示不检查
+    /// 默认值: 0
+    pub cert_watch_interval: usize,
+    /// 服务端校验客户端证书所用的CA文件, 配置后将要求客户端出示证书
+    pub client_ca: Option<String>,
+    /// 服务端配置了client_ca时, 是否允许客户端不出示证书
+    /// 默认值: false
+    pub client_auth_optional: bool,
+    /// 客户端出示的证书公钥文件, 用于双向认证
+    pub client_cert: Option<String>,
+    /// 客户端出示的证书私钥文件
+    pub client_key: Option<String>,
+    /// 内存中PEM或DER格式的客户端证书, 优先于client_cert文件
+    #[serde(skip)]
+    pub client_cert_data: Option<Vec<u8>>,
+    /// 内存中PEM或DER格式的客户端私钥, 优先于client_key文件
+    #[serde(skip)]
+    pub client_key_data: Option<Vec<u8>>,
+    /// 客户端信任的根证书来源
+    /// 默认值: Webpki
+    pub trust_roots: TrustRoots,
+    /// 客户端额外信任的CA证书文件, 可用于内部CA或自签名证书
+    pub ca_files: Vec<String>,
+    /// 客户端校验的服务端终端证书的sha256指纹, 任一匹配即通过
+    /// 格式为`spki:<hash>`或`cert:<hash>`, hash可为hex或base64
+    pub pins: Vec<String>,
+    /// 客户端不校验服务端的证书链, 仅可用于本地测试
+    /// 默认值: false
+    pub insecure_skip_verify: bool,
+    /// 心跳的间隔时长, 单位毫秒, 0表示不发送心跳
+    /// 默认值: 0
+    pub heartbeat_interval: usize,
+    /// 连续多少次心跳未响应则认为对端已失效, 并以CloseCode::Heartbeat关闭
+    /// 默认值: 3
+    pub heartbeat_miss: usize,
+    /// tcp, tls, ws, wss客户端经由的socks5或http代理, 为空则直接连接
+    /// 目标的域名不在本地解析, 交由代理解析
+    pub proxy: Option<ProxyConfig>,
+    /// 客户端断线重连的策略, 为空则不重连
+    /// 仅对tcp, ws, kcp的客户端生效, 释放NetSender不会停止重连,
+    /// 需通过NetSender::close_with_reason或发送Message::Shutdown主动关闭
+    pub reconnect: Option<ReconnectSettings>,
+    /// websocket的permessage-deflate压缩配置, 为空则不协商压缩
+    pub deflate: Option<DeflateSettings>,
+    /// websocket的子协议, 按优先级排列
+    /// 客户端据此发送Sec-WebSocket-Protocol, 服务端从中选取客户端支持的首个协议
+    pub ws_protocols: Vec<String>,
+    /// websocket客户端握手时附加的请求头, 如Authorization, Cookie, Origin, 同名时覆盖默认的头
+    /// 通过url配置时格式为`ws_header=Name:value`, 可重复配置
+    pub ws_headers: Vec<(String, String)>,
+    /// 连接的注册中心, 配置后通过run_handler运行的连接将自动注册
+    /// 监听者接受的连接将继承该配置
+    #[serde(skip)]
+    pub hub: Option<NetHub>,
+}
+
+/// 发送队列的限制方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum QueueType {
+    /// 按消息数量限制, 上限为queue_size
+    #[default]
+    Bounded,
+    /// 不限制
+    Unbounded,
+    /// 按消息的总字节数限制, 上限为out_buffer_max
+    Bytes,
+}
+
+impl FromStr for QueueType {
+    type Err = NetError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bounded" => Ok(QueueType::Bounded),
+            "unbounded" => Ok(QueueType::Unbounded),
+            "bytes" => Ok(QueueType::Bytes),
+            _ => Err(NetError::BadUrl("invalid settings value")),
+        }
+    }
+}
+
+/// 服务端按SNI选择的证书
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SniCert {
+    /// 匹配的域名, 支持`*.example.com`形式的通配符
+    pub host: String,
+    /// 证书的公钥文件
+    pub cert: String,
+    /// 证书的私钥文件
+    pub key: String,
+    /// 内存中PEM或DER格式的证书, 优先于cert文件
+    #[serde(skip)]
+    pub cert_data: Option<Vec<u8>>,
+    /// 内存中PEM或DER格式的私钥, 优先于key文件
+    #[serde(skip)]
+    pub key_data: Option<Vec<u8>>,
+}
+
+impl FromStr for SniCert {
+    type Err = NetError;
+
+    /// 格式为`host,cert,key`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut iter = s.split(',').map(|s| s.trim());
+        match (iter.next(), iter.next(), iter.next(), iter.next()) {
+            (Some(host), Some(cert), Some(key), None)
+                if !host.is_empty() && !cert.is_empty() && !key.is_empty() =>
+            {
+                Ok(SniCert {
+                    host: host.to_string(),
+                    cert: cert.to_string(),
+                    key: key.to_string(),
+                    cert_data: None,
+                    key_data: None,
+                })
+            }
+            _ => Err(NetError::BadUrl("invalid settings value")),
+        }
+    }
+}
+
+/// tcp, ws及kcp连接的tls启用方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TlsMode {
+    /// 由是否配置证书或domain决定
+    #[default]
+    Auto,
+    /// 不启用tls
+    Off,
+    /// 仅服务端启用tls, 未配置证书时监听失败
+    Server,
+    /// 仅客户端启用tls, 未配置domain时以对端ip校验证书
+    Client,
+    /// 服务端与客户端均启用tls
+    Both,
+}
+
+impl TlsMode {
+    /// 服务端是否加密, Auto时返回None
+    pub fn server(&self) -> Option<bool> {
+        match self {
+            TlsMode::Auto => None,
+            TlsMode::Off | TlsMode::Client => Some(false),
+            TlsMode::Server | TlsMode::Both => Some(true),
+        }
+    }
+
+    /// 客户端是否加密, Auto时返回None
+    pub fn client(&self) -> Option<bool> {
+        match self {
+            TlsMode::Auto => None,
+            TlsMode::Off | TlsMode::Server => Some(false),
+            TlsMode::Client | TlsMode::Both => Some(true),
+        }
+    }
+}
+
+impl FromStr for TlsMode {
+    type Err = NetError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(TlsMode::Auto),
+            "off" => Ok(TlsMode::Off),
+            "server" => Ok(TlsMode::Server),
+            "client" => Ok(TlsMode::Client),
+            "both" => Ok(TlsMode::Both),
+            _ => Err(NetError::BadUrl("invalid settings value")),
+        }
+    }
+}
+
+/// 客户端信任的根证书来源
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TrustRoots {
+    /// 内置的webpki根证书
+    #[default]
+    Webpki,
+    /// 操作系统的根证书
+    System,
+    /// 仅信任ca_files中的证书
+    Custom,
+}
+
+impl FromStr for TrustRoots {
+    type Err = NetError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "webpki" => Ok(TrustRoots::Webpki),
+            "system" => Ok(TrustRoots::System),
+            "custom" => Ok(TrustRoots::Custom),
+            _ => Err(NetError::BadUrl("invalid settings value")),
+        }
+    }
+}
+
+/// hcnet分包下消息的压缩方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CompressType {
+    /// 不压缩
+    #[default]
+    None,
+    /// lz4压缩, 数据前置4字节的原始长度
+    Lz4,
+}
+
+impl FromStr for CompressType {
+    type Err = NetError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(CompressType::None),
+            "lz4" => Ok(CompressType::Lz4),
+            _ => Err(NetError::BadUrl("invalid settings value")),
+        }
+    }
+}
+
+/// websocket的permessage-deflate压缩配置(RFC 7692)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DeflateSettings {
+    /// 服务端每条消息后重置压缩上下文, 降低内存占用但压缩率下降
+    /// 默认值: false
+    pub server_no_context_takeover: bool,
+    /// 客户端每条消息后重置压缩上下文
+    /// 默认值: false
+    pub client_no_context_takeover: bool,
+    /// 服务端压缩的窗口大小, 9-15
+    /// 默认值: 15
+    pub server_max_window_bits: u8,
+    /// 客户端压缩的窗口大小, 9-15
+    /// 默认值: 15
+    pub client_max_window_bits: u8,
+    /// 压缩等级, 0-9
+    /// 默认值: 6
+    pub level: u32,
+    /// 小于该长度的消息不压缩
+    /// 默认值: 128
+    pub threshold: usize,
+}
+
+impl Default for DeflateSettings {
+    fn default() -> Self {
+        Self {
+            server_no_context_takeover: false,
+            client_no_context_takeover: false,
+            server_max_window_bits: 15,
+            client_max_window_bits: 15,
+            level: 6,
+            threshold: 128,
+        }
+    }
+}
+
+/// 客户端断线重连的策略, 采用带随机抖动的指数退避
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ReconnectSettings {
+    /// 连续重连的最大次数, 0表示不限制
+    /// 默认值: 0
+    pub max_attempts: usize,
+    /// 首次重连前的等待时长, 单位毫秒
+    /// 默认值: 500ms
+    pub min_delay: usize,
+    /// 重连等待的最大时长, 单位毫秒
+    /// 默认值: 30000ms
+    pub max_delay: usize,
+    /// 每次失败后等待时长的倍数
+    /// 默认值: 2.0
+    pub factor: f64,
+    /// 是否添加随机抖动, 避免大量客户端同时重连
+    /// 默认值: true
+    pub jitter: bool,
+}
+
+impl Default for ReconnectSettings {
+    fn default() -> Self {
+        Self {
+            max_attempts: 0,
+            min_delay: 500,
+            max_delay: 30000,
+            factor: 2.0,
+            jitter: true,
+        }
+    }
+}
+
+impl ReconnectSettings {
+    /// 第attempt次(从0开始)重连前需要等待的时长
+    pub fn delay(&self, attempt: usize) -> Duration {
+        let exp = self.factor.max(1.0).powi(attempt.min(64) as i32);
+        let mut delay = (self.min_delay as f64 * exp).min(self.max_delay as f64);
+        if self.jitter {
+            delay = delay / 2.0 + delay / 2.0 * rand::random::<f64>();
+        }
+        Duration::from_millis(delay as u64)
+    }
 }
 
 
@@ -48,18 +390,178 @@ impl Default for Settings {
         Self {
             max_connections: 1024,
             queue_size: 10,
+            queue_type: QueueType::Bounded,
             in_buffer_max: 10485760,
             out_buffer_max: 10485760,
             onemsg_max_size: 65535,
+            fragment_size: 65531,
             closing_time: 1000,
             connect_timeout: 30000,
             shake_timeout: 30000,
             read_timeout: 60000,
             is_raw: false,
+            codec: CodecType::Hcnet,
+            compress: CompressType::None,
+            compress_threshold: 1024,
             domain: None,
             cert: None,
             key: None,
+            cert_data: None,
+            key_data: None,
+            key_password: None,
+            proxy_protocol: false,
+            tls_mode: TlsMode::Auto,
+            alpn: vec![],
+            sni_certs: vec![],
+            cert_watch_interval: 0,
+            client_ca: None,
+            client_auth_optional: false,
+            client_cert: None,
+            client_key: None,
+            client_cert_data: None,
+            client_key_data: None,
+            trust_roots: TrustRoots::Webpki,
+            ca_files: vec![],
+            pins: vec![],
+            insecure_skip_verify: false,
+            heartbeat_interval: 0,
+            heartbeat_miss: 3,
+            proxy: None,
+            reconnect: None,
+            deflate: None,
+            ws_protocols: vec![],
+            ws_headers: vec![],
+            hub: None,
         }
     }
 }
 
+fn parse_value<T: FromStr>(value: &str) -> NetResult<T> {
+    value
+        .parse::<T>()
+        .map_err(|_| NetError::BadUrl("invalid settings value"))
+}
+
+fn parse_bool(value: &str) -> NetResult<bool> {
+    match value {
+        "" | "1" | "true" | "on" | "yes" => Ok(true),
+        "0" | "false" | "off" | "no" => Ok(false),
+        _ => Err(NetError::BadUrl("invalid settings value")),
+    }
+}
+
+fn parse_list(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(|s| s.trim())
+        .filter(|s| !s.is_empty())
+        .map(|s| s.to_string())
+        .collect()
+}
+
+impl Settings {
+    /// 根据配置创建分包方式
+    pub fn build_codec(&self) -> Box<dyn Codec> {
+        if self.is_raw {
+            CodecType::Raw.build()
+        } else {
+            self.codec.build()
+        }
+    }
+
+    /// 客户端是否发起tls握手
+    pub fn is_client_tls(&self) -> bool {
+        self.tls_mode.client().unwrap_or(self.domain.is_some())
+    }
+
+    /// 按url的协议确定tls_mode, Auto时改为对应的模式, 显式配置的模式不一致时报错
+    pub(crate) fn require_tls(&mut self, server: bool, tls: bool) -> NetResult<()> {
+        let current = if server {
+            self.tls_mode.server()
+        } else {
+            self.tls_mode.client()
+        };
+        match current {
+            None => {
+                self.tls_mode = match (server, tls) {
+                    (_, false) => TlsMode::Off,
+                    (true, true) => TlsMode::Server,
+                    (false, true) => TlsMode::Client,
+                };
+                Ok(())
+            }
+            Some(v) if v == tls => Ok(()),
+            Some(_) => Err(TlsError::ModeMismatch.into()),
+        }
+    }
+
+    /// 根据字段名设置对应的值, 字段名不存在时返回false
+    pub fn set_value(&mut self, key: &str, value: &str) -> NetResult<bool> {
+        match key {
+            "max_connections" => self.max_connections = parse_value(value)?,
+            "queue_size" => self.queue_size = parse_value(value)?,
+            "queue_type" => self.queue_type = parse_value(value)?,
+            "in_buffer_max" => self.in_buffer_max = parse_value(value)?,
+            "out_buffer_max" => self.out_buffer_max = parse_value(value)?,
+            "onemsg_max_size" => self.onemsg_max_size = parse_value(value)?,
+            "fragment_size" => self.fragment_size = parse_value(value)?,
+            "closing_time" => self.closing_time = parse_value(value)?,
+            "connect_timeout" => self.connect_timeout = parse_value(value)?,
+            "shake_timeout" => self.shake_timeout = parse_value(value)?,
+            "read_timeout" => self.read_timeout = parse_value(value)?,
+            "is_raw" => self.is_raw = parse_bool(value)?,
+            "codec" => self.codec = parse_value(value)?,
+            "compress" => self.compress = parse_value(value)?,
+            "compress_threshold" => self.compress_threshold = parse_value(value)?,
+            "domain" => self.domain = Some(value.to_string()),
+            "cert" => self.cert = Some(value.to_string()),
+            "key" => self.key = Some(value.to_string()),
+            "key_password" => self.key_password = Some(value.to_string()),
+            "proxy_protocol" => self.proxy_protocol = parse_bool(value)?,
+            "tls_mode" => self.tls_mode = parse_value(value)?,
+            "alpn" => self.alpn = parse_list(value),
+            "sni_cert" => self.sni_certs.push(parse_value(value)?),
+            "cert_watch_interval" => self.cert_watch_interval = parse_value(value)?,
+            "client_ca" => self.client_ca = Some(value.to_string()),
+            "client_auth_optional" => self.client_auth_optional = parse_bool(value)?,
+            "client_cert" => self.client_cert = Some(value.to_string()),
+            "client_key" => self.client_key = Some(value.to_string()),
+            "trust_roots" => self.trust_roots = parse_value(value)?,
+            "ca_files" => self.ca_files = parse_list(value),
+            "pins" => self.pins = parse_list(value),
+            "insecure_skip_verify" => self.insecure_skip_verify = parse_bool(value)?,
+            "heartbeat_interval" => self.heartbeat_interval = parse_value(value)?,
+            "heartbeat_miss" => self.heartbeat_miss = parse_value(value)?,
+            "proxy" => self.proxy = Some(parse_value(value)?),
+            "reconnect" => {
+                self.reconnect = if parse_bool(value)? {
+                    Some(self.reconnect.take().unwrap_or_default())
+                } else {
+                    None
+                }
+            }
+            "deflate" => {
+                self.deflate = if parse_bool(value)? {
+                    Some(self.deflate.take().unwrap_or_default())
+                } else {
+                    None
+                }
+            }
+            "ws_protocols" => self.ws_protocols = parse_list(value),
+            "ws_header" => {
+                let (name, value) = value
+                    .split_once(':')
+                    .ok_or(NetError::BadUrl("invalid settings value"))?;
+                self.ws_headers
+                    .push((name.trim().to_string(), value.trim().to_string()));
+            }
+            "reconnect_max_attempts" => {
+                self.reconnect
+                    .get_or_insert_with(Default::default)
+                    .max_attempts = parse_value(value)?
+            }
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+}
@@ -11,21 +11,23 @@ use tokio::{
 };
 use tokio_rustls::{
     client::TlsStream,
-    rustls::{self, ClientConfig, RootCertStore},
+    rustls::{self, pki_types::CertificateDer},
     server::TlsStream as TlsServer,
     TlsAcceptor, TlsConnector,
 };
 
-use super::NetResult;
+use crate::tls::{to_owned_certs, TlsConfig, TlsInfo};
 
-/// 当前可能是tcp也可能是tcps的连接
-pub enum MaybeTlsStream {
+use super::{NetResult, ProxyHeader, Settings};
+
+/// 当前可能是明文也可能是tls加密的连接, 默认为tcp, kcp也复用该结构
+pub enum MaybeTlsStream<S = TcpStream> {
     /// 普通的socket
-    Stream(TcpStream),
+    Stream(S),
     /// tls客户端
-    TlsStream(TlsStream<TcpStream>),
+    TlsStream(TlsStream<S>),
     /// tls服务端
-    TlsServer(TlsServer<TcpStream>),
+    TlsServer(TlsServer<S>),
 }
 
 impl From<TcpStream> for MaybeTlsStream {
@@ -34,27 +36,30 @@ impl From<TcpStream> for MaybeTlsStream {
     }
 }
 
-impl From<TlsStream<TcpStream>> for MaybeTlsStream {
-    fn from(inner: TlsStream<TcpStream>) -> Self {
+impl<S> From<TlsStream<S>> for MaybeTlsStream<S> {
+    fn from(inner: TlsStream<S>) -> Self {
         MaybeTlsStream::TlsStream(inner)
     }
 }
 
-impl From<TlsServer<TcpStream>> for MaybeTlsStream {
-    fn from(inner: TlsServer<TcpStream>) -> Self {
+impl<S> From<TlsServer<S>> for MaybeTlsStream<S> {
+    fn from(inner: TlsServer<S>) -> Self {
         MaybeTlsStream::TlsServer(inner)
     }
 }
 
-impl MaybeTlsStream {
-    pub async fn connect_tls(stream: TcpStream, domain: String) -> NetResult<MaybeTlsStream> {
-        let mut roots = RootCertStore::empty();
-        roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+impl<S: AsyncRead + AsyncWrite + Unpin> MaybeTlsStream<S> {
+    pub async fn connect_tls(stream: S, domain: String) -> NetResult<MaybeTlsStream<S>> {
+        Self::connect_tls_with_settings(stream, domain, &Settings::default()).await
+    }
 
-        let config = ClientConfig::builder()
-            .with_root_certificates(roots)
-            .with_no_client_auth();
-        // config.alpn_protocols = self.inner.get_alpn_protocol();
+    /// 根据配置发起tls连接, 可出示客户端证书
+    pub async fn connect_tls_with_settings(
+        stream: S,
+        domain: String,
+        settings: &Settings,
+    ) -> NetResult<MaybeTlsStream<S>> {
+        let config = TlsConfig::client_config(settings)?;
         let tls_client = Arc::new(config);
         let connector = TlsConnector::from(tls_client);
 
@@ -66,13 +71,42 @@ impl MaybeTlsStream {
         Ok(MaybeTlsStream::from(outbound))
     }
 
-    pub async fn accept_tls(accept: &TlsAcceptor, stream: TcpStream) -> NetResult<MaybeTlsStream> {
+    pub async fn accept_tls(accept: &TlsAcceptor, stream: S) -> NetResult<MaybeTlsStream<S>> {
         let stream = accept.accept(stream).await?;
         Ok(MaybeTlsStream::from(stream))
     }
+
+    /// 对端的证书链, 仅在tls连接且对端出示了证书时存在
+    pub fn peer_certificates(&self) -> Option<Vec<CertificateDer<'static>>> {
+        match self {
+            MaybeTlsStream::Stream(_) => None,
+            MaybeTlsStream::TlsStream(s) => to_owned_certs(s.get_ref().1.peer_certificates()),
+            MaybeTlsStream::TlsServer(s) => to_owned_certs(s.get_ref().1.peer_certificates()),
+        }
+    }
+
+    /// tls握手协商的信息, 非tls连接时为None
+    pub fn tls_info(&self) -> Option<TlsInfo> {
+        match self {
+            MaybeTlsStream::Stream(_) => None,
+            MaybeTlsStream::TlsStream(s) => Some(TlsInfo {
+                server_name: None,
+                alpn_protocol: alpn_string(s.get_ref().1.alpn_protocol()),
+            }),
+            MaybeTlsStream::TlsServer(s) => Some(TlsInfo {
+                server_name: s.get_ref().1.server_name().map(|s| s.to_string()),
+                alpn_protocol: alpn_string(s.get_ref().1.alpn_protocol()),
+            }),
+        }
+    }
 }
 
-impl AsyncRead for MaybeTlsStream {
+/// 协商的ALPN协议
+fn alpn_string(protocol: Option<&[u8]>) -> Option<String> {
+    protocol.map(|p| String::from_utf8_lossy(p).to_string())
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncRead for MaybeTlsStream<S> {
     #[inline]
     fn poll_read(
         self: Pin<&mut Self>,
@@ -87,7 +121,7 @@ impl AsyncRead for MaybeTlsStream {
     }
 }
 
-impl AsyncWrite for MaybeTlsStream {
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncWrite for MaybeTlsStream<S> {
     #[inline]
     fn poll_write(
         self: Pin<&mut Self>,
@@ -118,18 +152,46 @@ impl AsyncWrite for MaybeTlsStream {
     }
 }
 
+/// tls握手的记录类型
+const TLS_HANDSHAKE: u8 = 0x16;
+
 pub struct MaybeAcceptStream {
     stream: TcpStream,
     accepter: Option<Arc<TlsAcceptor>>,
+    /// 仅在首字节为tls握手时进行tls握手, 用于单端口监听
+    detect_tls: bool,
 }
 
 impl MaybeAcceptStream {
     pub fn new(stream: TcpStream, accepter: Option<Arc<TlsAcceptor>>) -> Self {
-        MaybeAcceptStream { stream, accepter }
+        MaybeAcceptStream {
+            stream,
+            accepter,
+            detect_tls: false,
+        }
+    }
+
+    /// 同时接受加密及不加密的连接, 根据首字节判断是否进行tls握手
+    pub(crate) fn detect_tls(mut self) -> Self {
+        self.detect_tls = true;
+        self
+    }
+
+    /// 在tls握手前读取PROXY protocol头
+    pub(crate) async fn read_proxy_header(&mut self) -> NetResult<ProxyHeader> {
+        ProxyHeader::read_from(&mut self.stream).await
     }
 
     pub async fn accept(self) -> NetResult<MaybeTlsStream> {
-        if let Some(accepter) = self.accepter {
+        let mut accepter = self.accepter;
+        if self.detect_tls && accepter.is_some() {
+            let mut first = [0u8; 1];
+            let n = self.stream.peek(&mut first).await?;
+            if n != 1 || first[0] != TLS_HANDSHAKE {
+                accepter = None;
+            }
+        }
+        if let Some(accepter) = accepter {
             let stream = accepter.accept(self.stream).await?;
             Ok(MaybeTlsStream::from(stream))
         } else {
@@ -137,3 +199,78 @@ impl MaybeAcceptStream {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use rcgen::{CertificateParams, KeyPair};
+    use tokio::io::{duplex, DuplexStream};
+
+    use super::MaybeTlsStream;
+    use crate::{NetResult, Settings, TlsReloader};
+
+    /// 以给定的ALPN列表完成握手, 返回客户端及服务端的流
+    async fn handshake(
+        server_alpn: &[&str],
+        client_alpn: &[&str],
+    ) -> (
+        NetResult<MaybeTlsStream<DuplexStream>>,
+        NetResult<MaybeTlsStream<DuplexStream>>,
+    ) {
+        let key = KeyPair::generate().unwrap();
+        let cert = CertificateParams::new(vec!["alpn.test".to_string()])
+            .unwrap()
+            .self_signed(&key)
+            .unwrap();
+        let server = Settings {
+            cert_data: Some(cert.pem().into_bytes()),
+            key_data: Some(key.serialize_pem().into_bytes()),
+            alpn: server_alpn.iter().map(|p| p.to_string()).collect(),
+            ..Settings::default()
+        };
+        let client = Settings {
+            insecure_skip_verify: true,
+            alpn: client_alpn.iter().map(|p| p.to_string()).collect(),
+            ..Settings::default()
+        };
+        let acceptor = TlsReloader::acceptor(&server).unwrap().current().unwrap();
+        let (a, b) = duplex(16384);
+        tokio::join!(
+            MaybeTlsStream::connect_tls_with_settings(a, "alpn.test".to_string(), &client),
+            MaybeTlsStream::accept_tls(&acceptor, b)
+        )
+    }
+
+    #[tokio::test]
+    async fn alpn_negotiated() {
+        let (client, server) = handshake(&["hcnet", "http/1.1"], &["http/1.1", "hcnet"]).await;
+        let (client, server) = (client.unwrap(), server.unwrap());
+        // 按服务端的顺序优先选择
+        let info = client.tls_info().unwrap();
+        assert_eq!(info.alpn_protocol.as_deref(), Some("hcnet"));
+        let info = server.tls_info().unwrap();
+        assert_eq!(info.alpn_protocol.as_deref(), Some("hcnet"));
+        assert_eq!(info.server_name.as_deref(), Some("alpn.test"));
+    }
+
+    #[tokio::test]
+    async fn alpn_default_and_none() {
+        let (client, server) = handshake(&[], &["http/1.1"]).await;
+        let info = client.unwrap().tls_info().unwrap();
+        assert_eq!(info.alpn_protocol.as_deref(), Some("http/1.1"));
+        let info = server.unwrap().tls_info().unwrap();
+        assert_eq!(info.alpn_protocol.as_deref(), Some("http/1.1"));
+
+        // 客户端未提供ALPN时不协商
+        let (client, server) = handshake(&["hcnet"], &[]).await;
+        assert_eq!(client.unwrap().tls_info().unwrap().alpn_protocol, None);
+        assert_eq!(server.unwrap().tls_info().unwrap().alpn_protocol, None);
+    }
+
+    #[tokio::test]
+    async fn alpn_no_overlap() {
+        // 双方均提供了ALPN但无交集时服务端拒绝握手
+        let (client, server) = handshake(&["hcnet"], &["h2"]).await;
+        assert!(client.is_err());
+        assert!(server.is_err());
+    }
+}
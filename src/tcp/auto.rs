@@ -0,0 +1,238 @@
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+
+use algorithm::buf::BinaryMut;
+use log::warn;
+use tokio::{
+    io::AsyncReadExt,
+    net::TcpStream,
+    sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
+};
+use tokio_rustls::TlsAcceptor;
+
+use crate::{
+    online_count::OnlineCount,
+    stream::MaybeAcceptStream,
+    ws::{WsConn, WsServer},
+    NetConn, NetError, NetResult, Settings, TcpAcceptServer, WrapListener,
+};
+
+use super::{Tcp, TcpConn};
+
+/// websocket升级请求的起始
+const HTTP_GET: &[u8] = b"GET ";
+
+/// 单端口同时接受tcp, ws及tls的监听, 根据连接的首个数据识别协议
+/// 识别在后台进行, 完成后通过通道交给监听者, 不阻塞新的连接
+pub(crate) struct AutoListener {
+    pub listener: WrapListener,
+    sender: UnboundedSender<NetConn>,
+    receiver: UnboundedReceiver<NetConn>,
+}
+
+impl AutoListener {
+    pub fn new(listener: WrapListener) -> Self {
+        let (sender, receiver) = unbounded_channel();
+        Self {
+            listener,
+            sender,
+            receiver,
+        }
+    }
+
+    /// 接受新的连接或返回已识别完成的连接, 接受新连接时返回None
+    pub async fn accept(
+        &mut self,
+        count: &OnlineCount,
+        settings: &Settings,
+    ) -> NetResult<Option<NetConn>> {
+        tokio::select! {
+            r = self.listener.accept() => {
+                let (stream, addr, id, accepter) = r?;
+                let now = count.now();
+                if now >= settings.max_connections {
+                    warn!(
+                        "当前连接数:{now}, 超出最大连接数: {}, 故关闭连接",
+                        settings.max_connections
+                    );
+                    return Ok(None);
+                }
+                let count = count.add();
+                let sender = self.sender.clone();
+                let timeout = Duration::from_millis(settings.shake_timeout as u64);
+                let settings = settings.clone();
+                tokio::spawn(async move {
+                    let sniff = sniff(stream, addr, id, accepter, count, settings);
+                    match tokio::time::timeout(timeout, sniff).await {
+                        Ok(Ok(conn)) => {
+                            let _ = sender.send(conn);
+                        }
+                        Ok(Err(e)) => warn!("识别连接{}的协议失败:{:?}", addr, e),
+                        Err(_) => warn!("识别连接{}的协议超时", addr),
+                    }
+                });
+                Ok(None)
+            }
+            // 自身持有sender, 通道不会关闭
+            conn = self.receiver.recv() => Ok(conn),
+        }
+    }
+}
+
+/// 首字节为tls握手且配置了证书时先完成tls握手, 再按是否以`GET `开头区分ws及tcp
+/// 已读取的数据作为连接的读缓存, 不会丢失, 识别出的连接继承监听的配置
+async fn sniff(
+    stream: TcpStream,
+    addr: SocketAddr,
+    id: u64,
+    accepter: Option<Arc<TlsAcceptor>>,
+    count: OnlineCount,
+    settings: Settings,
+) -> NetResult<NetConn> {
+    let stream = MaybeAcceptStream::new(stream, accepter).detect_tls();
+    let mut server =
+        TcpAcceptServer::new(stream, addr).with_proxy_protocol(settings.proxy_protocol);
+    let (mut stream, addr, proxy) = server.accept().await?;
+
+    // 仅读取至可判断的长度, 剩余的数据由对应的协议自行读取
+    let mut data = [0u8; HTTP_GET.len()];
+    let mut len = 0;
+    while len < data.len() && HTTP_GET.starts_with(&data[..len]) {
+        let n = stream.read(&mut data[len..]).await?;
+        if n == 0 {
+            return Err(NetError::Extension("closed before protocol detected"));
+        }
+        len += n;
+    }
+    let mut read = BinaryMut::new();
+    read.put_slice(&data[..len]);
+
+    if data[..len] == *HTTP_GET {
+        let server = WsServer::new_with_read(stream, addr, read);
+        Ok(NetConn::Ws(WsConn::from_server(
+            server, id, count, proxy, settings,
+        )))
+    } else {
+        Ok(NetConn::Tcp(TcpConn {
+            tcp: Tcp::Stream(stream),
+            addr: Some(addr),
+            id,
+            read,
+            count,
+            proxy,
+            codec: settings.build_codec(),
+            settings,
+            ..Default::default()
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::SocketAddr, sync::Arc};
+
+    use algorithm::buf::Bt;
+    use tokio::{
+        io::AsyncWriteExt,
+        net::{TcpListener, TcpStream},
+    };
+    use tokio_rustls::{rustls::pki_types::ServerName, TlsConnector};
+
+    use super::super::{TcpConn, TcpReceiver};
+    use crate::{tls::TlsConfig, NetConn, Settings, TrustRoots};
+
+    /// 单端口监听, in_buffer_max用于确认识别出的连接继承了监听的配置
+    async fn bind(settings: Settings) -> (TcpConn, SocketAddr) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let settings = Settings {
+            in_buffer_max: 12345,
+            shake_timeout: 2000,
+            ..settings
+        };
+        let conn = TcpConn::auto_bind_with_listener(listener, settings)
+            .await
+            .unwrap();
+        (conn, addr)
+    }
+
+    async fn accept(listener: &mut TcpConn) -> NetConn {
+        loop {
+            if let TcpReceiver::AcceptConn(conn) = listener.process().await.unwrap() {
+                return conn;
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn sniff_binary() {
+        let (mut listener, addr) = bind(Settings::default()).await;
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        client.write_all(&[0, 0, 5, 1, b'h']).await.unwrap();
+        match accept(&mut listener).await {
+            NetConn::Tcp(mut tcp) => {
+                assert_eq!(tcp.get_settings().in_buffer_max, 12345);
+                assert!(tcp.tls_info().is_none());
+                // 识别时读取的数据保留在读缓存中
+                assert_eq!(tcp.read.chunk()[0], 0);
+            }
+            _ => panic!("expect tcp"),
+        }
+    }
+
+    #[tokio::test]
+    async fn sniff_http_get() {
+        let (mut listener, addr) = bind(Settings::default()).await;
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        client
+            .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .await
+            .unwrap();
+        match accept(&mut listener).await {
+            NetConn::Ws(mut ws) => {
+                assert_eq!(ws.get_settings().in_buffer_max, 12345);
+            }
+            _ => panic!("expect ws"),
+        }
+    }
+
+    #[tokio::test]
+    async fn sniff_tls_client_hello() {
+        let key = rcgen::KeyPair::generate().unwrap();
+        let cert = rcgen::CertificateParams::new(vec!["localhost".to_string()])
+            .unwrap()
+            .self_signed(&key)
+            .unwrap();
+        let (mut listener, addr) = bind(Settings {
+            cert_data: Some(cert.pem().into_bytes()),
+            key_data: Some(key.serialize_pem().into_bytes()),
+            ..Settings::default()
+        })
+        .await;
+
+        let ca = std::env::temp_dir().join(format!("hcnet_auto_{}.pem", addr.port()));
+        std::fs::write(&ca, cert.pem()).unwrap();
+        let client = Settings {
+            trust_roots: TrustRoots::Custom,
+            ca_files: vec![ca.to_string_lossy().to_string()],
+            ..Settings::default()
+        };
+        let connector = TlsConnector::from(Arc::new(TlsConfig::client_config(&client).unwrap()));
+        let _ = std::fs::remove_file(&ca);
+        let client = tokio::spawn(async move {
+            let stream = TcpStream::connect(addr).await.unwrap();
+            let name = ServerName::try_from("localhost").unwrap();
+            let mut stream = connector.connect(name, stream).await.unwrap();
+            stream.write_all(&[0, 0, 5, 1, b'h']).await.unwrap();
+            stream
+        });
+        match accept(&mut listener).await {
+            NetConn::Tcp(mut tcp) => {
+                assert_eq!(tcp.get_settings().in_buffer_max, 12345);
+                let info = tcp.tls_info().unwrap();
+                assert_eq!(info.server_name.as_deref(), Some("localhost"));
+            }
+            _ => panic!("expect tls tcp"),
+        }
+        drop(client.await.unwrap());
+    }
+}
@@ -6,36 +6,60 @@ use tokio::{
     net::{TcpListener, TcpStream, ToSocketAddrs},
     time,
 };
+use tokio_rustls::rustls::pki_types::CertificateDer;
 
+mod auto;
 mod state;
+use auto::AutoListener;
 pub use state::TcpState;
 
-use crate::{id_center::IdCenter, NetConn, NetReceiver};
+use crate::{
+    heartbeat::Heartbeat,
+    id_center::IdCenter,
+    reconnect::{Disconnect, FrameMarks},
+    NetConn, NetReceiver, Target, ToTarget,
+};
 
 use super::{
-    decode_message, encode_message, online_count::OnlineCount, stream::MaybeAcceptStream,
-    CloseCode, MaybeTlsStream, NetError, Settings, TcpAcceptServer, WrapListener,
+    online_count::OnlineCount, stream::MaybeAcceptStream, CloseCode, MaybeTlsStream, NetError,
+    ProxyHeader, Settings, TcpAcceptServer, TlsInfo, TlsReloader, WrapListener,
 };
 
-use super::{handler::Handler, message::Message, NetResult, NetSender};
+use super::{
+    codec::{encode_with_codec, Codec, CodecType},
+    handler::Handler,
+    message::Message,
+    NetResult,
+};
 
 enum Tcp {
     Stream(MaybeTlsStream),
     Listener(WrapListener),
+    /// 单端口识别协议的监听
+    Auto(AutoListener),
     AcceptServer(TcpAcceptServer),
     Unconnect(Vec<SocketAddr>),
     Uninit,
 }
 
 impl Tcp {
-    pub async fn try_accept(&mut self) -> NetResult<()> {
+    /// 完成服务端的接受, 返回对端的地址及PROXY protocol头
+    /// PROXY protocol头及tls握手需在timeout内完成, 避免空连接一直占用连接数
+    pub async fn try_accept(
+        &mut self,
+        timeout: Duration,
+    ) -> NetResult<Option<(SocketAddr, Option<ProxyHeader>)>> {
         match self {
             Tcp::AcceptServer(accept_server) => {
-                let (stream, _addr) = accept_server.accept().await?;
+                let (stream, addr, header) =
+                    match time::timeout(timeout, accept_server.accept()).await {
+                        Ok(v) => v?,
+                        Err(_) => return Err(NetError::Timeout),
+                    };
                 *self = Tcp::Stream(MaybeTlsStream::from(stream));
-                Ok(())
+                Ok(Some((addr, header)))
             }
-            _ => Ok(()),
+            _ => Ok(None),
         }
     }
 }
@@ -43,16 +67,26 @@ impl Tcp {
 pub struct TcpConn {
     tcp: Tcp,
     settings: Settings,
+    codec: Box<dyn Codec>,
     id: u64,
     state: TcpState,
     addr: Option<SocketAddr>,
     read: BinaryMut,
     write: BinaryMut,
+    /// 写缓存中各消息的边界
+    marks: FrameMarks,
     count: OnlineCount,
+    /// 客户端连接的目标地址, 用于断线重连
+    target: Option<Target>,
+    disconnect: Disconnect,
+    /// 负载均衡传递的PROXY protocol头
+    proxy: Option<ProxyHeader>,
 }
 
 enum TcpReceiver {
     Accept(TcpConn),
+    /// 单端口监听识别完成的连接, 可能为tcp或ws
+    AcceptConn(NetConn),
     Read(Message),
     Next,
 }
@@ -68,9 +102,14 @@ impl Default for TcpConn {
             id: 0,
             state: TcpState::Open,
             settings: Settings::default(),
+            codec: CodecType::default().build(),
             read: BinaryMut::new(),
             write: BinaryMut::new(),
+            marks: FrameMarks::default(),
             count: OnlineCount::default(),
+            target: None,
+            disconnect: Disconnect::default(),
+            proxy: None,
         }
     }
 }
@@ -78,6 +117,7 @@ impl Default for TcpConn {
 impl TcpConn {
     pub fn new(addrs: Vec<SocketAddr>) -> TcpConn {
         TcpConn {
+            target: Some(Target::Addrs(addrs.clone())),
             tcp: Tcp::Unconnect(addrs),
             ..Default::default()
         }
@@ -93,6 +133,8 @@ impl TcpConn {
             tcp: Tcp::Listener(wrap),
             id,
             count: OnlineCount::new(),
+            codec: settings.build_codec(),
+            settings,
             ..Default::default()
         })
     }
@@ -102,46 +144,91 @@ impl TcpConn {
         Self::bind_with_listener(listener, settings).await
     }
 
+    /// 单端口同时接受tcp, ws及tls的连接, 接受的连接为NetConn::Tcp或NetConn::Ws
+    pub async fn auto_bind_with_listener(
+        listener: TcpListener,
+        settings: Settings,
+    ) -> NetResult<TcpConn> {
+        let id = IdCenter::next_connect_id();
+        let wrap = WrapListener::new(listener, id, settings.domain.clone(), &settings).await?;
+        Ok(TcpConn {
+            tcp: Tcp::Auto(AutoListener::new(wrap)),
+            id,
+            count: OnlineCount::new(),
+            codec: settings.build_codec(),
+            settings,
+            ..Default::default()
+        })
+    }
+
+    pub async fn auto_bind<A: ToSocketAddrs>(addr: A, settings: Settings) -> NetResult<TcpConn> {
+        let listener = TcpListener::bind(addr).await?;
+        Self::auto_bind_with_listener(listener, settings).await
+    }
+
     pub async fn connect_with_stream(stream: TcpStream, settings: Settings) -> NetResult<TcpConn> {
         Ok(TcpConn {
             tcp: Tcp::Stream(MaybeTlsStream::from(stream)),
             id: IdCenter::next_connect_id(),
+            codec: settings.build_codec(),
             settings,
             ..Default::default()
         })
     }
 
-    pub async fn connect<A: ToSocketAddrs>(addr: A) -> NetResult<TcpConn> {
+    pub async fn connect<A: ToTarget>(addr: A) -> NetResult<TcpConn> {
         Self::connect_with_settings(addr, Settings::default()).await
     }
 
-    pub async fn connect_with_settings<A: ToSocketAddrs>(
+    /// 发起连接, 配置了代理时目标的域名交由代理解析
+    pub async fn connect_with_settings<A: ToTarget>(
         addr: A,
         settings: Settings,
     ) -> NetResult<TcpConn> {
+        let target = addr.to_target()?;
+        let connect = async {
+            match &settings.proxy {
+                Some(proxy) => {
+                    let (host, port) = target.host()?;
+                    proxy.connect(&host, port).await
+                }
+                None => Ok(TcpStream::connect(&target.resolve().await?[..]).await?),
+            }
+        };
         match tokio::time::timeout(
             Duration::from_millis(settings.connect_timeout as u64),
-            TcpStream::connect(addr),
+            connect,
         )
         .await
         {
             Ok(v) => {
                 let stream = v?;
-                if settings.domain.is_some() {
+                if settings.is_client_tls() {
+                    // 经由代理时peer_addr为代理的地址, 优先使用目标的域名
+                    let domain = match (&settings.domain, target.domain(), &settings.proxy) {
+                        (Some(domain), _, _) => domain.clone(),
+                        (None, Some(domain), _) => domain.to_string(),
+                        (None, None, Some(_)) => target.host()?.0,
+                        (None, None, None) => stream.peer_addr()?.ip().to_string(),
+                    };
                     let stream =
-                        MaybeTlsStream::connect_tls(stream, settings.domain.clone().unwrap())
+                        MaybeTlsStream::connect_tls_with_settings(stream, domain, &settings)
                             .await?;
                     Ok(TcpConn {
                         tcp: Tcp::Stream(stream),
                         id: IdCenter::next_connect_id(),
+                        codec: settings.build_codec(),
                         settings,
+                        target: Some(target),
                         ..Default::default()
                     })
                 } else {
                     Ok(TcpConn {
                         tcp: Tcp::Stream(MaybeTlsStream::from(stream)),
                         id: IdCenter::next_connect_id(),
+                        codec: settings.build_codec(),
                         settings,
+                        target: Some(target),
                         ..Default::default()
                     })
                 }
@@ -150,13 +237,13 @@ impl TcpConn {
         }
     }
 
-    pub async fn connect_tls<A: ToSocketAddrs>(addr: A, domain: String) -> NetResult<TcpConn> {
+    pub async fn connect_tls<A: ToTarget>(addr: A, domain: String) -> NetResult<TcpConn> {
         let mut settings = Settings::default();
         settings.domain = Some(domain);
         Self::connect_with_settings(addr, settings).await
     }
 
-    pub async fn connect_with_timeout<A: ToSocketAddrs>(
+    pub async fn connect_with_timeout<A: ToTarget>(
         addr: A,
         timeout: Duration,
     ) -> NetResult<TcpConn> {
@@ -165,7 +252,7 @@ impl TcpConn {
         Self::connect_with_settings(addr, settings).await
     }
 
-    pub async fn connect_tls_with_timeout<A: ToSocketAddrs>(
+    pub async fn connect_tls_with_timeout<A: ToTarget>(
         addr: A,
         domain: String,
         timeout: Duration,
@@ -189,22 +276,28 @@ impl TcpConn {
                     return Ok(TcpReceiver::Next);
                 }
                 Ok(TcpReceiver::Accept(TcpConn {
-                    tcp: Tcp::AcceptServer(TcpAcceptServer::new(
-                        MaybeAcceptStream::new(stream, accepter),
-                        addr,
-                    )),
+                    tcp: Tcp::AcceptServer(
+                        TcpAcceptServer::new(MaybeAcceptStream::new(stream, accepter), addr)
+                            .with_proxy_protocol(self.settings.proxy_protocol),
+                    ),
                     addr: Some(addr),
                     id,
                     count: self.count.add(),
+                    codec: self.settings.build_codec(),
+                    settings: self.settings.clone(),
                     ..Default::default()
                 }))
             }
+            Tcp::Auto(listener) => match listener.accept(&self.count, &self.settings).await? {
+                Some(conn) => Ok(TcpReceiver::AcceptConn(conn)),
+                None => Ok(TcpReceiver::Next),
+            },
             Tcp::Stream(stream) => {
                 match &self.state {
                     TcpState::Open => {
                         let (mut reader, mut writer) = split(stream);
                         loop {
-                            if let Some(v) = decode_message(&mut self.read, &self.settings)? {
+                            if let Some(v) = self.codec.decode(&mut self.read, &self.settings)? {
                                 return Ok(TcpReceiver::Read(v));
                             }
 
@@ -227,8 +320,10 @@ impl TcpConn {
                                 r = writer.write(self.write.chunk()), if self.write.has_remaining() => {
                                     let n = r?;
                                     self.write.advance(n);
+                                    self.marks.advance(n);
                                     if !self.write.has_remaining() {
                                         self.write.clear();
+                                        self.marks.clear();
                                     }
                                 }
                                 _ = tokio::time::sleep(Duration::from_millis(self.settings.read_timeout as u64)) => {
@@ -244,8 +339,10 @@ impl TcpConn {
                                 r = stream.write(self.write.chunk()), if self.write.has_remaining() => {
                                     let n = r?;
                                     self.write.advance(n);
+                                    self.marks.advance(n);
                                     if !self.write.has_remaining() {
                                         self.write.clear();
+                                        self.marks.clear();
                                         self.state = TcpState::Closed;
                                         time::sleep(Duration::from_millis(self.settings.closing_time as u64)).await;
                                         return Ok(TcpReceiver::Read(Message::Shutdown));
@@ -282,12 +379,55 @@ impl TcpConn {
         self.addr
     }
 
+    /// 监听开启proxy_protocol时负载均衡传递的头, 在运行后解析
+    pub fn proxy_header(&self) -> Option<&ProxyHeader> {
+        self.proxy.as_ref()
+    }
+
+    pub(crate) fn can_reconnect(&self) -> bool {
+        self.target.is_some() && self.disconnect.should_reconnect()
+    }
+
+    /// 重新连接目标地址, 重置连接状态及读缓存, 写缓存中完整未发送的消息将在重连后发出
+    pub(crate) async fn reconnect(&mut self) -> NetResult<()> {
+        let target = unwrap_or!(
+            self.target.clone(),
+            return Err(NetError::Extension("no target to reconnect"))
+        );
+        let conn = Self::connect_with_settings(target, self.settings.clone()).await?;
+        self.tcp = conn.tcp;
+        self.state = TcpState::Open;
+        self.disconnect = Disconnect::default();
+        self.read.clear();
+        // 丢弃已部分写出的消息, 保留完整未发送的消息
+        let partial = self.marks.take_partial();
+        self.write.advance(partial);
+        if self.codec.codec_type().is_some() {
+            self.codec = conn.codec;
+        }
+        Ok(())
+    }
+
+    /// 服务端接受的连接在shake_timeout内完成PROXY protocol头的解析及tls握手
+    async fn try_accept(&mut self) -> NetResult<()> {
+        let timeout = Duration::from_millis(self.settings.shake_timeout as u64);
+        if let Some((addr, proxy)) = self.tcp.try_accept(timeout).await? {
+            self.addr = Some(addr);
+            self.proxy = proxy;
+        }
+        Ok(())
+    }
+
+    /// 编码消息至写缓存, 并记录消息边界
+    fn encode(&mut self, msg: Message) -> NetResult<()> {
+        let len = self.write.remaining();
+        encode_with_codec(&mut *self.codec, &mut self.write, msg, &self.settings)?;
+        self.marks.push(self.write.remaining() - len);
+        Ok(())
+    }
+
     pub(crate) fn close(&mut self, code: CloseCode, reason: String) -> NetResult<()> {
-        encode_message(
-            &mut self.write,
-            Message::Close(code, reason.clone()),
-            self.settings.is_raw,
-        )?;
+        self.encode(Message::Close(code, reason.clone()))?;
         self.state = TcpState::Closing((code, reason));
         Ok(())
     }
@@ -295,35 +435,55 @@ impl TcpConn {
     pub(crate) async fn inner_run_with_handler<H>(
         &mut self,
         handler: &mut H,
-        mut receiver: NetReceiver,
+        receiver: &mut NetReceiver,
     ) -> NetResult<()>
     where
         H: Handler + 'static + Sync + Send,
     {
-        self.tcp.try_accept().await?;
+        self.try_accept().await?;
+        if let Some(header) = &self.proxy {
+            handler.on_proxy_header(header.clone()).await?;
+        }
+        if let Some(info) = self.tls_info() {
+            handler.on_tls_handshake(info).await?;
+        }
+        if let Some(certs) = self.peer_certificates() {
+            handler.on_peer_certificates(certs).await?;
+        }
         handler.on_open().await?;
         let is_listen = self.is_listen();
+        let mut heartbeat = Heartbeat::new(&self.settings, self.codec.has_control());
         loop {
+            let heartbeat_time = heartbeat.deadline();
             tokio::select! {
                 ret = self.process() => {
                     let r = ret?;
                     match r {
                         TcpReceiver::Accept(tcp) => {
-                            handler.on_accept(NetConn::from((tcp, self.settings.clone()))).await?
+                            handler.on_accept(NetConn::from(tcp)).await?
+                        },
+                        TcpReceiver::AcceptConn(conn) => {
+                            handler.on_accept(conn).await?
                         },
                         TcpReceiver::Read(msg) => {
                             match msg {
                                 Message::Text(_) | Message::Binary(_) => handler.on_message(msg).await?,
                                 Message::Close(code, reason) => {
+                                    self.disconnect = Disconnect::Remote(code);
                                     handler.on_close(code, reason).await;
                                     return Ok(())
                                 }
                                 Message::Ping(data) => {
                                     if let Some(ret) = handler.on_ping(data).await? {
-                                        encode_message(&mut self.write, Message::Pong(ret), self.settings.is_raw)?;
+                                        self.encode(Message::Pong(ret))?;
+                                    }
+                                },
+                                Message::Pong(data) => {
+                                    if let Some(rtt) = heartbeat.on_pong(&data) {
+                                        handler.on_heartbeat(rtt).await?;
                                     }
+                                    handler.on_pong(data).await?
                                 },
-                                Message::Pong(data) => handler.on_pong(data).await?,
                                 _ => return Ok(()),
                             }
                         },
@@ -334,19 +494,35 @@ impl TcpConn {
                     if is_listen {
                         return Ok(())
                     }
-                    let c = unwrap_or!(c, return Ok(()));
+                    let c = unwrap_or!(c, {
+                        self.disconnect = Disconnect::Local;
+                        return Ok(())
+                    });
                     match c.msg {
                         Message::Close(code, reason) => {
+                            self.disconnect = Disconnect::Local;
                             self.close(code, reason)?;
                             continue;
                         },
                         Message::Shutdown => {
+                            self.disconnect = Disconnect::Local;
                             self.close(CloseCode::Away, "Shutdown".to_string())?;
                             continue;
                         },
                         _ => {}
                     }
-                    encode_message(&mut self.write, c.msg, self.settings.is_raw)?;
+                    self.encode(c.msg)?;
+                }
+                _ = time::sleep_until(heartbeat_time), if heartbeat.is_enable() && !is_listen => {
+                    match heartbeat.on_tick() {
+                        Some(data) => self.encode(Message::Ping(data))?,
+                        None => {
+                            let reason = "heartbeat timeout".to_string();
+                            self.disconnect = Disconnect::Heartbeat;
+                            handler.on_close(CloseCode::Heartbeat, reason.clone()).await;
+                            self.close(CloseCode::Heartbeat, reason)?;
+                        }
+                    }
                 }
                 r = handler.on_logic() => {
                     let _ = r?;
@@ -355,21 +531,29 @@ impl TcpConn {
         }
     }
 
-    pub(crate) async fn inner_run_handler<F, H>(&mut self, factory: F) -> NetResult<()>
-    where
-        F: FnOnce(NetSender) -> H + Send + 'static,
-        H: Handler + 'static + Sync + Send,
-    {
-        let (sender, receiver) = NetSender::new(self.settings.queue_size, self.id);
-        let _avoid = sender.clone();
-        let mut handler = factory(sender);
-        if let Err(e) = self.inner_run_with_handler(&mut handler, receiver).await {
-            handler
-                .on_close(CloseCode::Error, "NetError".to_string())
-                .await;
-            return Err(e);
+    /// 对端的证书链, 仅在tls连接且对端出示了证书时存在
+    pub fn peer_certificates(&self) -> Option<Vec<CertificateDer<'static>>> {
+        match &self.tcp {
+            Tcp::Stream(stream) => stream.peer_certificates(),
+            _ => None,
+        }
+    }
+
+    /// 监听的证书热更新句柄, 未配置证书时替换后将以tls接受新的连接
+    pub fn tls_reloader(&self) -> Option<TlsReloader> {
+        match &self.tcp {
+            Tcp::Listener(listener) => Some(listener.accepter.clone()),
+            Tcp::Auto(auto) => Some(auto.listener.accepter.clone()),
+            _ => None,
+        }
+    }
+
+    /// tls握手协商的信息, 仅在tls连接时存在
+    pub fn tls_info(&self) -> Option<TlsInfo> {
+        match &self.tcp {
+            Tcp::Stream(stream) => stream.tls_info(),
+            _ => None,
         }
-        Ok(())
     }
 
     pub fn get_settings(&mut self) -> &mut Settings {
@@ -377,17 +561,113 @@ impl TcpConn {
     }
 
     pub fn set_settings(&mut self, settings: Settings) {
+        self.codec = settings.build_codec();
         self.settings = settings
     }
 
+    /// 设置自定义的分包方式, 之后调用set_settings将恢复为配置中的分包方式
+    pub fn set_codec(&mut self, codec: Box<dyn Codec>) {
+        self.codec = codec;
+    }
+
     pub fn get_connection_id(&self) -> u64 {
         self.id
     }
 
     pub fn is_listen(&self) -> bool {
         match &self.tcp {
-            Tcp::Listener(_) => true,
+            Tcp::Listener(_) | Tcp::Auto(_) => true,
             _ => false,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use tokio::{
+        io::AsyncWriteExt,
+        net::{TcpListener, TcpStream},
+    };
+
+    use super::{TcpConn, TcpReceiver};
+    use crate::{NetConn, NetError, Settings};
+
+    /// 开启proxy_protocol的监听
+    async fn bind_proxy() -> (TcpConn, SocketAddr) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let settings = Settings {
+            proxy_protocol: true,
+            shake_timeout: 500,
+            ..Settings::default()
+        };
+        match NetConn::tcp_bind_with_listener(listener, settings)
+            .await
+            .unwrap()
+        {
+            NetConn::Tcp(tcp) => (tcp, addr),
+            _ => unreachable!(),
+        }
+    }
+
+    async fn accept(listener: &mut TcpConn) -> TcpConn {
+        loop {
+            if let TcpReceiver::Accept(conn) = listener.process().await.unwrap() {
+                return conn;
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn proxy_header_v1() {
+        let (mut listener, addr) = bind_proxy().await;
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        client
+            .write_all(b"PROXY TCP4 192.0.2.1 198.51.100.1 56324 443\r\n")
+            .await
+            .unwrap();
+        let mut conn = accept(&mut listener).await;
+        conn.try_accept().await.unwrap();
+        assert_eq!(conn.remote_addr(), Some("192.0.2.1:56324".parse().unwrap()));
+        let header = conn.proxy_header().unwrap();
+        assert_eq!(header.version, 1);
+        assert_eq!(
+            header.destination,
+            Some("198.51.100.1:443".parse().unwrap())
+        );
+    }
+
+    #[tokio::test]
+    async fn proxy_header_v2() {
+        let (mut listener, addr) = bind_proxy().await;
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        let mut data = b"\r\n\r\n\0\r\nQUIT\n".to_vec();
+        // PROXY命令, TCP over IPv4, 12字节地址加上authority的TLV
+        data.extend_from_slice(&[0x21, 0x11, 0, 12 + 3 + 11]);
+        data.extend_from_slice(&[203, 0, 113, 7, 198, 51, 100, 1]);
+        data.extend_from_slice(&8080u16.to_be_bytes());
+        data.extend_from_slice(&443u16.to_be_bytes());
+        data.extend_from_slice(&[0x02, 0, 11]);
+        data.extend_from_slice(b"example.com");
+        client.write_all(&data).await.unwrap();
+        let mut conn = accept(&mut listener).await;
+        conn.try_accept().await.unwrap();
+        assert_eq!(
+            conn.remote_addr(),
+            Some("203.0.113.7:8080".parse().unwrap())
+        );
+        let header = conn.proxy_header().unwrap();
+        assert_eq!(header.version, 2);
+        assert_eq!(header.tlv(0x02), Some(&b"example.com"[..]));
+    }
+
+    #[tokio::test]
+    async fn proxy_header_timeout() {
+        let (mut listener, addr) = bind_proxy().await;
+        let _client = TcpStream::connect(addr).await.unwrap();
+        let mut conn = accept(&mut listener).await;
+        assert!(matches!(conn.try_accept().await, Err(NetError::Timeout)));
+    }
+}
@@ -0,0 +1,30 @@
+use tokio_rustls::rustls::{self, server::VerifierBuilderError};
+
+/// 证书及私钥加载相关的错误
+#[derive(Debug)]
+pub enum TlsError {
+    /// 数据中未找到证书
+    NoCertificate,
+    /// 证书格式错误
+    BadCertificate,
+    /// 数据中未找到私钥
+    NoPrivateKey,
+    /// 私钥格式错误或不支持
+    BadPrivateKey,
+    /// 私钥已加密, 需配置key_password
+    KeyPasswordRequired,
+    /// 私钥解密失败, 密码错误或加密算法不支持
+    KeyDecrypt,
+    /// 证书指纹格式错误
+    BadPin,
+    /// 未找到系统的根证书
+    NoRootCertificates,
+    /// tls_mode要求服务端加密, 但未配置证书
+    MissingCert,
+    /// tls_mode与url的协议不一致, 如tls_mode为off时连接wss
+    ModeMismatch,
+    /// 证书校验的配置错误
+    Verifier(VerifierBuilderError),
+    /// 证书与私钥不匹配等配置错误
+    Config(rustls::Error),
+}
@@ -0,0 +1,320 @@
+use std::sync::Arc;
+
+use tokio_rustls::rustls::{
+    self,
+    client::WebPkiServerVerifier,
+    crypto::CryptoProvider,
+    pki_types::{CertificateDer, PrivateKeyDer},
+    server::WebPkiClientVerifier,
+    sign::CertifiedKey,
+    ClientConfig, RootCertStore, ServerConfig,
+};
+
+mod error;
+
+mod reload;
+mod resolver;
+mod verifier;
+
+pub use error::TlsError;
+pub use reload::TlsReloader;
+use resolver::SniResolver;
+use verifier::{CertPin, PinnedVerifier};
+
+use crate::{helper::Helper, NetResult, Settings, TrustRoots};
+
+/// tls握手完成后协商的信息
+#[derive(Debug, Clone, Default)]
+pub struct TlsInfo {
+    /// 客户端通过SNI请求的域名, 仅服务端存在
+    pub server_name: Option<String>,
+    /// 通过ALPN协商的应用层协议, 未协商时为None
+    pub alpn_protocol: Option<String>,
+}
+
+/// 根据[`Settings`]构建tls的配置
+pub(crate) struct TlsConfig;
+
+impl TlsConfig {
+    /// 当前使用的加密实现
+    fn provider() -> Arc<CryptoProvider> {
+        CryptoProvider::get_default()
+            .cloned()
+            .unwrap_or_else(|| Arc::new(rustls::crypto::aws_lc_rs::default_provider()))
+    }
+
+    fn certified_key(
+        certs: Vec<CertificateDer<'static>>,
+        key: PrivateKeyDer<'static>,
+        provider: &CryptoProvider,
+    ) -> NetResult<Arc<CertifiedKey>> {
+        let key = provider
+            .key_provider
+            .load_private_key(key)
+            .map_err(TlsError::Config)?;
+        Ok(Arc::new(CertifiedKey::new(certs, key)))
+    }
+
+    /// 服务端的证书链, 内存中的cert_data优先于cert文件
+    pub fn cert_chain(settings: &Settings) -> NetResult<Option<Vec<CertificateDer<'static>>>> {
+        match (&settings.cert_data, &settings.cert) {
+            (Some(data), _) => Ok(Some(Helper::parse_certs(data)?)),
+            (None, Some(path)) => Ok(Some(Helper::load_certs(path)?)),
+            _ => Ok(None),
+        }
+    }
+
+    /// 服务端的默认证书及私钥, 内存中的数据优先于文件
+    fn single_cert(
+        settings: &Settings,
+    ) -> NetResult<Option<(Vec<CertificateDer<'static>>, PrivateKeyDer<'static>)>> {
+        let password = settings.key_password.as_deref();
+        let key = match (&settings.key_data, &settings.key) {
+            (Some(data), _) => Helper::parse_key(data, password)?,
+            (None, Some(path)) => Helper::load_keys(path, password)?,
+            _ => return Ok(None),
+        };
+        let certs = unwrap_or!(Self::cert_chain(settings)?, return Ok(None));
+        Ok(Some((certs, key)))
+    }
+
+    /// 服务端的配置, 未配置证书的公钥及私钥且未配置sni_certs时返回None
+    /// 配置了sni_certs时按SNI选择证书, cert及key作为默认证书
+    /// 配置了client_ca时将校验客户端证书
+    pub fn server_config(settings: &Settings) -> NetResult<Option<ServerConfig>> {
+        let single = Self::single_cert(settings)?;
+        if single.is_none() && settings.sni_certs.is_empty() {
+            return Ok(None);
+        }
+        let builder = ServerConfig::builder();
+        let builder = match &settings.client_ca {
+            Some(ca) => {
+                let mut roots = RootCertStore::empty();
+                for c in Helper::load_certs(ca)? {
+                    roots.add(c).map_err(|e| {
+                        log::warn!("添加客户端CA证书时失败:{:?}", e);
+                        TlsError::BadCertificate
+                    })?;
+                }
+                let verifier = WebPkiClientVerifier::builder(Arc::new(roots));
+                let verifier = if settings.client_auth_optional {
+                    verifier.allow_unauthenticated()
+                } else {
+                    verifier
+                };
+                builder.with_client_cert_verifier(verifier.build().map_err(TlsError::Verifier)?)
+            }
+            None => builder.with_no_client_auth(),
+        };
+        if settings.sni_certs.is_empty() {
+            let (certs, key) = single.unwrap();
+            let config = builder
+                .with_single_cert(certs, key)
+                .map_err(TlsError::Config)?;
+            return Ok(Some(config));
+        }
+        let provider = Self::provider();
+        let default = match single {
+            Some((certs, key)) => Some(Self::certified_key(certs, key, &provider)?),
+            None => None,
+        };
+        let mut resolver = SniResolver::new(default);
+        let password = settings.key_password.as_deref();
+        for sni in &settings.sni_certs {
+            let certs = match &sni.cert_data {
+                Some(data) => Helper::parse_certs(data)?,
+                None => Helper::load_certs(&sni.cert)?,
+            };
+            let key = match &sni.key_data {
+                Some(data) => Helper::parse_key(data, password)?,
+                None => Helper::load_keys(&sni.key, password)?,
+            };
+            resolver.add(&sni.host, Self::certified_key(certs, key, &provider)?);
+        }
+        Ok(Some(builder.with_cert_resolver(Arc::new(resolver))))
+    }
+
+    /// 配置的ALPN协议列表, 未配置时使用default
+    pub fn alpn(settings: &Settings, default: &[&[u8]]) -> Vec<Vec<u8>> {
+        if settings.alpn.is_empty() {
+            default.iter().map(|p| p.to_vec()).collect()
+        } else {
+            settings
+                .alpn
+                .iter()
+                .map(|p| p.as_bytes().to_vec())
+                .collect()
+        }
+    }
+
+    /// 客户端的配置, 信任的根证书由trust_roots及ca_files决定
+    pub fn client_config(settings: &Settings) -> NetResult<ClientConfig> {
+        Self::client_config_with_roots(settings, Self::roots(settings)?)
+    }
+
+    /// 客户端信任的根证书
+    pub fn roots(settings: &Settings) -> NetResult<RootCertStore> {
+        let mut roots = RootCertStore::empty();
+        match settings.trust_roots {
+            TrustRoots::Webpki => roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned()),
+            TrustRoots::System => {
+                let native = rustls_native_certs::load_native_certs();
+                for e in &native.errors {
+                    log::warn!("加载系统根证书时出错:{:?}", e);
+                }
+                let (added, _) = roots.add_parsable_certificates(native.certs);
+                if added == 0 {
+                    return Err(TlsError::NoRootCertificates.into());
+                }
+            }
+            TrustRoots::Custom => {}
+        }
+        for path in &settings.ca_files {
+            for c in Helper::load_certs(path)? {
+                roots.add(c).map_err(|e| {
+                    log::warn!("添加CA证书{}时失败:{:?}", path, e);
+                    TlsError::BadCertificate
+                })?;
+            }
+        }
+        Ok(roots)
+    }
+
+    /// 客户端出示的证书及私钥, 内存中的数据优先于文件
+    fn client_cert(
+        settings: &Settings,
+    ) -> NetResult<Option<(Vec<CertificateDer<'static>>, PrivateKeyDer<'static>)>> {
+        let password = settings.key_password.as_deref();
+        let certs = match (&settings.client_cert_data, &settings.client_cert) {
+            (Some(data), _) => Helper::parse_certs(data)?,
+            (None, Some(path)) => Helper::load_certs(path)?,
+            _ => return Ok(None),
+        };
+        let key = match (&settings.client_key_data, &settings.client_key) {
+            (Some(data), _) => Helper::parse_key(data, password)?,
+            (None, Some(path)) => Helper::load_keys(path, password)?,
+            _ => return Ok(None),
+        };
+        Ok(Some((certs, key)))
+    }
+
+    /// 客户端的配置, 配置了client_cert及client_key时将向服务端出示客户端证书
+    /// 配置了pins或insecure_skip_verify时将使用自定义的证书校验
+    pub fn client_config_with_roots(
+        settings: &Settings,
+        roots: RootCertStore,
+    ) -> NetResult<ClientConfig> {
+        let pins = settings
+            .pins
+            .iter()
+            .map(|p| p.parse::<CertPin>())
+            .collect::<Result<Vec<_>, _>>()?;
+        let builder = if settings.insecure_skip_verify || !pins.is_empty() {
+            let provider = Self::provider();
+            let inner = if settings.insecure_skip_verify {
+                log::warn!("已关闭服务端证书链的校验, 仅可用于本地测试");
+                None
+            } else {
+                let inner =
+                    WebPkiServerVerifier::builder_with_provider(Arc::new(roots), provider.clone())
+                        .build()
+                        .map_err(TlsError::Verifier)?;
+                Some(inner)
+            };
+            ClientConfig::builder()
+                .dangerous()
+                .with_custom_certificate_verifier(Arc::new(PinnedVerifier::new(
+                    inner, pins, provider,
+                )))
+        } else {
+            ClientConfig::builder().with_root_certificates(roots)
+        };
+        let mut config = match Self::client_cert(settings)? {
+            Some((certs, key)) => builder
+                .with_client_auth_cert(certs, key)
+                .map_err(TlsError::Config)?,
+            None => builder.with_no_client_auth(),
+        };
+        config.alpn_protocols = Self::alpn(settings, &[]);
+        Ok(config)
+    }
+}
+
+/// 对端证书链的拷贝
+pub(crate) fn to_owned_certs(
+    certs: Option<&[CertificateDer<'static>]>,
+) -> Option<Vec<CertificateDer<'static>>> {
+    certs.filter(|c| !c.is_empty()).map(|c| c.to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use rcgen::{BasicConstraints, CertificateParams, IsCa, KeyPair};
+    use tokio::io::duplex;
+    use tokio_rustls::{rustls::pki_types::ServerName, TlsAcceptor, TlsConnector};
+
+    use super::TlsConfig;
+    use crate::{Settings, SniCert, TrustRoots};
+
+    /// 内存中的sni证书及客户端证书完成双向认证
+    #[tokio::test]
+    async fn in_memory_sni_and_client_cert() {
+        let server_key = KeyPair::generate().unwrap();
+        let server_cert = CertificateParams::new(vec!["a.test".to_string()])
+            .unwrap()
+            .self_signed(&server_key)
+            .unwrap();
+        let ca_key = KeyPair::generate().unwrap();
+        let mut params = CertificateParams::new(vec![]).unwrap();
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let ca = params.self_signed(&ca_key).unwrap();
+        let client_key = KeyPair::generate().unwrap();
+        let client_cert = CertificateParams::new(vec!["client".to_string()])
+            .unwrap()
+            .signed_by(&client_key, &ca, &ca_key)
+            .unwrap();
+
+        let dir = std::env::temp_dir();
+        let ca_file = dir.join(format!("hcnet_tls_ca_{}.pem", std::process::id()));
+        let root_file = dir.join(format!("hcnet_tls_root_{}.pem", std::process::id()));
+        std::fs::write(&ca_file, ca.pem()).unwrap();
+        std::fs::write(&root_file, server_cert.pem()).unwrap();
+
+        let server = Settings {
+            sni_certs: vec![SniCert {
+                host: "a.test".to_string(),
+                cert: String::new(),
+                key: String::new(),
+                cert_data: Some(server_cert.pem().into_bytes()),
+                key_data: Some(server_key.serialize_pem().into_bytes()),
+            }],
+            client_ca: Some(ca_file.to_string_lossy().to_string()),
+            ..Settings::default()
+        };
+        let client = Settings {
+            trust_roots: TrustRoots::Custom,
+            ca_files: vec![root_file.to_string_lossy().to_string()],
+            client_cert_data: Some(client_cert.pem().into_bytes()),
+            client_key_data: Some(client_key.serialize_pem().into_bytes()),
+            ..Settings::default()
+        };
+        let acceptor = TlsAcceptor::from(Arc::new(
+            TlsConfig::server_config(&server).unwrap().unwrap(),
+        ));
+        let connector = TlsConnector::from(Arc::new(TlsConfig::client_config(&client).unwrap()));
+        let _ = std::fs::remove_file(&ca_file);
+        let _ = std::fs::remove_file(&root_file);
+
+        let (a, b) = duplex(16384);
+        let name = ServerName::try_from("a.test").unwrap();
+        let (server, client) = tokio::join!(acceptor.accept(a), connector.connect(name, b));
+        client.unwrap();
+        let server = server.unwrap();
+        let (_, conn) = server.get_ref();
+        assert_eq!(conn.server_name(), Some("a.test"));
+        let certs = conn.peer_certificates().unwrap();
+        assert_eq!(certs[0].as_ref(), client_cert.der().as_ref());
+    }
+}
@@ -0,0 +1,269 @@
+use std::{
+    fmt::Debug,
+    sync::{Arc, Mutex, RwLock},
+    time::{Duration, SystemTime},
+};
+
+use quinn::Endpoint;
+use tokio::task::JoinHandle;
+use tokio_rustls::TlsAcceptor;
+
+use crate::{quic::QuicConn, NetError, NetResult, Settings};
+
+use super::{TlsConfig, TlsError};
+
+enum Target {
+    /// tcp及ws监听所用的acceptor
+    Acceptor(RwLock<Option<Arc<TlsAcceptor>>>),
+    /// quic监听, 通过Endpoint替换配置
+    Quic(Endpoint),
+}
+
+struct ReloaderInner {
+    target: Target,
+    settings: Mutex<Settings>,
+}
+
+/// tls证书的热更新句柄, 可通过[`crate::NetConn::tls_reloader`]获取
+/// 更新后新的连接使用新的证书, 已建立的连接不受影响
+#[derive(Clone)]
+pub struct TlsReloader {
+    inner: Arc<ReloaderInner>,
+}
+
+impl Debug for TlsReloader {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TlsReloader").finish()
+    }
+}
+
+impl TlsReloader {
+    pub(crate) fn acceptor(settings: &Settings) -> NetResult<TlsReloader> {
+        let accepter = Self::build_acceptor(settings)?;
+        Ok(Self::new(Target::Acceptor(RwLock::new(accepter)), settings))
+    }
+
+    pub(crate) fn quic(endpoint: Endpoint, settings: &Settings) -> TlsReloader {
+        Self::new(Target::Quic(endpoint), settings)
+    }
+
+    fn new(target: Target, settings: &Settings) -> TlsReloader {
+        let reloader = TlsReloader {
+            inner: Arc::new(ReloaderInner {
+                target,
+                settings: Mutex::new(settings.clone()),
+            }),
+        };
+        if settings.cert_watch_interval > 0 {
+            reloader.watch(Duration::from_millis(settings.cert_watch_interval as u64));
+        }
+        reloader
+    }
+
+    fn build_acceptor(settings: &Settings) -> NetResult<Option<Arc<TlsAcceptor>>> {
+        if settings.tls_mode.server() == Some(false) {
+            return Ok(None);
+        }
+        match TlsConfig::server_config(settings)? {
+            Some(mut config) => {
+                config.alpn_protocols = TlsConfig::alpn(settings, &[b"http/1.1"]);
+                Ok(Some(Arc::new(TlsAcceptor::from(Arc::new(config)))))
+            }
+            None if settings.tls_mode.server() == Some(true) => Err(TlsError::MissingCert.into()),
+            None => Ok(None),
+        }
+    }
+
+    /// 当前的acceptor, 未配置证书或tls_mode未启用服务端加密时为None
+    pub fn current(&self) -> Option<Arc<TlsAcceptor>> {
+        match &self.inner.target {
+            Target::Acceptor(accepter) => accepter.read().unwrap().clone(),
+            Target::Quic(_) => None,
+        }
+    }
+
+    /// 按当前的配置重新读取证书文件
+    pub fn reload(&self) -> NetResult<()> {
+        let settings = self.inner.settings.lock().unwrap().clone();
+        self.apply(&settings)
+    }
+
+    /// 按新的配置加载证书, 成功后替换保存的配置, 失败时保留原证书
+    pub fn reload_with_settings(&self, settings: Settings) -> NetResult<()> {
+        self.apply(&settings)?;
+        *self.inner.settings.lock().unwrap() = settings;
+        Ok(())
+    }
+
+    fn apply(&self, settings: &Settings) -> NetResult<()> {
+        match &self.inner.target {
+            Target::Acceptor(accepter) => {
+                let new = unwrap_or!(
+                    Self::build_acceptor(settings)?,
+                    return Err(NetError::Extension("tls must config cert and key"))
+                );
+                *accepter.write().unwrap() = Some(new);
+            }
+            Target::Quic(endpoint) => {
+                endpoint.set_server_config(Some(QuicConn::server_config(settings)?));
+            }
+        }
+        log::info!("tls证书已重新加载");
+        Ok(())
+    }
+
+    /// 证书相关文件的修改时间
+    fn modified(settings: &Settings) -> Vec<Option<SystemTime>> {
+        let mut paths = vec![&settings.cert, &settings.key, &settings.client_ca]
+            .into_iter()
+            .flatten()
+            .collect::<Vec<&String>>();
+        for sni in &settings.sni_certs {
+            if sni.cert_data.is_none() {
+                paths.push(&sni.cert);
+            }
+            if sni.key_data.is_none() {
+                paths.push(&sni.key);
+            }
+        }
+        paths
+            .iter()
+            .map(|p| std::fs::metadata(p).and_then(|m| m.modified()).ok())
+            .collect()
+    }
+
+    /// 定时检查证书文件的修改时间, 有变化时重新加载, 加载失败将在下次检查时重试
+    /// 所有句柄及监听都释放后自动退出
+    pub fn watch(&self, interval: Duration) -> JoinHandle<()> {
+        let weak = Arc::downgrade(&self.inner);
+        tokio::spawn(async move {
+            let mut last = None;
+            loop {
+                {
+                    let reloader = TlsReloader {
+                        inner: unwrap_or!(weak.upgrade(), return),
+                    };
+                    let settings = reloader.inner.settings.lock().unwrap().clone();
+                    let now = Self::modified(&settings);
+                    match last.take() {
+                        Some(old) if old != now => match reloader.apply(&settings) {
+                            Ok(()) => last = Some(now),
+                            Err(e) => {
+                                log::warn!("重新加载tls证书失败:{:?}", e);
+                                last = Some(old);
+                            }
+                        },
+                        _ => last = Some(now),
+                    }
+                }
+                tokio::time::sleep(interval).await;
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::PathBuf, sync::Arc};
+
+    use rcgen::{CertificateParams, KeyPair};
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::{TcpListener, TcpStream},
+    };
+    use tokio_rustls::{
+        client::TlsStream,
+        rustls::pki_types::{CertificateDer, ServerName},
+        TlsConnector,
+    };
+
+    use crate::{tls::TlsConfig, Settings, WrapListener};
+
+    /// 生成自签名证书并写入临时文件, 返回证书及配置了该证书文件的配置
+    fn write_cert(name: &str) -> (CertificateDer<'static>, Settings, Vec<PathBuf>) {
+        let key = KeyPair::generate().unwrap();
+        let cert = CertificateParams::new(vec!["reload.test".to_string()])
+            .unwrap()
+            .self_signed(&key)
+            .unwrap();
+        let dir = std::env::temp_dir();
+        let cert_file = dir.join(format!("hcnet_reload_{name}_{}.pem", std::process::id()));
+        let key_file = dir.join(format!("hcnet_reload_{name}_{}.key", std::process::id()));
+        std::fs::write(&cert_file, cert.pem()).unwrap();
+        std::fs::write(&key_file, key.serialize_pem()).unwrap();
+        let settings = Settings {
+            cert: Some(cert_file.to_string_lossy().to_string()),
+            key: Some(key_file.to_string_lossy().to_string()),
+            ..Settings::default()
+        };
+        (cert.der().clone(), settings, vec![cert_file, key_file])
+    }
+
+    /// 接受一个连接并完成握手, 返回客户端的tls流及服务端出示的证书
+    async fn handshake(
+        listener: &mut WrapListener,
+    ) -> (
+        TlsStream<TcpStream>,
+        tokio_rustls::server::TlsStream<TcpStream>,
+        CertificateDer<'static>,
+    ) {
+        let settings = Settings {
+            insecure_skip_verify: true,
+            ..Settings::default()
+        };
+        let connector = TlsConnector::from(Arc::new(TlsConfig::client_config(&settings).unwrap()));
+        let addr = listener.listener.local_addr().unwrap();
+        let name = ServerName::try_from("reload.test").unwrap();
+        let (accepted, stream) = tokio::join!(listener.accept(), TcpStream::connect(addr));
+        let (accepted, _, _, acceptor) = accepted.unwrap();
+        let (server, client) = tokio::join!(
+            acceptor.unwrap().accept(accepted),
+            connector.connect(name, stream.unwrap())
+        );
+        let (server, client) = (server.unwrap(), client.unwrap());
+        let cert = client.get_ref().1.peer_certificates().unwrap()[0].clone();
+        (client, server, cert)
+    }
+
+    #[tokio::test]
+    async fn reload_running_listener() {
+        let (first_cert, first, mut files) = write_cert("first");
+        let (second_cert, second, more) = write_cert("second");
+        files.extend(more);
+        let tcp = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut listener = WrapListener::new(tcp, 1, None, &first).await.unwrap();
+        let reloader = listener.accepter.clone();
+
+        let (mut client, mut server, cert) = handshake(&mut listener).await;
+        assert_eq!(cert, first_cert);
+
+        reloader.reload_with_settings(second.clone()).unwrap();
+        let (_, _, cert) = handshake(&mut listener).await;
+        assert_eq!(cert, second_cert);
+
+        // 已建立的连接不受影响
+        client.write_all(b"ping").await.unwrap();
+        let mut buf = [0u8; 4];
+        server.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"ping");
+        server.write_all(b"pong").await.unwrap();
+        client.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"pong");
+
+        // 加载失败时返回错误并保留原证书及配置
+        let bad = Settings {
+            cert: Some("/nonexistent/hcnet_cert.pem".to_string()),
+            ..second.clone()
+        };
+        assert!(reloader.reload_with_settings(bad).is_err());
+        let (_, _, cert) = handshake(&mut listener).await;
+        assert_eq!(cert, second_cert);
+        reloader.reload().unwrap();
+        let (_, _, cert) = handshake(&mut listener).await;
+        assert_eq!(cert, second_cert);
+
+        for file in files {
+            let _ = std::fs::remove_file(file);
+        }
+    }
+}
@@ -0,0 +1,165 @@
+use std::{collections::HashMap, sync::Arc};
+
+use tokio_rustls::rustls::{
+    server::{ClientHello, ResolvesServerCert},
+    sign::CertifiedKey,
+};
+
+/// 根据客户端的SNI选择证书, 支持`*.example.com`形式的通配符
+/// 通配符只匹配一级子域名, 均未匹配或客户端未发送SNI时使用默认证书
+#[derive(Debug, Default)]
+pub(crate) struct SniResolver {
+    exact: HashMap<String, Arc<CertifiedKey>>,
+    wildcard: HashMap<String, Arc<CertifiedKey>>,
+    default: Option<Arc<CertifiedKey>>,
+}
+
+fn normalize(host: &str) -> String {
+    host.trim().trim_end_matches('.').to_ascii_lowercase()
+}
+
+impl SniResolver {
+    pub fn new(default: Option<Arc<CertifiedKey>>) -> Self {
+        Self {
+            default,
+            ..Default::default()
+        }
+    }
+
+    pub fn add(&mut self, host: &str, key: Arc<CertifiedKey>) {
+        let host = normalize(host);
+        match host.strip_prefix("*.") {
+            Some(suffix) => self.wildcard.insert(suffix.to_string(), key),
+            None => self.exact.insert(host, key),
+        };
+    }
+
+    pub fn find(&self, name: &str) -> Option<Arc<CertifiedKey>> {
+        let name = normalize(name);
+        if let Some(key) = self.exact.get(&name) {
+            return Some(key.clone());
+        }
+        let (_, suffix) = name.split_once('.')?;
+        self.wildcard.get(suffix).cloned()
+    }
+}
+
+impl ResolvesServerCert for SniResolver {
+    fn resolve(&self, client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        client_hello
+            .server_name()
+            .and_then(|name| self.find(name))
+            .or_else(|| self.default.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use rcgen::{CertificateParams, KeyPair};
+    use tokio::io::duplex;
+    use tokio_rustls::{
+        rustls::{
+            crypto::aws_lc_rs::default_provider,
+            pki_types::{CertificateDer, PrivateKeyDer, ServerName},
+            sign::CertifiedKey,
+            ServerConfig,
+        },
+        TlsAcceptor, TlsConnector,
+    };
+
+    use super::SniResolver;
+    use crate::{tls::TlsConfig, Settings};
+
+    /// 自签名的证书, 返回证书及对应的CertifiedKey
+    fn certified(host: &str) -> (CertificateDer<'static>, Arc<CertifiedKey>) {
+        let key = KeyPair::generate().unwrap();
+        let cert = CertificateParams::new(vec![host.to_string()])
+            .unwrap()
+            .self_signed(&key)
+            .unwrap();
+        let der = PrivateKeyDer::Pkcs8(key.serialize_der().into());
+        let signer = default_provider()
+            .key_provider
+            .load_private_key(der)
+            .unwrap();
+        let cert = cert.der().clone();
+        (
+            cert.clone(),
+            Arc::new(CertifiedKey::new(vec![cert], signer)),
+        )
+    }
+
+    #[test]
+    fn find_exact_and_wildcard() {
+        let (_, exact) = certified("a.example.com");
+        let (_, wildcard) = certified("*.example.com");
+        let mut resolver = SniResolver::new(None);
+        resolver.add("A.Example.com", exact.clone());
+        resolver.add("*.example.com", wildcard.clone());
+
+        let is = |name: &str, key: &Arc<CertifiedKey>| {
+            resolver
+                .find(name)
+                .map(|k| Arc::ptr_eq(&k, key))
+                .unwrap_or(false)
+        };
+        assert!(is("a.example.com", &exact));
+        assert!(is("A.EXAMPLE.COM.", &exact));
+        assert!(is("b.example.com", &wildcard));
+        assert!(is("B.Example.Com", &wildcard));
+        // 通配符只匹配一级子域名
+        assert!(resolver.find("x.b.example.com").is_none());
+        assert!(resolver.find("example.com").is_none());
+        assert!(resolver.find("other.test").is_none());
+    }
+
+    /// 以指定的名称握手, 返回服务端出示的证书
+    async fn served_cert(
+        acceptor: &TlsAcceptor,
+        name: ServerName<'static>,
+    ) -> CertificateDer<'static> {
+        let settings = Settings {
+            insecure_skip_verify: true,
+            ..Settings::default()
+        };
+        let connector = TlsConnector::from(Arc::new(TlsConfig::client_config(&settings).unwrap()));
+        let (a, b) = duplex(16384);
+        let (server, client) = tokio::join!(acceptor.accept(a), connector.connect(name, b));
+        server.unwrap();
+        let client = client.unwrap();
+        let (_, conn) = client.get_ref();
+        conn.peer_certificates().unwrap()[0].clone()
+    }
+
+    #[tokio::test]
+    async fn resolve_with_default() {
+        let (default_cert, default_key) = certified("default.test");
+        let (exact_cert, exact_key) = certified("a.test");
+        let (wildcard_cert, wildcard_key) = certified("*.w.test");
+        let mut resolver = SniResolver::new(Some(default_key));
+        resolver.add("a.test", exact_key);
+        resolver.add("*.w.test", wildcard_key);
+        let config = ServerConfig::builder()
+            .with_no_client_auth()
+            .with_cert_resolver(Arc::new(resolver));
+        let acceptor = TlsAcceptor::from(Arc::new(config));
+
+        let name = |n: &str| ServerName::try_from(n.to_string()).unwrap();
+        assert_eq!(served_cert(&acceptor, name("a.test")).await, exact_cert);
+        assert_eq!(
+            served_cert(&acceptor, name("x.w.test")).await,
+            wildcard_cert
+        );
+        assert_eq!(
+            served_cert(&acceptor, name("unknown.test")).await,
+            default_cert
+        );
+        // 以ip连接时客户端不发送SNI
+        assert_eq!(
+            served_cert(&acceptor, name("127.0.0.1")).await,
+            default_cert
+        );
+    }
+}
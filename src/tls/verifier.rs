@@ -0,0 +1,268 @@
+use std::{str::FromStr, sync::Arc};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use sha2::{Digest, Sha256};
+use tokio_rustls::rustls::{
+    self,
+    client::{
+        danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+        WebPkiServerVerifier,
+    },
+    crypto::{verify_tls12_signature, verify_tls13_signature, CryptoProvider},
+    pki_types::{CertificateDer, ServerName, UnixTime},
+    CertificateError, DigitallySignedStruct, SignatureScheme,
+};
+
+use super::TlsError;
+
+/// 证书的sha256指纹
+/// 格式为`spki:<hash>`或`cert:<hash>`, hash可为hex(允许以:分隔)或base64
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum CertPin {
+    /// 证书公钥(SubjectPublicKeyInfo)的指纹, 证书续期但公钥不变时无需更新
+    Spki([u8; 32]),
+    /// 整个证书的指纹
+    Cert([u8; 32]),
+}
+
+impl FromStr for CertPin {
+    type Err = TlsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || TlsError::BadPin;
+        let (kind, hash) = s.trim().split_once(':').ok_or_else(err)?;
+        let hex = hash.replace(':', "");
+        let bytes = if hex.len() == 64 && hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+            (0..32)
+                .map(|i| u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).map_err(|_| err()))
+                .collect::<Result<Vec<u8>, _>>()?
+        } else {
+            STANDARD.decode(hash).map_err(|_| err())?
+        };
+        let hash: [u8; 32] = bytes.try_into().map_err(|_| err())?;
+        match kind.to_ascii_lowercase().as_str() {
+            "spki" => Ok(CertPin::Spki(hash)),
+            "cert" => Ok(CertPin::Cert(hash)),
+            _ => Err(err()),
+        }
+    }
+}
+
+impl CertPin {
+    pub fn matches(&self, cert: &CertificateDer<'_>) -> bool {
+        match self {
+            CertPin::Cert(hash) => Sha256::digest(cert.as_ref()).as_slice() == hash,
+            CertPin::Spki(hash) => match spki(cert.as_ref()) {
+                Some(spki) => Sha256::digest(spki).as_slice() == hash,
+                None => false,
+            },
+        }
+    }
+}
+
+/// 读取一个DER元素, 返回(完整元素, 内容, 剩余数据)
+fn der_next(data: &[u8]) -> Option<(&[u8], &[u8], &[u8])> {
+    if data.len() < 2 {
+        return None;
+    }
+    let (len, head) = match data[1] {
+        n if n < 0x80 => (n as usize, 2),
+        n => {
+            let num = (n & 0x7F) as usize;
+            if num == 0 || num > 4 || data.len() < 2 + num {
+                return None;
+            }
+            let len = data[2..2 + num]
+                .iter()
+                .fold(0usize, |len, b| (len << 8) | *b as usize);
+            (len, 2 + num)
+        }
+    };
+    let end = head.checked_add(len)?;
+    if data.len() < end {
+        return None;
+    }
+    Some((&data[..end], &data[head..end], &data[end..]))
+}
+
+/// 提取证书中完整的SubjectPublicKeyInfo
+fn spki(cert: &[u8]) -> Option<&[u8]> {
+    let (_, cert, _) = der_next(cert)?;
+    let (_, tbs, _) = der_next(cert)?;
+    let mut rest = tbs;
+    // version为[0]的显式标签, v1证书中省略
+    if rest.first() == Some(&0xA0) {
+        rest = der_next(rest)?.2;
+    }
+    // 跳过serialNumber, signature, issuer, validity, subject
+    for _ in 0..5 {
+        rest = der_next(rest)?.2;
+    }
+    let (spki, _, _) = der_next(rest)?;
+    (spki.first() == Some(&0x30)).then_some(spki)
+}
+
+/// 服务端证书的校验, 在证书链校验的基础上校验终端证书的指纹
+/// 未配置证书链校验时(insecure_skip_verify)仅校验握手签名及终端证书的指纹
+#[derive(Debug)]
+pub(crate) struct PinnedVerifier {
+    inner: Option<Arc<WebPkiServerVerifier>>,
+    pins: Vec<CertPin>,
+    provider: Arc<CryptoProvider>,
+}
+
+impl PinnedVerifier {
+    pub fn new(
+        inner: Option<Arc<WebPkiServerVerifier>>,
+        pins: Vec<CertPin>,
+        provider: Arc<CryptoProvider>,
+    ) -> Self {
+        Self {
+            inner,
+            pins,
+            provider,
+        }
+    }
+}
+
+impl ServerCertVerifier for PinnedVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        if self.pins.is_empty() {
+            return match &self.inner {
+                Some(inner) => inner.verify_server_cert(
+                    end_entity,
+                    intermediates,
+                    server_name,
+                    ocsp_response,
+                    now,
+                ),
+                None => Ok(ServerCertVerified::assertion()),
+            };
+        }
+        // 对端发送的中间证书不一定在校验通过的证书链中, 可附加任意公开的证书, 故只匹配终端证书
+        if let Some(inner) = &self.inner {
+            inner.verify_server_cert(end_entity, intermediates, server_name, ocsp_response, now)?;
+        }
+        if self.pins.iter().any(|p| p.matches(end_entity)) {
+            Ok(ServerCertVerified::assertion())
+        } else {
+            log::warn!("服务端证书与配置的指纹均不匹配");
+            Err(rustls::Error::InvalidCertificate(
+                CertificateError::ApplicationVerificationFailure,
+            ))
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider
+            .signature_verification_algorithms
+            .supported_schemes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use rcgen::{BasicConstraints, CertificateParams, IsCa, KeyPair};
+    use sha2::{Digest, Sha256};
+    use tokio_rustls::rustls::{
+        client::{danger::ServerCertVerifier, WebPkiServerVerifier},
+        crypto::aws_lc_rs,
+        pki_types::{CertificateDer, ServerName, UnixTime},
+        RootCertStore,
+    };
+
+    use super::{CertPin, PinnedVerifier};
+
+    fn ca() -> (rcgen::Certificate, KeyPair) {
+        let key = KeyPair::generate().unwrap();
+        let mut params = CertificateParams::new(vec![]).unwrap();
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        (params.self_signed(&key).unwrap(), key)
+    }
+
+    /// 由ca签发的example.com证书, 返回(证书, 公钥的指纹)
+    fn leaf(ca: &rcgen::Certificate, ca_key: &KeyPair) -> (CertificateDer<'static>, CertPin) {
+        let key = KeyPair::generate().unwrap();
+        let params = CertificateParams::new(vec!["example.com".to_string()]).unwrap();
+        let cert = params.signed_by(&key, ca, ca_key).unwrap();
+        let pin = CertPin::Spki(Sha256::digest(key.public_key_der()).into());
+        (cert.der().clone(), pin)
+    }
+
+    fn verify(
+        pins: Vec<CertPin>,
+        trusted: &rcgen::Certificate,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+    ) -> bool {
+        let provider = Arc::new(aws_lc_rs::default_provider());
+        let mut roots = RootCertStore::empty();
+        roots.add(trusted.der().clone()).unwrap();
+        let inner = WebPkiServerVerifier::builder_with_provider(Arc::new(roots), provider.clone())
+            .build()
+            .unwrap();
+        let verifier = PinnedVerifier::new(Some(inner), pins, provider);
+        let name = ServerName::try_from("example.com").unwrap();
+        verifier
+            .verify_server_cert(end_entity, intermediates, &name, &[], UnixTime::now())
+            .is_ok()
+    }
+
+    #[test]
+    fn pin_matches_end_entity() {
+        let (ca, ca_key) = ca();
+        let (cert, pin) = leaf(&ca, &ca_key);
+        assert!(verify(vec![pin], &ca, &cert, &[]));
+    }
+
+    #[test]
+    fn appended_pinned_ca_is_rejected() {
+        // 公开受信任的证书后附加被固定的CA, 该CA并不在校验通过的证书链中
+        let (trusted, trusted_key) = ca();
+        let (pinned, pinned_key) = ca();
+        let (cert, _) = leaf(&trusted, &trusted_key);
+        let pins = vec![
+            CertPin::Cert(Sha256::digest(pinned.der()).into()),
+            CertPin::Spki(Sha256::digest(pinned_key.public_key_der()).into()),
+        ];
+        assert!(verify(vec![], &trusted, &cert, &[pinned.der().clone()]));
+        assert!(!verify(pins, &trusted, &cert, &[pinned.der().clone()]));
+    }
+}
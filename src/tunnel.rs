@@ -0,0 +1,596 @@
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6},
+    str::FromStr,
+};
+
+use base64::{prelude::BASE64_STANDARD, Engine};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{lookup_host, TcpStream},
+};
+
+use crate::{NetError, NetResult};
+
+/// 代理响应头的最大长度
+const HTTP_HEADER_MAX: usize = 8192;
+
+/// 客户端连接的目标, 经由代理连接时域名不在本地解析, 交由代理解析
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Target {
+    /// 已解析的地址
+    Addrs(Vec<SocketAddr>),
+    /// 域名及端口
+    Host(String, u16),
+}
+
+impl Target {
+    /// 本地解析出目标的地址
+    pub(crate) async fn resolve(&self) -> NetResult<Vec<SocketAddr>> {
+        match self {
+            Target::Addrs(addrs) => Ok(addrs.clone()),
+            Target::Host(host, port) => Ok(lookup_host((&**host, *port)).await?.collect()),
+        }
+    }
+
+    /// 交由代理连接的主机及端口
+    pub(crate) fn host(&self) -> NetResult<(String, u16)> {
+        match self {
+            Target::Addrs(addrs) => {
+                let addr = addrs.first().ok_or(NetError::BadUrl("miss host"))?;
+                Ok((addr.ip().to_string(), addr.port()))
+            }
+            Target::Host(host, port) => Ok((host.clone(), *port)),
+        }
+    }
+
+    /// 目标的域名, 用于tls的SNI
+    pub(crate) fn domain(&self) -> Option<&str> {
+        match self {
+            Target::Addrs(_) => None,
+            Target::Host(host, _) => Some(host),
+        }
+    }
+}
+
+/// 可转换为连接目标的类型, 与`ToSocketAddrs`支持的类型一致
+pub trait ToTarget {
+    fn to_target(&self) -> NetResult<Target>;
+}
+
+impl ToTarget for Target {
+    fn to_target(&self) -> NetResult<Target> {
+        Ok(self.clone())
+    }
+}
+
+impl ToTarget for SocketAddr {
+    fn to_target(&self) -> NetResult<Target> {
+        Ok(Target::Addrs(vec![*self]))
+    }
+}
+
+impl ToTarget for SocketAddrV4 {
+    fn to_target(&self) -> NetResult<Target> {
+        SocketAddr::V4(*self).to_target()
+    }
+}
+
+impl ToTarget for SocketAddrV6 {
+    fn to_target(&self) -> NetResult<Target> {
+        SocketAddr::V6(*self).to_target()
+    }
+}
+
+impl ToTarget for (IpAddr, u16) {
+    fn to_target(&self) -> NetResult<Target> {
+        SocketAddr::from(*self).to_target()
+    }
+}
+
+impl ToTarget for (Ipv4Addr, u16) {
+    fn to_target(&self) -> NetResult<Target> {
+        SocketAddr::from(*self).to_target()
+    }
+}
+
+impl ToTarget for (Ipv6Addr, u16) {
+    fn to_target(&self) -> NetResult<Target> {
+        SocketAddr::from(*self).to_target()
+    }
+}
+
+impl ToTarget for [SocketAddr] {
+    fn to_target(&self) -> NetResult<Target> {
+        Ok(Target::Addrs(self.to_vec()))
+    }
+}
+
+impl ToTarget for Vec<SocketAddr> {
+    fn to_target(&self) -> NetResult<Target> {
+        self[..].to_target()
+    }
+}
+
+impl ToTarget for (&str, u16) {
+    fn to_target(&self) -> NetResult<Target> {
+        let host = self.0.trim_start_matches('[').trim_end_matches(']');
+        match host.parse::<IpAddr>() {
+            Ok(ip) => (ip, self.1).to_target(),
+            Err(_) => Ok(Target::Host(host.to_string(), self.1)),
+        }
+    }
+}
+
+impl ToTarget for (String, u16) {
+    fn to_target(&self) -> NetResult<Target> {
+        (&*self.0, self.1).to_target()
+    }
+}
+
+impl ToTarget for str {
+    fn to_target(&self) -> NetResult<Target> {
+        let (host, port) = self
+            .rsplit_once(':')
+            .ok_or(NetError::BadUrl("invalid socket address"))?;
+        let port = port
+            .parse::<u16>()
+            .map_err(|_| NetError::BadUrl("invalid port"))?;
+        (host, port).to_target()
+    }
+}
+
+impl ToTarget for String {
+    fn to_target(&self) -> NetResult<Target> {
+        self[..].to_target()
+    }
+}
+
+impl<T: ToTarget + ?Sized> ToTarget for &T {
+    fn to_target(&self) -> NetResult<Target> {
+        (**self).to_target()
+    }
+}
+
+/// 客户端代理的类型
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProxyKind {
+    /// socks5代理, 支持用户名密码认证
+    Socks5,
+    /// http代理, 通过CONNECT建立隧道, 支持basic认证
+    Http,
+}
+
+/// 客户端经由代理发起连接的配置
+/// 格式为`socks5://[user:pass@]host:port`或`http://[user:pass@]host:port`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProxyConfig {
+    /// 代理的类型
+    pub kind: ProxyKind,
+    /// 代理服务器的地址, host:port
+    pub addr: String,
+    /// 认证的用户名及密码
+    pub auth: Option<(String, String)>,
+}
+
+impl FromStr for ProxyConfig {
+    type Err = NetError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || NetError::BadUrl("invalid proxy");
+        let (scheme, rest) = s.trim().split_once("://").ok_or_else(err)?;
+        let kind = match &*scheme.to_ascii_lowercase() {
+            "socks5" | "socks5h" => ProxyKind::Socks5,
+            "http" => ProxyKind::Http,
+            _ => return Err(err()),
+        };
+        let rest = rest.trim_end_matches('/');
+        let (auth, addr) = match rest.rsplit_once('@') {
+            Some((auth, addr)) => {
+                let (user, pass) = auth.split_once(':').unwrap_or((auth, ""));
+                (Some((user.to_string(), pass.to_string())), addr)
+            }
+            None => (None, rest),
+        };
+        if addr.is_empty() || addr.contains('/') {
+            return Err(err());
+        }
+        let addr = if addr
+            .rsplit_once(':')
+            .is_some_and(|(_, p)| p.parse::<u16>().is_ok())
+        {
+            addr.to_string()
+        } else {
+            let port = match kind {
+                ProxyKind::Socks5 => 1080,
+                ProxyKind::Http => 80,
+            };
+            format!("{addr}:{port}")
+        };
+        Ok(ProxyConfig { kind, addr, auth })
+    }
+}
+
+impl ProxyConfig {
+    /// 连接代理服务器并建立到目标的隧道, host可为ip或域名, 域名交由代理解析
+    pub(crate) async fn connect(&self, host: &str, port: u16) -> NetResult<TcpStream> {
+        let mut stream = TcpStream::connect(&self.addr).await?;
+        match self.kind {
+            ProxyKind::Socks5 => self.socks5(&mut stream, host, port).await?,
+            ProxyKind::Http => self.http(&mut stream, host, port).await?,
+        }
+        Ok(stream)
+    }
+
+    async fn socks5(&self, stream: &mut TcpStream, host: &str, port: u16) -> NetResult<()> {
+        let err = NetError::Proxy;
+        match &self.auth {
+            Some(_) => stream.write_all(&[5, 2, 0, 2]).await?,
+            None => stream.write_all(&[5, 1, 0]).await?,
+        }
+        let mut reply = [0u8; 2];
+        stream.read_exact(&mut reply).await?;
+        if reply[0] != 5 {
+            return Err(err("socks5 invalid version"));
+        }
+        match (reply[1], &self.auth) {
+            (0, _) => {}
+            (2, Some((user, pass))) => {
+                if user.len() > 255 || pass.len() > 255 {
+                    return Err(err("socks5 auth too long"));
+                }
+                let mut data = vec![1, user.len() as u8];
+                data.extend_from_slice(user.as_bytes());
+                data.push(pass.len() as u8);
+                data.extend_from_slice(pass.as_bytes());
+                stream.write_all(&data).await?;
+                stream.read_exact(&mut reply).await?;
+                if reply[1] != 0 {
+                    return Err(err("socks5 auth failed"));
+                }
+            }
+            _ => return Err(err("socks5 no acceptable auth method")),
+        }
+
+        let mut data = vec![5, 1, 0];
+        match host
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .parse::<IpAddr>()
+        {
+            Ok(IpAddr::V4(ip)) => {
+                data.push(1);
+                data.extend_from_slice(&ip.octets());
+            }
+            Ok(IpAddr::V6(ip)) => {
+                data.push(4);
+                data.extend_from_slice(&ip.octets());
+            }
+            Err(_) => {
+                if host.len() > 255 {
+                    return Err(err("socks5 host too long"));
+                }
+                data.push(3);
+                data.push(host.len() as u8);
+                data.extend_from_slice(host.as_bytes());
+            }
+        }
+        data.extend_from_slice(&port.to_be_bytes());
+        stream.write_all(&data).await?;
+
+        let mut head = [0u8; 4];
+        stream.read_exact(&mut head).await?;
+        if head[0] != 5 {
+            return Err(err("socks5 invalid version"));
+        }
+        if head[1] != 0 {
+            log::warn!("socks5代理连接{}:{}失败, 错误码:{}", host, port, head[1]);
+            return Err(err("socks5 connect failed"));
+        }
+        // 跳过代理绑定的地址及端口
+        let len = match head[3] {
+            1 => 4,
+            4 => 16,
+            3 => stream.read_u8().await? as usize,
+            _ => return Err(err("socks5 invalid address type")),
+        };
+        let mut bind = vec![0u8; len + 2];
+        stream.read_exact(&mut bind).await?;
+        Ok(())
+    }
+
+    async fn http(&self, stream: &mut TcpStream, host: &str, port: u16) -> NetResult<()> {
+        let authority = if host.contains(':') && !host.starts_with('[') {
+            format!("[{host}]:{port}")
+        } else {
+            format!("{host}:{port}")
+        };
+        let mut req = format!("CONNECT {authority} HTTP/1.1\r\nHost: {authority}\r\n");
+        if let Some((user, pass)) = &self.auth {
+            let token = BASE64_STANDARD.encode(format!("{user}:{pass}"));
+            req.push_str(&format!("Proxy-Authorization: Basic {token}\r\n"));
+        }
+        req.push_str("\r\n");
+        stream.write_all(req.as_bytes()).await?;
+
+        // 逐字节读取至头结束, 隧道建立后的数据留给后续的协议
+        let mut head = vec![];
+        while !head.ends_with(b"\r\n\r\n") {
+            if head.len() >= HTTP_HEADER_MAX {
+                return Err(NetError::Proxy("http proxy response too long"));
+            }
+            head.push(stream.read_u8().await?);
+        }
+        let line = String::from_utf8_lossy(&head);
+        let status = line
+            .split_whitespace()
+            .nth(1)
+            .and_then(|s| s.parse::<u16>().ok())
+            .ok_or(NetError::Proxy("http proxy invalid response"))?;
+        if !(200..300).contains(&status) {
+            log::warn!("http代理连接{}失败, 状态码:{}", authority, status);
+            return Err(NetError::Proxy("http proxy connect failed"));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::SocketAddr, time::Duration};
+
+    use async_trait::async_trait;
+    use base64::{prelude::BASE64_STANDARD, Engine};
+    use tokio::{
+        io::{copy_bidirectional, AsyncReadExt, AsyncWriteExt},
+        net::{TcpListener, TcpStream},
+        sync::mpsc,
+    };
+
+    use super::ProxyConfig;
+    use crate::{Handler, Message, NetConn, NetResult, NetSender, Settings, TlsMode, TrustRoots};
+
+    /// 目标的域名, 本地无法解析, 由代理映射至本机的回显服务
+    const HOST: &str = "echo.test";
+
+    struct Echo {
+        sender: NetSender,
+    }
+
+    #[async_trait]
+    impl Handler for Echo {
+        async fn on_message(&mut self, msg: Message) -> NetResult<()> {
+            self.sender.send_message(msg)
+        }
+    }
+
+    struct Server;
+
+    #[async_trait]
+    impl Handler for Server {
+        async fn on_accept(&mut self, conn: NetConn) -> NetResult<()> {
+            let _ = conn.run_handler(|sender| Echo { sender }).await;
+            Ok(())
+        }
+    }
+
+    struct Client {
+        sender: NetSender,
+        tx: mpsc::UnboundedSender<Message>,
+    }
+
+    #[async_trait]
+    impl Handler for Client {
+        async fn on_open(&mut self) -> NetResult<()> {
+            self.sender.send_message(Message::Text("hello".to_string()))
+        }
+
+        async fn on_message(&mut self, msg: Message) -> NetResult<()> {
+            let _ = self.tx.send(msg);
+            Ok(())
+        }
+    }
+
+    /// 代理连接目标域名时映射的地址, 同时校验代理收到的是域名而非本地解析的地址
+    async fn upstream(host: &str, port: u16) -> TcpStream {
+        assert_eq!(host, HOST);
+        TcpStream::connect(("127.0.0.1", port)).await.unwrap()
+    }
+
+    /// socks5代理的替身, auth为期望的用户名及密码
+    async fn socks5_proxy(auth: Option<(&'static str, &'static str)>) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                tokio::spawn(async move {
+                    let mut head = [0u8; 2];
+                    stream.read_exact(&mut head).await.unwrap();
+                    let mut methods = vec![0u8; head[1] as usize];
+                    stream.read_exact(&mut methods).await.unwrap();
+                    match auth {
+                        Some((user, pass)) => {
+                            assert!(methods.contains(&2));
+                            stream.write_all(&[5, 2]).await.unwrap();
+                            let mut data = vec![0u8; 3 + user.len() + pass.len()];
+                            stream.read_exact(&mut data).await.unwrap();
+                            let mut expect = vec![1, user.len() as u8];
+                            expect.extend_from_slice(user.as_bytes());
+                            expect.push(pass.len() as u8);
+                            expect.extend_from_slice(pass.as_bytes());
+                            assert_eq!(data, expect);
+                            stream.write_all(&[1, 0]).await.unwrap();
+                        }
+                        None => stream.write_all(&[5, 0]).await.unwrap(),
+                    }
+                    let mut req = [0u8; 5];
+                    stream.read_exact(&mut req).await.unwrap();
+                    assert_eq!(&req[..4], &[5, 1, 0, 3]);
+                    let mut host = vec![0u8; req[4] as usize];
+                    stream.read_exact(&mut host).await.unwrap();
+                    let port = stream.read_u16().await.unwrap();
+                    let mut target = upstream(&String::from_utf8_lossy(&host), port).await;
+                    stream
+                        .write_all(&[5, 0, 0, 1, 127, 0, 0, 1, 0, 0])
+                        .await
+                        .unwrap();
+                    let _ = copy_bidirectional(&mut stream, &mut target).await;
+                });
+            }
+        });
+        addr
+    }
+
+    /// http代理的替身, auth为期望的用户名及密码
+    async fn http_proxy(auth: Option<(&'static str, &'static str)>) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                tokio::spawn(async move {
+                    let mut head = vec![];
+                    while !head.ends_with(b"\r\n\r\n") {
+                        head.push(stream.read_u8().await.unwrap());
+                    }
+                    let head = String::from_utf8(head).unwrap();
+                    let authority = head
+                        .strip_prefix("CONNECT ")
+                        .and_then(|s| s.split_whitespace().next())
+                        .unwrap()
+                        .to_string();
+                    if let Some((user, pass)) = auth {
+                        let token = BASE64_STANDARD.encode(format!("{user}:{pass}"));
+                        let line = format!("\r\nProxy-Authorization: Basic {token}\r\n");
+                        if !head.contains(&line) {
+                            stream
+                                .write_all(b"HTTP/1.1 407 Proxy Authentication Required\r\n\r\n")
+                                .await
+                                .unwrap();
+                            return;
+                        }
+                    }
+                    let (host, port) = authority.rsplit_once(':').unwrap();
+                    let mut target = upstream(host, port.parse().unwrap()).await;
+                    stream
+                        .write_all(b"HTTP/1.1 200 Connection established\r\n\r\n")
+                        .await
+                        .unwrap();
+                    let _ = copy_bidirectional(&mut stream, &mut target).await;
+                });
+            }
+        });
+        addr
+    }
+
+    /// 启动回显服务, 返回端口
+    async fn echo_server(ws: bool, settings: Settings) -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let conn = if ws {
+            NetConn::ws_bind_with_listener(listener, settings).await
+        } else {
+            NetConn::tcp_bind_with_listener(listener, settings).await
+        };
+        conn.unwrap().run_handler(|_| Server).await.unwrap();
+        port
+    }
+
+    /// 经由代理连接后发送消息, 并等待回显
+    async fn echo(conn: NetConn) {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        conn.run_handler(|sender| Client { sender, tx })
+            .await
+            .unwrap();
+        let msg = tokio::time::timeout(Duration::from_secs(5), rx.recv())
+            .await
+            .unwrap();
+        assert!(matches!(msg, Some(Message::Text(text)) if text == "hello"));
+    }
+
+    /// 分别经由代理连接tcp, tls及ws的回显服务
+    async fn check_all(proxy: ProxyConfig) {
+        let settings = Settings {
+            proxy: Some(proxy),
+            ..Settings::default()
+        };
+
+        let port = echo_server(false, Settings::default()).await;
+        let conn = NetConn::tcp_connect_with_settings(format!("{HOST}:{port}"), settings.clone())
+            .await
+            .unwrap();
+        echo(conn).await;
+
+        let key = rcgen::KeyPair::generate().unwrap();
+        let cert = rcgen::CertificateParams::new(vec![HOST.to_string()])
+            .unwrap()
+            .self_signed(&key)
+            .unwrap();
+        let server = Settings {
+            cert_data: Some(cert.pem().into_bytes()),
+            key_data: Some(key.serialize_pem().into_bytes()),
+            ..Settings::default()
+        };
+        let port = echo_server(false, server).await;
+        let ca = std::env::temp_dir().join(format!("hcnet_tunnel_{port}.pem"));
+        std::fs::write(&ca, cert.pem()).unwrap();
+        // 未配置domain, SNI及证书校验使用目标的域名而非代理的地址
+        let tls = Settings {
+            tls_mode: TlsMode::Client,
+            trust_roots: TrustRoots::Custom,
+            ca_files: vec![ca.to_string_lossy().to_string()],
+            ..settings.clone()
+        };
+        let conn = NetConn::tcp_connect_with_settings(format!("{HOST}:{port}"), tls).await;
+        let _ = std::fs::remove_file(&ca);
+        echo(conn.unwrap()).await;
+
+        let port = echo_server(true, Settings::default()).await;
+        let conn = NetConn::ws_connect_with_settings(format!("ws://{HOST}:{port}/"), settings)
+            .await
+            .unwrap();
+        echo(conn).await;
+    }
+
+    #[tokio::test]
+    async fn socks5_no_auth() {
+        let addr = socks5_proxy(None).await;
+        check_all(format!("socks5://{addr}").parse().unwrap()).await;
+    }
+
+    #[tokio::test]
+    async fn socks5_user_pass() {
+        let addr = socks5_proxy(Some(("user", "pass"))).await;
+        check_all(format!("socks5://user:pass@{addr}").parse().unwrap()).await;
+    }
+
+    #[tokio::test]
+    async fn http_basic_auth() {
+        let addr = http_proxy(Some(("user", "pass"))).await;
+        check_all(format!("http://user:pass@{addr}").parse().unwrap()).await;
+        let proxy: ProxyConfig = format!("http://user:wrong@{addr}").parse().unwrap();
+        assert!(proxy.connect(HOST, 80).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn socks5_bad_reply_version() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut head = [0u8; 3];
+            stream.read_exact(&mut head).await.unwrap();
+            stream.write_all(&[5, 0]).await.unwrap();
+            let mut req = vec![0u8; 7 + HOST.len()];
+            stream.read_exact(&mut req).await.unwrap();
+            stream
+                .write_all(&[4, 0, 0, 1, 0, 0, 0, 0, 0, 0])
+                .await
+                .unwrap();
+        });
+        let proxy: ProxyConfig = format!("socks5://{addr}").parse().unwrap();
+        assert!(proxy.connect(HOST, 80).await.is_err());
+    }
+}
@@ -0,0 +1,68 @@
+use std::{
+    io,
+    os::unix::fs::FileTypeExt,
+    path::{Path, PathBuf},
+};
+
+use tokio::net::{UnixListener, UnixStream};
+
+use super::NetResult;
+
+pub struct WrapUnixListener {
+    pub listener: UnixListener,
+    pub path: PathBuf,
+    pub server_id: u64,
+    pub next_connection_id: u32,
+}
+
+impl WrapUnixListener {
+    pub fn new(server_id: u64, listener: UnixListener, path: PathBuf) -> Self {
+        Self {
+            listener,
+            path,
+            server_id: server_id << 32,
+            next_connection_id: 0,
+        }
+    }
+
+    /// 绑定unix socket, 如果文件已存在且无进程监听则认为是残留文件, 清理后再绑定
+    pub async fn bind<P: AsRef<Path>>(path: P) -> NetResult<UnixListener> {
+        let path = path.as_ref();
+        if let Ok(meta) = std::fs::symlink_metadata(path) {
+            if !meta.file_type().is_socket() {
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!("{} exists and is not a socket", path.display()),
+                )
+                .into());
+            }
+            match UnixStream::connect(path).await {
+                Ok(_) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::AddrInUse,
+                        format!("{} is in use", path.display()),
+                    )
+                    .into());
+                }
+                Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => {
+                    log::warn!("清理残留的unix socket文件:{}", path.display());
+                    std::fs::remove_file(path)?;
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(UnixListener::bind(path)?)
+    }
+
+    pub async fn accept(&mut self) -> NetResult<(UnixStream, u64)> {
+        let (stream, _) = self.listener.accept().await?;
+        self.next_connection_id = self.next_connection_id.wrapping_add(1);
+        Ok((stream, self.server_id + self.next_connection_id as u64))
+    }
+}
+
+impl Drop for WrapUnixListener {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
@@ -0,0 +1,418 @@
+use algorithm::buf::{BinaryMut, Bt, BtMut};
+use log::warn;
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use tokio::{
+    io::{split, AsyncReadExt, AsyncWriteExt, ReadBuf},
+    net::{unix::UCred, UnixStream},
+    time,
+};
+
+mod listener;
+mod state;
+use listener::WrapUnixListener;
+pub use state::UnixState;
+
+use crate::{heartbeat::Heartbeat, id_center::IdCenter, NetConn, NetReceiver};
+
+use super::{online_count::OnlineCount, CloseCode, NetError, Settings};
+
+use super::{
+    codec::{encode_with_codec, Codec, CodecType},
+    handler::Handler,
+    message::Message,
+    NetResult,
+};
+
+enum Unix {
+    Stream(UnixStream),
+    Listener(WrapUnixListener),
+    Unconnect(PathBuf),
+    Uninit,
+}
+
+/// unix domain socket的封装, 与tcp采用相同的分包格式
+pub struct UnixConn {
+    unix: Unix,
+    settings: Settings,
+    codec: Box<dyn Codec>,
+    id: u64,
+    state: UnixState,
+    cred: Option<UCred>,
+    read: BinaryMut,
+    write: BinaryMut,
+    count: OnlineCount,
+}
+
+enum UnixReceiver {
+    Accept(UnixConn),
+    Read(Message),
+    Next,
+}
+
+unsafe impl Sync for UnixConn {}
+unsafe impl Send for UnixConn {}
+
+impl Default for UnixConn {
+    fn default() -> Self {
+        Self {
+            unix: Unix::Uninit,
+            id: 0,
+            state: UnixState::Open,
+            cred: None,
+            settings: Settings::default(),
+            codec: CodecType::default().build(),
+            read: BinaryMut::new(),
+            write: BinaryMut::new(),
+            count: OnlineCount::default(),
+        }
+    }
+}
+
+impl UnixConn {
+    pub fn new(path: PathBuf) -> UnixConn {
+        UnixConn {
+            unix: Unix::Unconnect(path),
+            ..Default::default()
+        }
+    }
+
+    pub async fn bind<P: AsRef<Path>>(path: P, settings: Settings) -> NetResult<UnixConn> {
+        let listener = WrapUnixListener::bind(path.as_ref()).await?;
+        let id = IdCenter::next_connect_id();
+        Ok(UnixConn {
+            unix: Unix::Listener(WrapUnixListener::new(
+                id,
+                listener,
+                path.as_ref().to_path_buf(),
+            )),
+            id,
+            settings,
+            count: OnlineCount::new(),
+            ..Default::default()
+        })
+    }
+
+    pub async fn connect_with_stream(
+        stream: UnixStream,
+        settings: Settings,
+    ) -> NetResult<UnixConn> {
+        Ok(UnixConn {
+            cred: stream.peer_cred().ok(),
+            unix: Unix::Stream(stream),
+            id: IdCenter::next_connect_id(),
+            codec: settings.build_codec(),
+            settings,
+            ..Default::default()
+        })
+    }
+
+    pub async fn connect<P: AsRef<Path>>(path: P) -> NetResult<UnixConn> {
+        Self::connect_with_settings(path, Settings::default()).await
+    }
+
+    pub async fn connect_with_settings<P: AsRef<Path>>(
+        path: P,
+        settings: Settings,
+    ) -> NetResult<UnixConn> {
+        match tokio::time::timeout(
+            Duration::from_millis(settings.connect_timeout as u64),
+            UnixStream::connect(path),
+        )
+        .await
+        {
+            Ok(v) => Self::connect_with_stream(v?, settings).await,
+            Err(_) => Err(NetError::Timeout),
+        }
+    }
+
+    async fn process(&mut self) -> NetResult<UnixReceiver> {
+        match &mut self.unix {
+            Unix::Listener(listener) => {
+                let (stream, id) = listener.accept().await?;
+                let now = self.count.now();
+                if now >= self.settings.max_connections {
+                    warn!(
+                        "当前连接数:{now}, 超出最大连接数: {}, 故关闭连接",
+                        self.settings.max_connections
+                    );
+                    return Ok(UnixReceiver::Next);
+                }
+                Ok(UnixReceiver::Accept(UnixConn {
+                    cred: stream.peer_cred().ok(),
+                    unix: Unix::Stream(stream),
+                    id,
+                    count: self.count.add(),
+                    codec: self.settings.build_codec(),
+                    settings: self.settings.clone(),
+                    ..Default::default()
+                }))
+            }
+            Unix::Stream(stream) => {
+                match &self.state {
+                    UnixState::Open => {
+                        let (mut reader, mut writer) = split(stream);
+                        loop {
+                            if let Some(v) = self.codec.decode(&mut self.read, &self.settings)? {
+                                return Ok(UnixReceiver::Read(v));
+                            }
+
+                            if self.read.len() >= self.settings.in_buffer_max {
+                                return Err(NetError::OverInbufferSize);
+                            }
+                            let mut buf = ReadBuf::uninit(self.read.chunk_mut());
+                            tokio::select! {
+                                val = reader.read_buf(&mut buf) => {
+                                    val?;
+                                    let s = buf.filled().len();
+                                    unsafe {
+                                        self.read.advance_mut(s);
+                                    }
+                                    if s == 0 {
+                                        return Ok(UnixReceiver::Read(Message::Shutdown));
+                                    }
+                                }
+                                // 一旦有写数据，则尝试写入数据，写入成功后扣除相应的数据
+                                r = writer.write(self.write.chunk()), if self.write.has_remaining() => {
+                                    let n = r?;
+                                    self.write.advance(n);
+                                    if !self.write.has_remaining() {
+                                        self.write.clear();
+                                    }
+                                }
+                                _ = tokio::time::sleep(Duration::from_millis(self.settings.read_timeout as u64)) => {
+                                    return Err(NetError::ReadTimeout);
+                                }
+                            }
+                        }
+                    }
+                    UnixState::Closing((_, _)) => {
+                        loop {
+                            tokio::select! {
+                                // 一旦有写数据，则尝试写入数据，写入成功后扣除相应的数据
+                                r = stream.write(self.write.chunk()), if self.write.has_remaining() => {
+                                    let n = r?;
+                                    self.write.advance(n);
+                                    if !self.write.has_remaining() {
+                                        self.write.clear();
+                                        self.state = UnixState::Closed;
+                                        return Ok(UnixReceiver::Read(Message::Shutdown));
+                                    }
+                                }
+                                _ = time::sleep(Duration::from_millis(self.settings.closing_time as u64)) => {
+                                    self.state = UnixState::Closed;
+                                    return Ok(UnixReceiver::Read(Message::Shutdown));
+                                }
+                            }
+                        }
+                    }
+                    UnixState::Closed => Ok(UnixReceiver::Read(Message::Shutdown)),
+                }
+            }
+            Unix::Unconnect(path) => {
+                let conn = Self::connect_with_settings(&*path, self.settings.clone()).await?;
+                self.cred = conn.cred;
+                self.unix = conn.unix;
+                Ok(UnixReceiver::Next)
+            }
+            _ => {
+                let pend = std::future::pending();
+                let () = pend.await;
+                unreachable!()
+            }
+        }
+    }
+
+    /// 对端进程的凭证信息(uid/gid/pid), 仅在连接建立后存在
+    pub fn peer_cred(&self) -> Option<UCred> {
+        self.cred
+    }
+
+    pub(crate) fn close(&mut self, code: CloseCode, reason: String) -> NetResult<()> {
+        encode_with_codec(
+            &mut *self.codec,
+            &mut self.write,
+            Message::Close(code, reason.clone()),
+            &self.settings,
+        )?;
+        self.state = UnixState::Closing((code, reason));
+        Ok(())
+    }
+
+    pub(crate) async fn inner_run_with_handler<H>(
+        &mut self,
+        handler: &mut H,
+        receiver: &mut NetReceiver,
+    ) -> NetResult<()>
+    where
+        H: Handler + 'static + Sync + Send,
+    {
+        handler.on_open().await?;
+        let is_listen = self.is_listen();
+        let mut heartbeat = Heartbeat::new(&self.settings, self.codec.has_control());
+        loop {
+            let heartbeat_time = heartbeat.deadline();
+            tokio::select! {
+                ret = self.process() => {
+                    let r = ret?;
+                    match r {
+                        UnixReceiver::Accept(unix) => {
+                            handler.on_accept(NetConn::from(unix)).await?
+                        },
+                        UnixReceiver::Read(msg) => {
+                            match msg {
+                                Message::Text(_) | Message::Binary(_) => handler.on_message(msg).await?,
+                                Message::Close(code, reason) => {
+                                    handler.on_close(code, reason).await;
+                                    return Ok(())
+                                }
+                                Message::Ping(data) => {
+                                    if let Some(ret) = handler.on_ping(data).await? {
+                                        encode_with_codec(&mut *self.codec, &mut self.write, Message::Pong(ret), &self.settings)?;
+                                    }
+                                },
+                                Message::Pong(data) => {
+                                    if let Some(rtt) = heartbeat.on_pong(&data) {
+                                        handler.on_heartbeat(rtt).await?;
+                                    }
+                                    handler.on_pong(data).await?
+                                },
+                                _ => return Ok(()),
+                            }
+                        },
+                        UnixReceiver::Next => continue,
+                    }
+                }
+                c = receiver.recv(), if self.write.len() < self.settings.out_buffer_max => {
+                    if is_listen {
+                        return Ok(())
+                    }
+                    let c = unwrap_or!(c, return Ok(()));
+                    match c.msg {
+                        Message::Close(code, reason) => {
+                            self.close(code, reason)?;
+                            continue;
+                        },
+                        Message::Shutdown => {
+                            self.close(CloseCode::Away, "Shutdown".to_string())?;
+                            continue;
+                        },
+                        _ => {}
+                    }
+                    encode_with_codec(&mut *self.codec, &mut self.write, c.msg, &self.settings)?;
+                }
+                _ = time::sleep_until(heartbeat_time), if heartbeat.is_enable() && !is_listen => {
+                    match heartbeat.on_tick() {
+                        Some(data) => encode_with_codec(&mut *self.codec, &mut self.write, Message::Ping(data), &self.settings)?,
+                        None => {
+                            let reason = "heartbeat timeout".to_string();
+                            handler.on_close(CloseCode::Heartbeat, reason.clone()).await;
+                            self.close(CloseCode::Heartbeat, reason)?;
+                        }
+                    }
+                }
+                r = handler.on_logic() => {
+                    r?;
+                }
+            };
+        }
+    }
+
+    pub fn get_settings(&mut self) -> &mut Settings {
+        &mut self.settings
+    }
+
+    pub fn set_settings(&mut self, settings: Settings) {
+        self.codec = settings.build_codec();
+        self.settings = settings
+    }
+
+    /// 设置自定义的分包方式, 之后调用set_settings将恢复为配置中的分包方式
+    pub fn set_codec(&mut self, codec: Box<dyn Codec>) {
+        self.codec = codec;
+    }
+
+    pub fn get_connection_id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn is_listen(&self) -> bool {
+        matches!(&self.unix, Unix::Listener(_))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use async_trait::async_trait;
+    use tokio::sync::mpsc;
+
+    use crate::{Handler, Message, NetConn, NetError, NetResult, NetSender, NetType, Settings};
+
+    struct Echo {
+        sender: NetSender,
+    }
+
+    #[async_trait]
+    impl Handler for Echo {
+        async fn on_message(&mut self, msg: Message) -> NetResult<()> {
+            self.sender.send_message(msg)
+        }
+    }
+
+    struct Server;
+
+    #[async_trait]
+    impl Handler for Server {
+        async fn on_accept(&mut self, conn: NetConn) -> NetResult<()> {
+            let _ = conn.run_handler(|sender| Echo { sender }).await;
+            Ok(())
+        }
+    }
+
+    struct Client {
+        sender: NetSender,
+        tx: mpsc::UnboundedSender<Message>,
+    }
+
+    #[async_trait]
+    impl Handler for Client {
+        async fn on_open(&mut self) -> NetResult<()> {
+            self.sender.send_message(Message::Text("hello".to_string()))
+        }
+
+        async fn on_message(&mut self, msg: Message) -> NetResult<()> {
+            let _ = self.tx.send(msg);
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn new_unix_connects_on_run() {
+        assert!(matches!(
+            NetConn::new(NetType::UnixSocket, "127.0.0.1:0").await,
+            Err(NetError::BadUrl(_))
+        ));
+
+        let path = std::env::temp_dir().join(format!("hcnet_unix_{}.sock", std::process::id()));
+        NetConn::unix_bind(&path, Settings::default())
+            .await
+            .unwrap()
+            .run_handler(|_| Server)
+            .await
+            .unwrap();
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        NetConn::new_unix(&path)
+            .run_handler(|sender| Client { sender, tx })
+            .await
+            .unwrap();
+        let msg = tokio::time::timeout(Duration::from_secs(5), rx.recv())
+            .await
+            .unwrap();
+        let _ = std::fs::remove_file(&path);
+        assert!(matches!(msg, Some(Message::Text(text)) if text == "hello"));
+    }
+}
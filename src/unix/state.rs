@@ -0,0 +1,7 @@
+use crate::CloseCode;
+
+pub enum UnixState {
+    Open,
+    Closing((CloseCode, String)),
+    Closed,
+}
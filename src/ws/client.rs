@@ -1,7 +1,7 @@
 use std::{net::SocketAddr, time::Duration};
 
 use crate::{
-    Message, NetError, NetResult, {CloseCode, MaybeTlsStream, Settings},
+    Message, NetError, NetResult, TlsError, {CloseCode, MaybeTlsStream, Settings, TlsInfo},
 };
 use algorithm::buf::{BinaryMut, Bt, BtMut};
 use base64::{prelude::BASE64_STANDARD, Engine};
@@ -10,12 +10,13 @@ use tokio::{
     net::TcpStream,
     time::{self, Instant},
 };
+use tokio_rustls::rustls::pki_types::CertificateDer;
 use webparse::{
     ws::{DataFrame, DataFrameable, OwnedMessage},
     HttpError, Request, Response, Url, WebError,
 };
 
-use super::{WsError, WsMsgReceiver, WsState};
+use super::{WsDeflate, WsError, WsMsgReceiver, WsState};
 
 /// websocket的客户端
 pub struct WsClient {
@@ -29,10 +30,24 @@ pub struct WsClient {
     read: BinaryMut,
     /// 写缓存
     write: BinaryMut,
+    /// 协商成功后的permessage-deflate压缩状态
+    deflate: Option<WsDeflate>,
+    /// 协商成功的子协议
+    protocol: Option<String>,
 }
 
 impl WsClient {
-    pub async fn new(stream: TcpStream, url: Url) -> NetResult<WsClient> {
+    /// 根据配置创建客户端, wss时可出示客户端证书
+    pub async fn new_with_settings(
+        stream: TcpStream,
+        url: Url,
+        settings: &Settings,
+    ) -> NetResult<WsClient> {
+        // 显式配置的tls_mode需与ws/wss一致
+        let wss = matches!(url.scheme, webparse::Scheme::Wss);
+        if settings.tls_mode.client().is_some_and(|tls| tls != wss) {
+            return Err(TlsError::ModeMismatch.into());
+        }
         match url.scheme {
             webparse::Scheme::Ws => Ok(WsClient {
                 stream: MaybeTlsStream::from(stream),
@@ -40,11 +55,14 @@ impl WsClient {
                 state: WsState::Wait,
                 read: BinaryMut::new(),
                 write: BinaryMut::new(),
+                deflate: None,
+                protocol: None,
             }),
             webparse::Scheme::Wss => {
-                let stream = MaybeTlsStream::connect_tls(
+                let stream = MaybeTlsStream::connect_tls_with_settings(
                     stream,
                     url.domain.clone().unwrap_or(String::new()),
+                    settings,
                 )
                 .await?;
                 Ok(WsClient {
@@ -53,26 +71,36 @@ impl WsClient {
                     state: WsState::Wait,
                     read: BinaryMut::new(),
                     write: BinaryMut::new(),
+                    deflate: None,
+                    protocol: None,
                 })
             }
             _ => return Err(WsError::ProtocolError("dismatch scheme only support ws, wss").into()),
         }
     }
 
-    /// 通过url发起连接
-    pub async fn connect(url: Url) -> NetResult<WsClient> {
+    /// 通过url及配置发起连接
+    pub async fn connect_with_settings(url: Url, settings: &Settings) -> NetResult<WsClient> {
         let domain = unwrap_or!(url.domain.clone(), return Err(WsError::UnknowHost.into()));
         let port = unwrap_or!(url.port, return Err(WsError::UnknowHost.into()));
         match url.scheme {
             webparse::Scheme::Ws | webparse::Scheme::Wss => {
-                let stream = TcpStream::connect(format!("{domain}:{port}")).await?;
-                Self::new(stream, url).await
+                let stream = match &settings.proxy {
+                    Some(proxy) => proxy.connect(&domain, port).await?,
+                    None => TcpStream::connect(format!("{domain}:{port}")).await?,
+                };
+                Self::new_with_settings(stream, url, settings).await
             }
             _ => return Err(WsError::ProtocolError("dismatch scheme only support ws, wss").into()),
         }
     }
 
     pub(crate) fn send_message(&mut self, msg: Message) -> NetResult<()> {
+        if let Some(deflate) = &mut self.deflate {
+            if deflate.write_message(&mut self.write, &msg, Some(rand::random()))? {
+                return Ok(());
+            }
+        }
         let msg: OwnedMessage = msg.into();
         msg.write_to(&mut self.write, Some(rand::random()))?;
         Ok(())
@@ -89,9 +117,29 @@ impl WsClient {
         Ok(())
     }
 
-    pub(crate) fn handler_response(&mut self, _res: Response<Vec<u8>>) -> NetResult<()> {
+    /// 握手成功, 根据返回的扩展头确定是否启用压缩
+    /// 服务端选择了未发送的子协议时握手失败
+    pub(crate) fn handler_response(
+        &mut self,
+        res: &Response<Vec<u8>>,
+        settings: &Settings,
+    ) -> NetResult<()> {
         match &self.state {
             WsState::WaitRet => {
+                if let Some(protocol) = res.headers().get_str_value(&"Sec-WebSocket-Protocol") {
+                    if !settings.ws_protocols.contains(&protocol) {
+                        return Err(WsError::ProtocolError(
+                            "server selected unoffered subprotocol",
+                        )
+                        .into());
+                    }
+                    self.protocol = Some(protocol);
+                }
+                if let Some(deflate) = &settings.deflate {
+                    if let Some(ext) = res.headers().get_str_value(&"Sec-WebSocket-Extensions") {
+                        self.deflate = WsDeflate::client_accept(&ext, deflate)?;
+                    }
+                }
                 self.state = WsState::Open;
             }
             _ => return Err(WsError::BadStatus.into()),
@@ -155,7 +203,15 @@ impl WsClient {
                     let key: [u8; 16] = rand::random();
                     header.insert("Sec-WebSocket-Key", BASE64_STANDARD.encode(&key));
                     header.insert("Sec-WebSocket-Version", "13");
-                    header.insert("Sec-WebSocket-Protocol", "chat, superchat");
+                    if !settings.ws_protocols.is_empty() {
+                        header.insert("Sec-WebSocket-Protocol", settings.ws_protocols.join(", "));
+                    }
+                    if let Some(deflate) = &settings.deflate {
+                        header.insert("Sec-WebSocket-Extensions", WsDeflate::client_offer(deflate));
+                    }
+                    for (name, value) in &settings.ws_headers {
+                        header.insert(name.clone(), value.clone());
+                    }
                     let data = req.http1_data()?;
                     self.write.put(&data[..]);
 
@@ -192,10 +248,7 @@ impl WsClient {
 
                         if !response.is_partial() {
                             self.read.advance(s);
-                            if response.status() != 101 {
-                                // println!("body = {:?}", String::from_utf8_lossy(response.body()));
-                                return Err(WsError::FailStatus(response.status().as_u16()).into());
-                            }
+                            // 非101的结果同样返回, 由上层交给on_response后结束连接
                             return Ok(WsMsgReceiver::Res(response));
                         }
                     }
@@ -221,7 +274,12 @@ impl WsClient {
                         let is_finish = frame.is_last();
                         vec.push(frame);
                         if is_finish {
-                            let msg = Message::from(OwnedMessage::from_dataframes(vec)?);
+                            let msg = match &mut self.deflate {
+                                Some(deflate) if vec[0].reserved()[0] => {
+                                    deflate.read_message(vec, settings.in_buffer_max)?
+                                }
+                                _ => Message::from(OwnedMessage::from_dataframes(vec)?),
+                            };
                             return Ok(WsMsgReceiver::Msg(msg));
                         }
                     }
@@ -251,6 +309,19 @@ impl WsClient {
         None
     }
 
+    pub fn peer_certificates(&self) -> Option<Vec<CertificateDer<'static>>> {
+        self.stream.peer_certificates()
+    }
+
+    pub fn tls_info(&self) -> Option<TlsInfo> {
+        self.stream.tls_info()
+    }
+
+    /// 握手协商的子协议
+    pub fn protocol(&self) -> Option<&String> {
+        self.protocol.as_ref()
+    }
+
     fn closing_to_closed(&mut self) {
         match &self.state {
             WsState::Closing(v) => self.state = WsState::Closed(v.clone()),
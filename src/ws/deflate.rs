@@ -0,0 +1,497 @@
+use algorithm::buf::{BinaryMut, BtMut};
+use flate2::{Compress, Compression, Decompress, FlushCompress, FlushDecompress, Status};
+use webparse::ws::{DataFrame, DataFrameable};
+
+use crate::{DeflateSettings, Message, NetError, NetResult};
+
+use super::WsError;
+
+static EXTENSION_NAME: &str = "permessage-deflate";
+/// 压缩数据在同步刷新后末尾固定的4个字节, 发送时去除, 接收时补回
+static DEFLATE_TAIL: [u8; 4] = [0x00, 0x00, 0xff, 0xff];
+
+/// 协商后的permessage-deflate参数, 以本端的视角区分压缩和解压
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct DeflateParams {
+    /// 本端压缩的窗口大小
+    compress_bits: u8,
+    /// 本端每条消息后是否重置压缩上下文
+    compress_reset: bool,
+    /// 对端压缩的窗口大小
+    decompress_bits: u8,
+    /// 对端每条消息后是否重置压缩上下文
+    decompress_reset: bool,
+}
+
+/// websocket的permessage-deflate压缩状态(RFC 7692)
+pub struct WsDeflate {
+    compress: Compress,
+    decompress: Decompress,
+    params: DeflateParams,
+    threshold: usize,
+}
+
+/// 原始deflate不支持8位窗口, 统一限定在9-15之间
+fn clamp_bits(bits: u8) -> u8 {
+    bits.clamp(9, 15)
+}
+
+/// 扩展的名称及参数
+type Extension = (String, Vec<(String, Option<String>)>);
+
+/// 解析扩展头, 返回各个扩展的名称及参数
+fn parse_extensions(header: &str) -> Vec<Extension> {
+    header
+        .split(',')
+        .filter_map(|offer| {
+            let mut parts = offer.split(';').map(|s| s.trim());
+            let name = parts.next().filter(|s| !s.is_empty())?.to_ascii_lowercase();
+            let params = parts
+                .filter(|s| !s.is_empty())
+                .map(|p| match p.split_once('=') {
+                    Some((k, v)) => (
+                        k.trim().to_ascii_lowercase(),
+                        Some(v.trim().trim_matches('"').to_string()),
+                    ),
+                    None => (p.to_ascii_lowercase(), None),
+                })
+                .collect();
+            Some((name, params))
+        })
+        .collect()
+}
+
+fn parse_bits(value: &Option<String>) -> Option<u8> {
+    let bits = value.as_ref()?.parse::<u8>().ok()?;
+    if (8..=15).contains(&bits) {
+        Some(bits)
+    } else {
+        None
+    }
+}
+
+impl WsDeflate {
+    fn new(params: DeflateParams, settings: &DeflateSettings) -> Self {
+        let compress_bits = clamp_bits(params.compress_bits);
+        let decompress_bits = clamp_bits(params.decompress_bits);
+        Self {
+            compress: Compress::new_with_window_bits(
+                Compression::new(settings.level.min(9)),
+                false,
+                compress_bits,
+            ),
+            decompress: Decompress::new_with_window_bits(false, decompress_bits),
+            params,
+            threshold: settings.threshold,
+        }
+    }
+
+    /// 客户端发起握手时的扩展头
+    pub fn client_offer(settings: &DeflateSettings) -> String {
+        let mut offer = format!("{EXTENSION_NAME}; client_max_window_bits");
+        let client_bits = clamp_bits(settings.client_max_window_bits);
+        if client_bits < 15 {
+            offer.push_str(&format!("={client_bits}"));
+        }
+        let server_bits = clamp_bits(settings.server_max_window_bits);
+        if server_bits < 15 {
+            offer.push_str(&format!("; server_max_window_bits={server_bits}"));
+        }
+        if settings.client_no_context_takeover {
+            offer.push_str("; client_no_context_takeover");
+        }
+        if settings.server_no_context_takeover {
+            offer.push_str("; server_no_context_takeover");
+        }
+        offer
+    }
+
+    /// 客户端根据服务端返回的扩展头创建压缩状态, 服务端未接受时返回None
+    pub fn client_accept(header: &str, settings: &DeflateSettings) -> NetResult<Option<WsDeflate>> {
+        let (_, params) = unwrap_or!(
+            parse_extensions(header)
+                .into_iter()
+                .find(|(name, _)| name == EXTENSION_NAME),
+            return Ok(None)
+        );
+        let mut result = DeflateParams {
+            compress_bits: clamp_bits(settings.client_max_window_bits),
+            compress_reset: settings.client_no_context_takeover,
+            decompress_bits: 15,
+            decompress_reset: false,
+        };
+        for (key, value) in &params {
+            match &**key {
+                "server_no_context_takeover" => result.decompress_reset = true,
+                "client_no_context_takeover" => result.compress_reset = true,
+                "server_max_window_bits" => {
+                    result.decompress_bits = unwrap_or!(
+                        parse_bits(value),
+                        return Err(WsError::ProtocolError("invalid server_max_window_bits").into())
+                    );
+                }
+                "client_max_window_bits" => {
+                    let bits = unwrap_or!(
+                        parse_bits(value),
+                        return Err(WsError::ProtocolError("invalid client_max_window_bits").into())
+                    );
+                    if bits < 9 {
+                        return Err(
+                            WsError::ProtocolError("unsupported client_max_window_bits").into()
+                        );
+                    }
+                    result.compress_bits = result.compress_bits.min(bits);
+                }
+                _ => return Err(WsError::ProtocolError("unknown deflate parameter").into()),
+            }
+        }
+        Ok(Some(WsDeflate::new(result, settings)))
+    }
+
+    /// 服务端根据客户端请求的扩展头协商, 返回压缩状态及需要响应的扩展头
+    pub fn server_negotiate(
+        header: &str,
+        settings: &DeflateSettings,
+    ) -> Option<(WsDeflate, String)> {
+        'offer: for (name, params) in parse_extensions(header) {
+            if name != EXTENSION_NAME {
+                continue;
+            }
+            let mut result = DeflateParams {
+                compress_bits: clamp_bits(settings.server_max_window_bits),
+                compress_reset: settings.server_no_context_takeover,
+                decompress_bits: 15,
+                decompress_reset: settings.client_no_context_takeover,
+            };
+            // 客户端允许限定其压缩窗口, 及其提议的窗口大小
+            let mut client_bits_offer = None;
+            for (key, value) in &params {
+                match &**key {
+                    "server_no_context_takeover" => result.compress_reset = true,
+                    "client_no_context_takeover" => result.decompress_reset = true,
+                    "server_max_window_bits" => {
+                        let bits = unwrap_or!(parse_bits(value), continue 'offer);
+                        // 原始deflate无法以8位窗口压缩, 拒绝该提议
+                        if bits < 9 {
+                            continue 'offer;
+                        }
+                        result.compress_bits = result.compress_bits.min(bits);
+                    }
+                    "client_max_window_bits" => {
+                        client_bits_offer = match value {
+                            Some(_) => Some(unwrap_or!(parse_bits(value), continue 'offer)),
+                            None => Some(15),
+                        };
+                    }
+                    _ => continue 'offer,
+                }
+            }
+            let mut response = EXTENSION_NAME.to_string();
+            if result.compress_reset {
+                response.push_str("; server_no_context_takeover");
+            }
+            if result.decompress_reset {
+                response.push_str("; client_no_context_takeover");
+            }
+            if result.compress_bits < 15 {
+                response.push_str(&format!(
+                    "; server_max_window_bits={}",
+                    result.compress_bits
+                ));
+            }
+            // 响应的窗口不可大于客户端的提议, 8位窗口的数据以9位窗口解压
+            if let Some(offer) = client_bits_offer {
+                let bits = clamp_bits(settings.client_max_window_bits).min(offer);
+                result.decompress_bits = clamp_bits(bits);
+                if bits < 15 {
+                    response.push_str(&format!("; client_max_window_bits={bits}"));
+                }
+            }
+            return Some((WsDeflate::new(result, settings), response));
+        }
+        None
+    }
+
+    fn compress(&mut self, data: &[u8]) -> NetResult<Vec<u8>> {
+        let start = self.compress.total_in();
+        let mut out = Vec::with_capacity(data.len() / 2 + 64);
+        loop {
+            let consumed = (self.compress.total_in() - start) as usize;
+            if out.capacity() - out.len() < 64 {
+                out.reserve(out.capacity().max(64));
+            }
+            self.compress
+                .compress_vec(&data[consumed..], &mut out, FlushCompress::Sync)
+                .map_err(|_| NetError::Compress("deflate compress failed"))?;
+            let consumed = (self.compress.total_in() - start) as usize;
+            if consumed == data.len() && out.len() < out.capacity() {
+                break;
+            }
+        }
+        if out.ends_with(&DEFLATE_TAIL) {
+            out.truncate(out.len() - DEFLATE_TAIL.len());
+        }
+        if self.params.compress_reset {
+            self.compress.reset();
+        }
+        Ok(out)
+    }
+
+    /// 解压数据, 解压后超出max_size则返回错误以防止压缩炸弹
+    fn decompress(&mut self, data: &[u8], max_size: usize) -> NetResult<Vec<u8>> {
+        let mut input = Vec::with_capacity(data.len() + DEFLATE_TAIL.len());
+        input.extend_from_slice(data);
+        input.extend_from_slice(&DEFLATE_TAIL);
+        let start = self.decompress.total_in();
+        let mut out = Vec::with_capacity((data.len() * 2).clamp(64, max_size.max(64)));
+        loop {
+            let (before_in, before_out) = (self.decompress.total_in(), self.decompress.total_out());
+            let consumed = (before_in - start) as usize;
+            if out.len() == out.capacity() {
+                out.reserve(out.capacity().max(64));
+            }
+            let status = self
+                .decompress
+                .decompress_vec(&input[consumed..], &mut out, FlushDecompress::Sync)
+                .map_err(|_| NetError::Compress("deflate decompress failed"))?;
+            if out.len() > max_size {
+                return Err(NetError::OverMsgSize);
+            }
+            let consumed = (self.decompress.total_in() - start) as usize;
+            if status == Status::StreamEnd
+                || (consumed == input.len() && out.len() < out.capacity())
+            {
+                break;
+            }
+            if self.decompress.total_in() == before_in && self.decompress.total_out() == before_out
+            {
+                return Err(NetError::Compress("deflate decompress stalled"));
+            }
+        }
+        if self.params.decompress_reset {
+            self.decompress.reset(false);
+        }
+        Ok(out)
+    }
+
+    /// 压缩并写入数据消息, 控制消息及小于阈值的消息返回false由调用方按原格式写入
+    pub fn write_message(
+        &mut self,
+        buf: &mut BinaryMut,
+        msg: &Message,
+        mask: Option<[u8; 4]>,
+    ) -> NetResult<bool> {
+        let (opcode, data) = match msg {
+            Message::Text(text) => (1, text.as_bytes()),
+            Message::Binary(bytes) => (2, &bytes[..]),
+            Message::Prepared(p) => return self.write_message(buf, p.message(), mask),
+            _ => return Ok(false),
+        };
+        if data.len() < self.threshold {
+            return Ok(false);
+        }
+        let payload = self.compress(data)?;
+        write_frame(buf, opcode, true, &payload, mask);
+        Ok(true)
+    }
+
+    /// 将设置了RSV1的数据帧解压成消息
+    pub fn read_message(&mut self, frames: Vec<DataFrame>, max_size: usize) -> NetResult<Message> {
+        let opcode = frames.first().map(|f| f.opcode()).unwrap_or(0);
+        let mut payload = vec![];
+        for (i, frame) in frames.into_iter().enumerate() {
+            if i > 0 && frame.opcode() != 0 {
+                return Err(WsError::ProtocolError("unexpected non-continuation frame").into());
+            }
+            payload.extend_from_slice(&frame.take_payload());
+        }
+        let data = self.decompress(&payload, max_size)?;
+        match opcode {
+            1 => match String::from_utf8(data) {
+                Ok(text) => Ok(Message::Text(text)),
+                Err(_) => Err(NetError::BadText),
+            },
+            2 => Ok(Message::Binary(data)),
+            _ => Err(WsError::ProtocolError("compressed control frame").into()),
+        }
+    }
+}
+
+/// 写入单个完整的帧, 压缩帧需设置RSV1
+fn write_frame(buf: &mut BinaryMut, opcode: u8, rsv1: bool, payload: &[u8], mask: Option<[u8; 4]>) {
+    let mut first = 0x80 | opcode;
+    if rsv1 {
+        first |= 0x40;
+    }
+    buf.put_u8(first);
+    let mask_bit = if mask.is_some() { 0x80 } else { 0 };
+    let len = payload.len();
+    if len < 126 {
+        buf.put_u8(mask_bit | len as u8);
+    } else if len <= u16::MAX as usize {
+        buf.put_u8(mask_bit | 126);
+        buf.put_u16(len as u16);
+    } else {
+        buf.put_u8(mask_bit | 127);
+        buf.put_u64(len as u64);
+    }
+    match mask {
+        Some(key) => {
+            buf.put_slice(&key);
+            let masked: Vec<u8> = payload
+                .iter()
+                .enumerate()
+                .map(|(i, b)| b ^ key[i % 4])
+                .collect();
+            buf.put_slice(&masked);
+        }
+        None => {
+            buf.put_slice(payload);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::WsDeflate;
+    use crate::DeflateSettings;
+
+    /// 按客户端的配置握手, 返回客户端及服务端的压缩状态及服务端的响应头
+    fn negotiate(
+        client: &DeflateSettings,
+        server: &DeflateSettings,
+    ) -> (WsDeflate, WsDeflate, String) {
+        let offer = WsDeflate::client_offer(client);
+        let (server_deflate, response) = WsDeflate::server_negotiate(&offer, server).unwrap();
+        let client_deflate = WsDeflate::client_accept(&response, client)
+            .unwrap()
+            .unwrap();
+        (client_deflate, server_deflate, response)
+    }
+
+    #[test]
+    fn negotiate_default() {
+        let settings = DeflateSettings::default();
+        assert_eq!(
+            WsDeflate::client_offer(&settings),
+            "permessage-deflate; client_max_window_bits"
+        );
+        let (client, server, response) = negotiate(&settings, &settings);
+        assert_eq!(response, "permessage-deflate");
+        assert!(!client.params.compress_reset && !client.params.decompress_reset);
+        assert!(!server.params.compress_reset && !server.params.decompress_reset);
+        assert!(WsDeflate::client_accept("x-other", &settings)
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn negotiate_context_takeover() {
+        let client = DeflateSettings {
+            client_no_context_takeover: true,
+            server_no_context_takeover: true,
+            ..DeflateSettings::default()
+        };
+        let (client, server, response) = negotiate(&client, &DeflateSettings::default());
+        assert_eq!(
+            response,
+            "permessage-deflate; server_no_context_takeover; client_no_context_takeover"
+        );
+        assert!(client.params.compress_reset && client.params.decompress_reset);
+        assert!(server.params.compress_reset && server.params.decompress_reset);
+    }
+
+    #[test]
+    fn negotiate_window_bits() {
+        let settings = DeflateSettings::default();
+        // 响应不可大于客户端提议的窗口
+        let (_, response) =
+            WsDeflate::server_negotiate("permessage-deflate; client_max_window_bits=8", &settings)
+                .unwrap();
+        assert_eq!(response, "permessage-deflate; client_max_window_bits=8");
+        // 客户端未提议client_max_window_bits时不可在响应中限定
+        let server = DeflateSettings {
+            client_max_window_bits: 10,
+            server_max_window_bits: 11,
+            ..DeflateSettings::default()
+        };
+        let (_, response) = WsDeflate::server_negotiate("permessage-deflate", &server).unwrap();
+        assert_eq!(response, "permessage-deflate; server_max_window_bits=11");
+
+        let (client, server, response) = negotiate(&settings, &server);
+        assert_eq!(
+            response,
+            "permessage-deflate; server_max_window_bits=11; client_max_window_bits=10"
+        );
+        assert_eq!(client.params.compress_bits, 10);
+        assert_eq!(client.params.decompress_bits, 11);
+        assert_eq!(server.params.compress_bits, 11);
+        assert_eq!(server.params.decompress_bits, 10);
+        // 原始deflate无法以8位窗口压缩
+        assert!(WsDeflate::server_negotiate(
+            "permessage-deflate; server_max_window_bits=8",
+            &settings
+        )
+        .is_none());
+    }
+
+    #[test]
+    fn reject_unknown_parameter() {
+        let settings = DeflateSettings::default();
+        assert!(WsDeflate::server_negotiate("permessage-deflate; foo", &settings).is_none());
+        assert!(WsDeflate::server_negotiate(
+            "permessage-deflate; client_max_window_bits=16",
+            &settings
+        )
+        .is_none());
+        // 拒绝后尝试下一个提议
+        let (_, response) = WsDeflate::server_negotiate(
+            "permessage-deflate; foo, permessage-deflate; client_no_context_takeover",
+            &settings,
+        )
+        .unwrap();
+        assert_eq!(response, "permessage-deflate; client_no_context_takeover");
+        assert!(WsDeflate::client_accept("permessage-deflate; foo", &settings).is_err());
+    }
+
+    /// 客户端压缩多条消息由服务端解压, 返回各条压缩后的长度
+    fn round_trip(settings: &DeflateSettings) -> Vec<usize> {
+        let (mut client, mut server, _) = negotiate(settings, settings);
+        let msg = "hello permessage-deflate ".repeat(20);
+        let mut lens = vec![];
+        for _ in 0..3 {
+            let data = client.compress(msg.as_bytes()).unwrap();
+            lens.push(data.len());
+            let out = server.decompress(&data, 1024 * 1024).unwrap();
+            assert_eq!(out, msg.as_bytes());
+        }
+        lens
+    }
+
+    #[test]
+    fn round_trip_context_takeover() {
+        let lens = round_trip(&DeflateSettings::default());
+        // 保持上下文时后续的相同消息只需引用前文
+        assert!(lens[1] < lens[0]);
+    }
+
+    #[test]
+    fn round_trip_no_context_takeover() {
+        let settings = DeflateSettings {
+            client_no_context_takeover: true,
+            server_no_context_takeover: true,
+            ..DeflateSettings::default()
+        };
+        let lens = round_trip(&settings);
+        assert_eq!(lens[0], lens[1]);
+        assert_eq!(lens[1], lens[2]);
+    }
+
+    #[test]
+    fn decompress_limit() {
+        let settings = DeflateSettings::default();
+        let (mut client, mut server, _) = negotiate(&settings, &settings);
+        let data = client.compress(&[0; 4096]).unwrap();
+        assert!(server.decompress(&data, 1024).is_err());
+    }
+}
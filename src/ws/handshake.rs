@@ -43,9 +43,10 @@ impl WsHandshake {
         }
     }
 
+    /// 构建握手的返回结果, 子协议由服务端根据配置的ws_protocols协商
+    /// 如需自行选择子协议, 可在返回结果中设置Sec-WebSocket-Protocol
     pub fn build_request(req: &Request<Vec<u8>>) -> NetResult<Response<Vec<u8>>> {
         let key = req.headers().get_str_value(&"Sec-WebSocket-Key");
-        let protocol = req.headers().get_str_value(&"Sec-WebSocket-Protocol");
         let version = req.headers().get_str_value(&"Sec-WebSocket-Version");
         if key.is_none() || version.as_ref().map(|s| &**s) != Some("13") {
             return Ok(Response::builder()
@@ -54,19 +55,60 @@ impl WsHandshake {
                 .unwrap()
                 .into_type());
         }
-        let (key, protocol) = (key.unwrap(), protocol.unwrap_or("chat".to_string()));
-        let accept = Self::build_accept(&key)?;
-        let protocols: Vec<&str> = protocol
-            .split(|c| c == ',' || c == ' ')
-            .filter(|s| !s.is_empty())
-            .collect();
+        let accept = Self::build_accept(&key.unwrap())?;
         return Ok(Response::builder()
             .status(101)
             .header("Upgrade", "websocket")
             .header("Connection", "Upgrade")
             .header("Sec-WebSocket-Accept", accept)
-            .header("Sec-WebSocket-Protocol", protocols[0].to_string())
             .body(vec![])
             .unwrap());
     }
+
+    /// 解析Sec-WebSocket-Protocol中的子协议列表
+    pub fn parse_protocols(value: &str) -> Vec<&str> {
+        value
+            .split(|c| c == ',' || c == ' ')
+            .filter(|s| !s.is_empty())
+            .collect()
+    }
+
+    /// 按服务端的优先级选取客户端同样支持的子协议, 均不支持时返回None
+    pub fn select_protocol(offered: &str, supported: &[String]) -> Option<String> {
+        let offered = Self::parse_protocols(offered);
+        supported
+            .iter()
+            .find(|p| offered.contains(&p.as_str()))
+            .cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::WsHandshake;
+
+    #[test]
+    fn parse_protocols() {
+        assert_eq!(
+            WsHandshake::parse_protocols("chat, superchat,mqtt  ,"),
+            vec!["chat", "superchat", "mqtt"]
+        );
+        assert!(WsHandshake::parse_protocols(" , ").is_empty());
+    }
+
+    #[test]
+    fn select_protocol() {
+        let supported = vec!["chat".to_string(), "superchat".to_string()];
+        // 按服务端的优先级选择, 与客户端的顺序无关
+        assert_eq!(
+            WsHandshake::select_protocol("superchat, chat", &supported),
+            Some("chat".to_string())
+        );
+        assert_eq!(
+            WsHandshake::select_protocol("mqtt, superchat", &supported),
+            Some("superchat".to_string())
+        );
+        assert_eq!(WsHandshake::select_protocol("mqtt", &supported), None);
+        assert_eq!(WsHandshake::select_protocol("chat", &[]), None);
+    }
 }
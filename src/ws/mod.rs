@@ -1,32 +1,42 @@
-use std::net::SocketAddr;
+use std::{net::SocketAddr, time::Duration};
 
 use log::warn;
-use tokio::net::{TcpListener, TcpStream, ToSocketAddrs};
+use tokio::{
+    net::{TcpListener, TcpStream, ToSocketAddrs},
+    time,
+};
+use tokio_rustls::rustls::pki_types::CertificateDer;
 use webparse::{Request, Response, Url};
 
 mod client;
+mod deflate;
 mod error;
 mod handshake;
 mod server;
 mod state;
 
 pub use client::WsClient;
+pub use deflate::WsDeflate;
 pub use error::WsError;
 pub use handshake::WsHandshake;
 pub use server::WsServer;
 pub use state::WsState;
 
-use crate::{id_center::IdCenter, NetConn, NetReceiver};
+use crate::{
+    heartbeat::Heartbeat, id_center::IdCenter, reconnect::Disconnect, NetConn, NetReceiver,
+};
 
 use super::{
     online_count::OnlineCount, stream::MaybeAcceptStream, CloseCode, Handler, Message, NetError,
-    NetResult, NetSender, Settings, TcpAcceptServer, WrapListener,
+    NetResult, ProxyHeader, Settings, TcpAcceptServer, TlsInfo, TlsReloader, WrapListener,
 };
 
 pub(crate) enum WsMsgReceiver {
     Accept(WsConn),
     Req(Request<Vec<u8>>),
     Res(Response<Vec<u8>>),
+    /// 服务端收到的非升级请求
+    Http(Request<Vec<u8>>),
     Msg(Message),
     Next,
 }
@@ -41,14 +51,20 @@ pub(crate) enum Ws {
 }
 
 impl Ws {
-    pub async fn try_accept(&mut self) -> NetResult<()> {
+    /// 完成服务端的接受, 返回PROXY protocol头
+    /// PROXY protocol头及tls握手需在timeout内完成, 避免空连接一直占用连接数
+    pub async fn try_accept(&mut self, timeout: Duration) -> NetResult<Option<ProxyHeader>> {
         match self {
             Ws::AcceptServer(ws_accept_server) => {
-                let (stream, addr) = ws_accept_server.accept().await?;
+                let (stream, addr, header) =
+                    match time::timeout(timeout, ws_accept_server.accept()).await {
+                        Ok(v) => v?,
+                        Err(_) => return Err(NetError::Timeout),
+                    };
                 *self = Ws::Server(WsServer::new(stream, addr));
-                Ok(())
+                Ok(header)
             }
-            _ => Ok(()),
+            _ => Ok(None),
         }
     }
 }
@@ -59,6 +75,11 @@ pub struct WsConn {
     id: u64,
     settings: Settings,
     count: OnlineCount,
+    /// 客户端连接的目标地址, 用于断线重连
+    url: Option<Url>,
+    disconnect: Disconnect,
+    /// 负载均衡传递的PROXY protocol头
+    proxy: Option<ProxyHeader>,
 }
 
 impl Default for WsConn {
@@ -68,6 +89,9 @@ impl Default for WsConn {
             id: 0,
             settings: Default::default(),
             count: Default::default(),
+            url: None,
+            disconnect: Default::default(),
+            proxy: None,
         }
     }
 }
@@ -91,6 +115,24 @@ impl WsConn {
         })
     }
 
+    /// 由单端口监听识别为websocket的连接
+    pub(crate) fn from_server(
+        server: WsServer,
+        id: u64,
+        count: OnlineCount,
+        proxy: Option<ProxyHeader>,
+        settings: Settings,
+    ) -> WsConn {
+        WsConn {
+            ws: Ws::Server(server),
+            id,
+            count,
+            proxy,
+            settings,
+            ..Default::default()
+        }
+    }
+
     pub async fn bind<A: ToSocketAddrs>(addr: A, settings: Settings) -> NetResult<WsConn> {
         let listener = TcpListener::bind(addr).await?;
         Self::new(listener, settings).await
@@ -110,11 +152,12 @@ impl WsConn {
         <Url as TryFrom<U>>::Error: Into<NetError>,
     {
         let url = Url::try_from(u).map_err(|e| e.into())?;
-        let client = WsClient::connect(url).await?;
+        let client = WsClient::connect_with_settings(url.clone(), &settings).await?;
         Ok(WsConn {
             ws: Ws::Client(client),
             id: IdCenter::next_connect_id(),
             settings,
+            url: Some(url),
             ..Default::default()
         })
     }
@@ -129,11 +172,12 @@ impl WsConn {
         <Url as TryFrom<U>>::Error: Into<NetError>,
     {
         let url = Url::try_from(u).map_err(|e| e.into())?;
-        let client = WsClient::new(stream, url).await?;
+        let client = WsClient::new_with_settings(stream, url.clone(), &settings).await?;
         Ok(WsConn {
             ws: Ws::Client(client),
             id: IdCenter::next_connect_id(),
             settings,
+            url: Some(url),
             ..Default::default()
         })
     }
@@ -147,6 +191,46 @@ impl WsConn {
         }
     }
 
+    /// 监听开启proxy_protocol时负载均衡传递的头, 在运行后解析
+    pub fn proxy_header(&self) -> Option<&ProxyHeader> {
+        self.proxy.as_ref()
+    }
+
+    /// 对端的证书链, 仅在wss连接且对端出示了证书时存在
+    pub fn peer_certificates(&self) -> Option<Vec<CertificateDer<'static>>> {
+        match &self.ws {
+            Ws::Client(ws_client) => ws_client.peer_certificates(),
+            Ws::Server(ws_server) => ws_server.peer_certificates(),
+            _ => None,
+        }
+    }
+
+    /// 监听的证书热更新句柄
+    pub fn tls_reloader(&self) -> Option<TlsReloader> {
+        match &self.ws {
+            Ws::Listener(listener) => Some(listener.accepter.clone()),
+            _ => None,
+        }
+    }
+
+    /// tls握手协商的信息, 仅在wss连接时存在
+    pub fn tls_info(&self) -> Option<TlsInfo> {
+        match &self.ws {
+            Ws::Client(ws_client) => ws_client.tls_info(),
+            Ws::Server(ws_server) => ws_server.tls_info(),
+            _ => None,
+        }
+    }
+
+    /// 握手协商的子协议, 服务端接受的连接需在握手完成后获取
+    pub fn protocol(&self) -> Option<String> {
+        match &self.ws {
+            Ws::Client(ws_client) => ws_client.protocol().cloned(),
+            Ws::Server(ws_server) => ws_server.protocol().cloned(),
+            _ => None,
+        }
+    }
+
     async fn process(&mut self) -> NetResult<WsMsgReceiver> {
         match &mut self.ws {
             Ws::Listener(listener) => {
@@ -160,12 +244,13 @@ impl WsConn {
                     return Ok(WsMsgReceiver::Next);
                 }
                 Ok(WsMsgReceiver::Accept(WsConn {
-                    ws: Ws::AcceptServer(TcpAcceptServer::new(
-                        MaybeAcceptStream::new(stream, accepter),
-                        addr,
-                    )),
+                    ws: Ws::AcceptServer(
+                        TcpAcceptServer::new(MaybeAcceptStream::new(stream, accepter), addr)
+                            .with_proxy_protocol(self.settings.proxy_protocol),
+                    ),
                     id,
                     count: self.count.add(),
+                    settings: self.settings.clone(),
                     ..Default::default()
                 }))
             }
@@ -179,6 +264,22 @@ impl WsConn {
         }
     }
 
+    pub(crate) fn can_reconnect(&self) -> bool {
+        self.url.is_some() && self.disconnect.should_reconnect()
+    }
+
+    /// 重新连接目标地址, 将重新进行握手
+    pub(crate) async fn reconnect(&mut self) -> NetResult<()> {
+        let url = unwrap_or!(
+            self.url.clone(),
+            return Err(NetError::Extension("no target to reconnect"))
+        );
+        let client = WsClient::connect_with_settings(url, &self.settings).await?;
+        self.ws = Ws::Client(client);
+        self.disconnect = Disconnect::default();
+        Ok(())
+    }
+
     fn close(&mut self, code: CloseCode, reason: String) -> NetResult<()> {
         match &mut self.ws {
             Ws::Listener(_) => {
@@ -195,7 +296,11 @@ impl WsConn {
         Ok(())
     }
 
+    /// 握手完成前不可发送websocket消息
     fn send_message(&mut self, msg: Message) -> NetResult<()> {
+        if !self.is_ready() {
+            return Err(WsError::BadStatus.into());
+        }
         match &mut self.ws {
             Ws::Client(ws_client) => ws_client.send_message(msg)?,
             Ws::Server(ws_server) => ws_server.send_message(msg)?,
@@ -223,32 +328,51 @@ impl WsConn {
     pub(crate) async fn inner_run_with_handler<H>(
         &mut self,
         handler: &mut H,
-        mut receiver: NetReceiver,
+        receiver: &mut NetReceiver,
     ) -> NetResult<()>
     where
         H: Handler + 'static + Sync + Send,
     {
-        self.ws.try_accept().await?;
+        let timeout = Duration::from_millis(self.settings.shake_timeout as u64);
+        if let Some(proxy) = self.ws.try_accept(timeout).await? {
+            self.proxy = Some(proxy);
+        }
+        if let Some(header) = &self.proxy {
+            handler.on_proxy_header(header.clone()).await?;
+        }
         let is_listen = self.is_listen();
         let mut call_ready = false;
+        let mut heartbeat = Heartbeat::new(&self.settings, true);
         loop {
             if !call_ready && self.is_ready() {
+                if let Some(info) = self.tls_info() {
+                    handler.on_tls_handshake(info).await?;
+                }
+                if let Some(certs) = self.peer_certificates() {
+                    handler.on_peer_certificates(certs).await?;
+                }
+                if let Some(protocol) = self.protocol() {
+                    handler.on_ws_protocol(protocol).await?;
+                }
                 handler.on_open().await?;
                 call_ready = true;
             }
+            let heartbeat_time = heartbeat.deadline();
             tokio::select! {
                 ret = self.process() => {
                     let r = ret?;
                     match r {
                         WsMsgReceiver::Accept(ws) => {
-                            handler.on_accept(NetConn::from((ws, self.settings.clone()))).await?
+                            handler.on_accept(NetConn::from(ws)).await?
                         },
                         WsMsgReceiver::Req(request) => {
+                            let extensions = request.headers().get_str_value(&"Sec-WebSocket-Extensions");
+                            let protocols = request.headers().get_str_value(&"Sec-WebSocket-Protocol");
                             let res = handler.on_request(request).await?;
                             let is_right = res.status() == 101;
                             match &mut self.ws {
                                 Ws::Server(ws_server) => {
-                                    ws_server.handler_response(res)?;
+                                    ws_server.handler_response(res, extensions, protocols, &self.settings)?;
                                 },
                                 _ => unreachable!(),
                             }
@@ -256,23 +380,38 @@ impl WsConn {
                                 self.close(CloseCode::Normal, "handshake failed!".to_string())?;
                             }
                         },
+                        WsMsgReceiver::Http(request) => {
+                            let keep_alive = WsServer::is_keep_alive(&request);
+                            let res = handler.on_http(request).await?;
+                            let keep_alive = match &mut self.ws {
+                                Ws::Server(ws_server) => ws_server.handler_http(res, keep_alive)?,
+                                _ => unreachable!(),
+                            };
+                            if !keep_alive {
+                                self.close(CloseCode::Normal, "http finished".to_string())?;
+                            }
+                        },
                         WsMsgReceiver::Res(response) => {
-                            if response.status() != 101 {
-                                self.close(CloseCode::Normal, "handshake failed!".to_string())?;
-                            } else {
+                            let status = response.status().as_u16();
+                            if status == 101 {
                                 match &mut self.ws {
                                     Ws::Client(ws_client) => {
-                                        ws_client.handler_response(response)?;
+                                        ws_client.handler_response(&response, &self.settings)?;
                                     },
                                     _ => todo!(),
                                 }
                             }
+                            handler.on_response(response).await?;
+                            if status != 101 {
+                                return Err(WsError::FailStatus(status).into());
+                            }
                         },
                         WsMsgReceiver::Msg(msg) => {
                             match msg {
                                 Message::Text(_) | Message::Binary(_) => handler.on_message(msg).await?,
                                 Message::Close(code, reason) => {
-                                    handler.on_close(code.into(), reason).await;
+                                    self.disconnect = Disconnect::Remote(code);
+                                    handler.on_close(code, reason).await;
                                     return Ok(())
                                 }
                                 Message::Ping(data) => {
@@ -280,24 +419,35 @@ impl WsConn {
                                         self.send_message(Message::Pong(ret))?;
                                     }
                                 },
-                                Message::Pong(data) => handler.on_pong(data).await?,
+                                Message::Pong(data) => {
+                                    if let Some(rtt) = heartbeat.on_pong(&data) {
+                                        handler.on_heartbeat(rtt).await?;
+                                    }
+                                    handler.on_pong(data).await?
+                                },
                                 _ => return Ok(()),
                             }
                         },
                         WsMsgReceiver::Next => continue,
                     }
                 }
-                c = receiver.recv(), if !self.is_outbuffer_full() => {
+                // 握手完成前消息保留在队列中, 不作为帧写入
+                c = receiver.recv(), if !self.is_outbuffer_full() && (self.is_ready() || is_listen) => {
                     if is_listen {
                         return Ok(());
                     }
-                    let c = unwrap_or!(c, return Ok(()));
+                    let c = unwrap_or!(c, {
+                        self.disconnect = Disconnect::Local;
+                        return Ok(())
+                    });
                     match c.msg {
                         Message::Close(code, reason) => {
-                            self.close(code.into(), reason)?;
+                            self.disconnect = Disconnect::Local;
+                            self.close(code, reason)?;
                             continue;
                         }
                         Message::Shutdown => {
+                            self.disconnect = Disconnect::Local;
                             self.close(CloseCode::Normal, "Shutdown".to_string())?;
                             continue;
                         }
@@ -305,6 +455,17 @@ impl WsConn {
                     }
                     self.send_message(c.msg)?;
                 }
+                _ = time::sleep_until(heartbeat_time), if heartbeat.is_enable() && self.is_ready() => {
+                    match heartbeat.on_tick() {
+                        Some(data) => self.send_message(Message::Ping(data))?,
+                        None => {
+                            let reason = "heartbeat timeout".to_string();
+                            self.disconnect = Disconnect::Heartbeat;
+                            handler.on_close(CloseCode::Heartbeat, reason.clone()).await;
+                            self.close(CloseCode::Heartbeat, reason)?;
+                        }
+                    }
+                }
                 r = handler.on_logic() => {
                     let _ = r?;
                 }
@@ -312,24 +473,6 @@ impl WsConn {
         }
     }
 
-    pub(crate) async fn inner_run_handler<F, H>(&mut self, factory: F) -> NetResult<()>
-    where
-        F: FnOnce(NetSender) -> H + Send + 'static,
-        H: Handler + 'static + Sync + Send,
-    {
-        let (sender, receiver) = NetSender::new(self.settings.queue_size, self.id);
-        let _avoid = sender.clone();
-        let mut handler = factory(sender);
-        self.ws.try_accept().await?;
-        if let Err(e) = self.inner_run_with_handler(&mut handler, receiver).await {
-            handler
-                .on_close(CloseCode::Error, "NetError".to_string())
-                .await;
-            return Err(e);
-        }
-        Ok(())
-    }
-
     pub fn is_ready(&self) -> bool {
         match &self.ws {
             Ws::Client(ws_client) => ws_client.is_ready(),
@@ -350,3 +493,293 @@ impl WsConn {
         self.id
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use async_trait::async_trait;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::{TcpListener, TcpStream},
+    };
+    use webparse::{Request, Response};
+
+    use super::{WsConn, WsError, WsHandshake, WsMsgReceiver};
+    use crate::{CloseCode, Handler, Message, NetConn, NetError, NetResult, NetSender, Settings};
+
+    /// 仅实现on_request的旧式处理, 普通http请求也应交由其处理
+    struct Health;
+
+    #[async_trait]
+    impl Handler for Health {
+        async fn on_request(&mut self, req: Request<Vec<u8>>) -> NetResult<Response<Vec<u8>>> {
+            assert_eq!(req.path(), "/health");
+            Ok(Response::builder()
+                .status(200)
+                .body(b"ok".to_vec())
+                .unwrap())
+        }
+    }
+
+    async fn accept(settings: Settings) -> (WsConn, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let mut listener = match NetConn::ws_bind_with_listener(listener, settings)
+            .await
+            .unwrap()
+        {
+            NetConn::Ws(ws) => ws,
+            _ => unreachable!(),
+        };
+        let client = TcpStream::connect(addr).await.unwrap();
+        loop {
+            if let WsMsgReceiver::Accept(conn) = listener.process().await.unwrap() {
+                return (conn, client);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn http_keep_alive_idle_close() {
+        let settings = Settings {
+            shake_timeout: 300,
+            ..Settings::default()
+        };
+        let (mut conn, mut client) = accept(settings).await;
+        let (mut sender, mut receiver) = NetSender::new(10, 0);
+        // 握手前的消息不能作为websocket帧写入http连接
+        sender
+            .send_message(Message::Text("early".to_string()))
+            .unwrap();
+        let server = tokio::spawn(async move {
+            conn.inner_run_with_handler(&mut Health, &mut receiver)
+                .await
+        });
+
+        client
+            .write_all(b"GET /health HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .await
+            .unwrap();
+        let mut data = vec![];
+        let mut buf = [0u8; 1024];
+        while !data.ends_with(b"\r\n\r\nok") {
+            let n = client.read(&mut buf).await.unwrap();
+            assert!(n > 0);
+            data.extend_from_slice(&buf[..n]);
+        }
+        assert!(data.starts_with(b"HTTP/1.1 200"));
+
+        // 空闲超时后服务端正常关闭连接, 期间没有其它数据写出
+        assert_eq!(client.read(&mut buf).await.unwrap(), 0);
+        let ret = tokio::time::timeout(Duration::from_secs(5), server)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(ret.is_ok(), "{ret:?}");
+        drop(sender);
+    }
+
+    /// 服务端记录收到的升级请求, 可指定返回的子协议或拒绝升级
+    #[derive(Default)]
+    struct Server {
+        request: Option<Request<Vec<u8>>>,
+        protocol: Option<&'static str>,
+        reject: bool,
+    }
+
+    #[async_trait]
+    impl Handler for Server {
+        async fn on_request(&mut self, req: Request<Vec<u8>>) -> NetResult<Response<Vec<u8>>> {
+            let mut res = WsHandshake::build_request(&req)?;
+            if self.reject {
+                res = Response::builder()
+                    .status(403)
+                    .header("Set-Cookie", "session=expired")
+                    .body(vec![])
+                    .unwrap();
+            }
+            if let Some(protocol) = self.protocol {
+                res.headers_mut().insert("Sec-WebSocket-Protocol", protocol);
+            }
+            self.request = Some(req);
+            Ok(res)
+        }
+    }
+
+    /// 客户端记录收到的升级结果, 握手完成后关闭连接
+    struct Client {
+        response: Option<Response<Vec<u8>>>,
+        sender: NetSender,
+    }
+
+    #[async_trait]
+    impl Handler for Client {
+        async fn on_response(&mut self, res: Response<Vec<u8>>) -> NetResult<()> {
+            self.response = Some(res);
+            self.sender
+                .close_with_reason(CloseCode::Normal, "done".to_string())
+        }
+    }
+
+    /// 完成一次握手并关闭连接, 返回两端协商的子协议, 处理器及客户端的运行结果
+    async fn handshake(
+        mut server: Server,
+        server_settings: Settings,
+        client_settings: Settings,
+    ) -> (
+        Option<String>,
+        Server,
+        Option<String>,
+        Client,
+        NetResult<()>,
+    ) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let mut listener = WsConn::new(listener, server_settings).await.unwrap();
+        let url = format!("ws://{addr}/chat");
+        let mut conn = WsConn::connect_with_settings(url, client_settings)
+            .await
+            .unwrap();
+        let (sender, mut receiver) = NetSender::new(10, 0);
+        let mut client = Client {
+            response: None,
+            sender,
+        };
+        let run_server = async {
+            let mut accepted = loop {
+                if let WsMsgReceiver::Accept(conn) = listener.process().await.unwrap() {
+                    break conn;
+                }
+            };
+            let (_sender, mut receiver) = NetSender::new(10, 0);
+            let _ = accepted
+                .inner_run_with_handler(&mut server, &mut receiver)
+                .await;
+            accepted.protocol()
+        };
+        // 运行结束后释放连接, 握手失败时服务端随即读到连接关闭
+        let run_client = async move {
+            let ret = conn
+                .inner_run_with_handler(&mut client, &mut receiver)
+                .await;
+            (conn.protocol(), client, ret)
+        };
+        let (accepted, (protocol, client, ret)) =
+            tokio::time::timeout(Duration::from_secs(5), async {
+                tokio::join!(run_server, run_client)
+            })
+            .await
+            .unwrap();
+        (accepted, server, protocol, client, ret)
+    }
+
+    #[tokio::test]
+    async fn protocol_server_preference() {
+        let server_settings = Settings {
+            ws_protocols: vec!["chat".to_string(), "superchat".to_string()],
+            ..Settings::default()
+        };
+        let client_settings = Settings {
+            ws_protocols: vec!["superchat".to_string(), "chat".to_string()],
+            ..Settings::default()
+        };
+        let (accepted, _, protocol, client, ret) =
+            handshake(Server::default(), server_settings, client_settings).await;
+        ret.unwrap();
+        let res = client.response.unwrap();
+        assert_eq!(
+            res.headers().get_str_value(&"Sec-WebSocket-Protocol"),
+            Some("chat".to_string())
+        );
+        assert_eq!(protocol.as_deref(), Some("chat"));
+        assert_eq!(accepted.as_deref(), Some("chat"));
+    }
+
+    #[tokio::test]
+    async fn protocol_no_overlap() {
+        let server_settings = Settings {
+            ws_protocols: vec!["chat".to_string()],
+            ..Settings::default()
+        };
+        let client_settings = Settings {
+            ws_protocols: vec!["mqtt".to_string()],
+            ..Settings::default()
+        };
+        let (accepted, _, protocol, client, ret) =
+            handshake(Server::default(), server_settings, client_settings).await;
+        ret.unwrap();
+        let res = client.response.unwrap();
+        assert_eq!(res.status().as_u16(), 101);
+        assert!(res
+            .headers()
+            .get_str_value(&"Sec-WebSocket-Protocol")
+            .is_none());
+        assert_eq!(protocol, None);
+        assert_eq!(accepted, None);
+    }
+
+    #[tokio::test]
+    async fn protocol_unoffered_rejected() {
+        let server = Server {
+            protocol: Some("evil"),
+            ..Server::default()
+        };
+        let client_settings = Settings {
+            ws_protocols: vec!["chat".to_string()],
+            ..Settings::default()
+        };
+        let (_, _, protocol, client, ret) =
+            handshake(server, Settings::default(), client_settings).await;
+        assert!(ret.is_err());
+        assert!(client.response.is_none());
+        assert_eq!(protocol, None);
+    }
+
+    #[tokio::test]
+    async fn custom_headers_and_response() {
+        let client_settings = Settings {
+            ws_headers: vec![
+                ("Authorization".to_string(), "Bearer token".to_string()),
+                ("Cookie".to_string(), "session=1".to_string()),
+            ],
+            ..Settings::default()
+        };
+        let (_, server, _, client, ret) =
+            handshake(Server::default(), Settings::default(), client_settings).await;
+        ret.unwrap();
+        let req = server.request.unwrap();
+        assert_eq!(
+            req.headers().get_str_value(&"Authorization"),
+            Some("Bearer token".to_string())
+        );
+        assert_eq!(
+            req.headers().get_str_value(&"Cookie"),
+            Some("session=1".to_string())
+        );
+        let res = client.response.unwrap();
+        assert_eq!(res.status().as_u16(), 101);
+        assert!(res
+            .headers()
+            .get_str_value(&"Sec-WebSocket-Accept")
+            .is_some());
+    }
+
+    #[tokio::test]
+    async fn rejected_response() {
+        let server = Server {
+            reject: true,
+            ..Server::default()
+        };
+        let (_, _, _, client, ret) =
+            handshake(server, Settings::default(), Settings::default()).await;
+        // 非101的响应同样交给on_response, 之后以FailStatus结束
+        assert!(matches!(ret, Err(NetError::Ws(WsError::FailStatus(403)))));
+        let res = client.response.unwrap();
+        assert_eq!(res.status().as_u16(), 403);
+        assert_eq!(
+            res.headers().get_str_value(&"Set-Cookie"),
+            Some("session=expired".to_string())
+        );
+    }
+}
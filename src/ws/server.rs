@@ -2,19 +2,20 @@ use std::{net::SocketAddr, time::Duration};
 
 use crate::{
     Message, NetError, NetResult,
-    {protocol::CloseCode, MaybeTlsStream, Settings},
+    {protocol::CloseCode, MaybeTlsStream, Settings, TlsInfo},
 };
 use algorithm::buf::{BinaryMut, Bt, BtMut};
 use tokio::{
     io::{split, AsyncReadExt, AsyncWriteExt, ReadBuf},
     time::{self, Instant},
 };
+use tokio_rustls::rustls::pki_types::CertificateDer;
 use webparse::{
     ws::{DataFrame, DataFrameable, OwnedMessage},
-    HttpError, Request, Response, Serialize, WebError,
+    HeaderMap, HttpError, Request, Response, Serialize, WebError,
 };
 
-use super::{WsError, WsMsgReceiver, WsState};
+use super::{WsDeflate, WsError, WsHandshake, WsMsgReceiver, WsState};
 
 /// websocket的服务端
 pub struct WsServer {
@@ -28,23 +29,73 @@ pub struct WsServer {
     read: BinaryMut,
     /// 写缓存
     write: BinaryMut,
+    /// 协商成功后的permessage-deflate压缩状态
+    deflate: Option<WsDeflate>,
+    /// 协商成功的子协议
+    protocol: Option<String>,
+    /// 是否已处理过普通http请求, 之后保持连接的空闲超时视为正常关闭
+    http_served: bool,
 }
 
 impl WsServer {
     pub fn new(stream: MaybeTlsStream, addr: SocketAddr) -> WsServer {
+        Self::new_with_read(stream, addr, BinaryMut::new())
+    }
+
+    /// 以已读取的数据作为读缓存, 用于识别协议时已读取了部分请求
+    pub(crate) fn new_with_read(
+        stream: MaybeTlsStream,
+        addr: SocketAddr,
+        read: BinaryMut,
+    ) -> WsServer {
         WsServer {
             stream,
             addr,
             state: WsState::Wait,
-            read: BinaryMut::new(),
+            read,
             write: BinaryMut::new(),
+            deflate: None,
+            protocol: None,
+            http_served: false,
         }
     }
 
     /// 构建返回结果, 如果非101的状态码后续将直接关闭状态
-    pub(crate) fn handler_response(&mut self, mut res: Response<Vec<u8>>) -> NetResult<()> {
+    /// extensions为请求中的扩展头, 配置了压缩且返回结果未自行处理扩展时协商压缩
+    /// protocols为请求中的子协议头, 返回结果未自行选择子协议时按配置的ws_protocols选取
+    pub(crate) fn handler_response(
+        &mut self,
+        mut res: Response<Vec<u8>>,
+        extensions: Option<String>,
+        protocols: Option<String>,
+        settings: &Settings,
+    ) -> NetResult<()> {
         match &self.state {
             WsState::WaitRet => {
+                if res.status() == 101 {
+                    self.protocol = res.headers().get_str_value(&"Sec-WebSocket-Protocol");
+                    if self.protocol.is_none() {
+                        self.protocol = protocols
+                            .and_then(|p| WsHandshake::select_protocol(&p, &settings.ws_protocols));
+                        if let Some(protocol) = &self.protocol {
+                            res.headers_mut()
+                                .insert("Sec-WebSocket-Protocol", protocol.clone());
+                        }
+                    }
+                }
+                if let (Some(ext), Some(deflate)) = (extensions, &settings.deflate) {
+                    if res.status() == 101
+                        && res
+                            .headers()
+                            .get_str_value(&"Sec-WebSocket-Extensions")
+                            .is_none()
+                    {
+                        if let Some((deflate, value)) = WsDeflate::server_negotiate(&ext, deflate) {
+                            res.headers_mut().insert("Sec-WebSocket-Extensions", value);
+                            self.deflate = Some(deflate);
+                        }
+                    }
+                }
                 res.serialize(&mut self.write)?;
                 self.state = WsState::Open;
             }
@@ -54,6 +105,15 @@ impl WsServer {
     }
 
     pub(crate) fn send_message(&mut self, msg: Message) -> NetResult<()> {
+        if let Some(deflate) = &mut self.deflate {
+            if deflate.write_message(&mut self.write, &msg, None)? {
+                return Ok(());
+            }
+        }
+        if let Message::Prepared(p) = &msg {
+            self.write.put_slice(p.ws_frame()?);
+            return Ok(());
+        }
         let msg: OwnedMessage = msg.into();
         msg.write_to(&mut self.write, None)?;
         Ok(())
@@ -105,6 +165,108 @@ impl WsServer {
         }
     }
 
+    /// 是否为websocket的升级请求
+    fn is_upgrade(headers: &HeaderMap) -> bool {
+        headers
+            .get_str_value(&"Upgrade")
+            .is_some_and(|v| v.to_ascii_lowercase().contains("websocket"))
+    }
+
+    /// 请求是否要求保持连接
+    pub(crate) fn is_keep_alive(request: &Request<Vec<u8>>) -> bool {
+        !request
+            .headers()
+            .get_str_value(&"Connection")
+            .is_some_and(|v| v.to_ascii_lowercase().contains("close"))
+    }
+
+    /// 解析读缓存中完整的请求, 数据不足时返回None
+    /// 升级请求进入握手, 其余请求读取完Content-Length的body后作为普通http请求
+    fn parse_request(&mut self, settings: &Settings) -> NetResult<Option<WsMsgReceiver>> {
+        if !self.read.has_remaining() {
+            return Ok(None);
+        }
+        self.read.mark();
+        let mut request = Request::new();
+        let s = match request.parse_buffer(&mut self.read.chunk()) {
+            Ok(s) => s,
+            Err(WebError::Http(HttpError::Partial)) => {
+                self.read.rewind_mark();
+                return Ok(None);
+            }
+            Err(e) => {
+                return Err(e.into());
+            }
+        };
+        if request.is_partial() {
+            self.read.rewind_mark();
+            return Ok(None);
+        }
+        if Self::is_upgrade(request.headers()) {
+            self.read.advance(s);
+            self.state = WsState::WaitRet;
+            return Ok(Some(WsMsgReceiver::Req(request.into(vec![]).0)));
+        }
+
+        if request
+            .headers()
+            .get_str_value(&"Transfer-Encoding")
+            .is_some()
+        {
+            return Err(WsError::ProtocolError("chunked http body not supported").into());
+        }
+        let len = match request.headers().get_str_value(&"Content-Length") {
+            Some(len) => len
+                .trim()
+                .parse::<usize>()
+                .map_err(|_| WsError::ProtocolError("invalid Content-Length"))?,
+            None => 0,
+        };
+        if len > settings.in_buffer_max {
+            return Err(NetError::OverInbufferSize);
+        }
+        if self.read.remaining() < s + len {
+            self.read.rewind_mark();
+            return Ok(None);
+        }
+        self.read.advance(s);
+        let body = self.read.chunk()[..len].to_vec();
+        self.read.advance(len);
+        Ok(Some(WsMsgReceiver::Http(request.into(body).0)))
+    }
+
+    /// 写入普通http请求的返回结果, 返回是否保持连接
+    /// 请求或返回结果带有`Connection: close`时不保持连接
+    pub(crate) fn handler_http(
+        &mut self,
+        mut res: Response<Vec<u8>>,
+        keep_alive: bool,
+    ) -> NetResult<bool> {
+        match &self.state {
+            WsState::Wait => {
+                let keep_alive = keep_alive
+                    && !res
+                        .headers()
+                        .get_str_value(&"Connection")
+                        .is_some_and(|v| v.to_ascii_lowercase().contains("close"));
+                let headers = res.headers();
+                if headers.get_str_value(&"Content-Length").is_none()
+                    && headers.get_str_value(&"Transfer-Encoding").is_none()
+                {
+                    let len = res.body().len();
+                    res.headers_mut().insert("Content-Length", len.to_string());
+                }
+                if !keep_alive {
+                    res.headers_mut().insert("Connection", "close");
+                }
+                res.serialize(&mut self.write)?;
+                self.http_served = true;
+                Ok(keep_alive)
+            }
+            _ => Err(WsError::BadStatus.into()),
+        }
+    }
+
     pub(crate) async fn process(&mut self, settings: &Settings) -> NetResult<WsMsgReceiver> {
         let mut vec = vec![];
         loop {
@@ -112,34 +274,31 @@ impl WsServer {
                 WsState::Wait => {
                     let util_time =
                         Instant::now() + Duration::from_millis(settings.shake_timeout as u64);
-                    let mut request = Request::new();
                     loop {
+                        // 保持连接时读缓存中可能已有下一个请求
+                        if let Some(r) = self.parse_request(settings)? {
+                            return Ok(r);
+                        }
+                        // 保持连接且无未读完的请求时, 空闲超时为正常关闭
+                        let idle = self.http_served && !self.read.has_remaining();
                         tokio::select! {
                             is_end = self.process_io(false, settings) => {
-                                if is_end? {
-                                    return Ok(WsMsgReceiver::Msg(Message::Shutdown));
+                                match is_end {
+                                    Ok(true) => return Ok(WsMsgReceiver::Msg(Message::Shutdown)),
+                                    Ok(false) => {}
+                                    Err(NetError::ReadTimeout) if idle => {
+                                        return Ok(WsMsgReceiver::Msg(Message::Shutdown));
+                                    }
+                                    Err(e) => return Err(e),
                                 }
                             }
                             _ = time::sleep_until(util_time) => {
+                                if idle {
+                                    return Ok(WsMsgReceiver::Msg(Message::Shutdown));
+                                }
                                 return Err(NetError::Timeout.into());
                             }
                         }
-                        self.read.mark();
-                        let s = match request.parse_buffer(&mut self.read.chunk()) {
-                            Ok(s) => s,
-                            Err(WebError::Http(HttpError::Partial)) => {
-                                self.read.rewind_mark();
-                                continue;
-                            }
-                            Err(e) => {
-                                return Err(e.into());
-                            }
-                        };
-                        if !request.is_partial() {
-                            self.read.advance(s);
-                            self.state = WsState::WaitRet;
-                            return Ok(WsMsgReceiver::Req(request.into(vec![]).0));
-                        }
                     }
                 }
                 WsState::WaitRet => {
@@ -165,7 +324,12 @@ impl WsServer {
                         let is_finish = frame.is_last();
                         vec.push(frame);
                         if is_finish {
-                            let msg = Message::from(OwnedMessage::from_dataframes(vec)?);
+                            let msg = match &mut self.deflate {
+                                Some(deflate) if vec[0].reserved()[0] => {
+                                    deflate.read_message(vec, settings.in_buffer_max)?
+                                }
+                                _ => Message::from(OwnedMessage::from_dataframes(vec)?),
+                            };
                             return Ok(WsMsgReceiver::Msg(msg));
                         }
                     }
@@ -193,6 +357,19 @@ impl WsServer {
         Some(self.addr)
     }
 
+    pub fn peer_certificates(&self) -> Option<Vec<CertificateDer<'static>>> {
+        self.stream.peer_certificates()
+    }
+
+    pub fn tls_info(&self) -> Option<TlsInfo> {
+        self.stream.tls_info()
+    }
+
+    /// 握手协商的子协议
+    pub fn protocol(&self) -> Option<&String> {
+        self.protocol.as_ref()
+    }
+
     fn closing_to_closed(&mut self) {
         match &self.state {
             WsState::Closing(v) => self.state = WsState::Closed(v.clone()),